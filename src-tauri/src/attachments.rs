@@ -0,0 +1,427 @@
+// attachments.rs - Content-addressed files attached to wins, with thumbnails
+
+use crate::encryption;
+use crate::errors;
+use image::ImageFormat;
+use rusqlite::{ffi, Connection, OptionalExtension, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+const ATTACHMENT_DIR: &str = "attachments";
+const THUMB_DIR: &str = "thumbs";
+/// Longest edge of a generated thumbnail, in pixels
+const THUMB_SIZE: u32 = 256;
+pub const MAX_ATTACHMENT_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Serialize, Clone)]
+pub struct Attachment {
+    pub id: i64,
+    pub win_id: i64,
+    /// Name of the file as it was attached
+    pub file_name: String,
+    pub mime: String,
+    pub size_bytes: i64,
+    pub created_at: i64,
+    /// SHA-256 of the contents, which is also the stored file's name
+    pub hash: String,
+    pub has_thumbnail: bool,
+}
+
+/// Attachments sit next to the database file, like backups, and are stored
+/// as plain files. So they can't leak past the privacy lock, they are only
+/// accepted while the database is unencrypted and the lock can't be turned
+/// on while any exist.
+fn store_dir(conn: &Connection) -> Result<PathBuf> {
+    let db_path = conn.path().filter(|p| !p.is_empty()).ok_or_else(|| {
+        errors::failure(
+            ffi::SQLITE_CANTOPEN,
+            "No attachment folder for an in-memory database.",
+        )
+    })?;
+    Ok(store_dir_for(Path::new(db_path)))
+}
+
+pub(crate) fn store_dir_for(db_path: &Path) -> PathBuf {
+    db_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(ATTACHMENT_DIR)
+}
+
+/// Attachments on any win, trashed ones included.
+pub(crate) fn count(conn: &Connection) -> Result<i64> {
+    conn.query_row("SELECT COUNT(*) FROM win_attachments", [], |row| row.get(0))
+}
+
+/// `<hash>.<ext>`, keeping the original extension so the file opens in the
+/// right app.
+fn object_name(hash: &str, file_name: &str) -> String {
+    match extension(file_name) {
+        Some(ext) => format!("{}.{}", hash, ext),
+        None => hash.to_string(),
+    }
+}
+
+fn extension(file_name: &str) -> Option<String> {
+    Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| !e.is_empty() && e.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(|e| e.to_ascii_lowercase())
+}
+
+fn mime_for(file_name: &str) -> &'static str {
+    match extension(file_name).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("bmp") => "image/bmp",
+        Some("pdf") => "application/pdf",
+        Some("txt" | "md") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+pub(crate) fn object_path(conn: &Connection, attachment: &Attachment) -> Result<PathBuf> {
+    Ok(store_dir(conn)?.join(object_name(&attachment.hash, &attachment.file_name)))
+}
+
+fn thumb_path(dir: &Path, hash: &str) -> PathBuf {
+    dir.join(THUMB_DIR).join(format!("{}.png", hash))
+}
+
+/// Copies `source` into the store (once per distinct content) and attaches
+/// it to a win in the log.
+pub fn add_attachment(conn: &Connection, win_id: i64, source: &Path) -> Result<Attachment> {
+    let io = |e: std::io::Error| errors::failure(ffi::SQLITE_IOERR, &e.to_string());
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM wins WHERE id = ?1)",
+        [win_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(errors::failure(ffi::SQLITE_NOTFOUND, "No such win."));
+    }
+    if conn
+        .path()
+        .is_some_and(|p| encryption::is_encrypted(Path::new(p)))
+    {
+        return Err(errors::failure(
+            ffi::SQLITE_AUTH,
+            "Attachments are stored unencrypted, so they can't be added while the Privacy Lock is on.",
+        ));
+    }
+    let size = fs::metadata(source).map_err(io)?.len();
+    if size > MAX_ATTACHMENT_BYTES {
+        return Err(errors::failure(
+            ffi::SQLITE_TOOBIG,
+            &format!(
+                "Attachments can be at most {} MB.",
+                MAX_ATTACHMENT_BYTES / (1024 * 1024)
+            ),
+        ));
+    }
+    let bytes = fs::read(source).map_err(io)?;
+    let hash: String = Sha256::digest(&bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let file_name = source
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| hash.clone());
+    let dir = store_dir(conn)?;
+    fs::create_dir_all(&dir).map_err(io)?;
+    let dest = dir.join(object_name(&hash, &file_name));
+    if !dest.exists() {
+        // Written under a temporary name so a crash never leaves a truncated object
+        let staged = encryption::sibling(&dest, ".partial");
+        fs::write(&staged, &bytes)
+            .and_then(|_| fs::rename(&staged, &dest))
+            .map_err(io)?;
+    }
+    let mime = mime_for(&file_name);
+    if mime.starts_with("image/") {
+        write_thumbnail(&dir, &hash, &bytes);
+    }
+    println!(
+        "[add_attachment] {} ({} bytes) on win {}",
+        file_name, size, win_id
+    );
+    conn.execute(
+        "INSERT INTO win_attachments (win_id, hash, file_name, mime, size_bytes, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (
+            win_id,
+            &hash,
+            &file_name,
+            mime,
+            size as i64,
+            OffsetDateTime::now_utc().unix_timestamp(),
+        ),
+    )?;
+    get_attachment(conn, conn.last_insert_rowid())?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+/// Best effort: an image the `image` crate can't decode just has no thumbnail.
+fn write_thumbnail(dir: &Path, hash: &str, bytes: &[u8]) {
+    let path = thumb_path(dir, hash);
+    if path.exists() {
+        return;
+    }
+    let written = image::load_from_memory(bytes)
+        .map_err(|e| e.to_string())
+        .and_then(|img| {
+            fs::create_dir_all(dir.join(THUMB_DIR)).map_err(|e| e.to_string())?;
+            img.thumbnail(THUMB_SIZE, THUMB_SIZE)
+                .save_with_format(&path, ImageFormat::Png)
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = written {
+        println!("[add_attachment] No thumbnail for {}: {}", hash, e);
+        let _ = fs::remove_file(&path);
+    }
+}
+
+const ATTACHMENT_SELECT: &str =
+    "SELECT id, win_id, hash, file_name, mime, size_bytes, created_at FROM win_attachments";
+
+fn attachment_from_row(row: &rusqlite::Row) -> Result<Attachment> {
+    Ok(Attachment {
+        id: row.get(0)?,
+        win_id: row.get(1)?,
+        hash: row.get(2)?,
+        file_name: row.get(3)?,
+        mime: row.get(4)?,
+        size_bytes: row.get(5)?,
+        created_at: row.get(6)?,
+        has_thumbnail: false,
+    })
+}
+
+fn with_thumbnail(conn: &Connection, mut attachment: Attachment) -> Attachment {
+    attachment.has_thumbnail = store_dir(conn)
+        .map(|dir| thumb_path(&dir, &attachment.hash).exists())
+        .unwrap_or(false);
+    attachment
+}
+
+pub fn get_attachment(conn: &Connection, id: i64) -> Result<Option<Attachment>> {
+    conn.query_row(
+        &format!("{} WHERE id = ?1", ATTACHMENT_SELECT),
+        [id],
+        attachment_from_row,
+    )
+    .optional()
+    .map(|a| a.map(|a| with_thumbnail(conn, a)))
+}
+
+/// Oldest first.
+pub fn list_attachments(conn: &Connection, win_id: i64) -> Result<Vec<Attachment>> {
+    let mut stmt = conn.prepare(&format!(
+        "{} WHERE win_id = ?1 ORDER BY id",
+        ATTACHMENT_SELECT
+    ))?;
+    let rows = stmt.query_map([win_id], attachment_from_row)?;
+    rows.map(|a| a.map(|a| with_thumbnail(conn, a))).collect()
+}
+
+/// PNG bytes of the thumbnail, if the attachment is an image.
+pub fn get_thumbnail(conn: &Connection, id: i64) -> Result<Option<Vec<u8>>> {
+    let Some(attachment) = get_attachment(conn, id)? else {
+        return Ok(None);
+    };
+    Ok(fs::read(thumb_path(&store_dir(conn)?, &attachment.hash)).ok())
+}
+
+/// The stored file for `id`, for handing to the system opener.
+pub fn attachment_file(conn: &Connection, id: i64) -> Result<PathBuf> {
+    let attachment = get_attachment(conn, id)?
+        .ok_or_else(|| errors::failure(ffi::SQLITE_NOTFOUND, "No such attachment."))?;
+    let path = object_path(conn, &attachment)?;
+    if !path.exists() {
+        return Err(errors::failure(
+            ffi::SQLITE_NOTFOUND,
+            &format!("The file for {} is missing.", attachment.file_name),
+        ));
+    }
+    Ok(path)
+}
+
+pub fn remove_attachment(conn: &Connection, id: i64) -> Result<()> {
+    println!("[remove_attachment] Removing attachment {}", id);
+    if conn.execute("DELETE FROM win_attachments WHERE id = ?1", [id])? == 0 {
+        return Err(errors::failure(ffi::SQLITE_NOTFOUND, "No such attachment."));
+    }
+    collect_garbage(conn);
+    Ok(())
+}
+
+/// Deletes stored files and thumbnails that no attachment row refers to any
+/// more. Run after rows are removed and committed.
+pub(crate) fn collect_garbage(conn: &Connection) {
+    let Ok(dir) = store_dir(conn) else {
+        return;
+    };
+    let referenced = conn
+        .prepare("SELECT DISTINCT hash, file_name FROM win_attachments")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>>>()
+        });
+    let Ok(referenced) = referenced else {
+        return;
+    };
+    let hashes: HashSet<&str> = referenced.iter().map(|(h, _)| h.as_str()).collect();
+    let objects: HashSet<String> = referenced
+        .iter()
+        .map(|(h, name)| object_name(h, name))
+        .collect();
+    let unreferenced = |dir: &Path, keep: &dyn Fn(&str) -> bool| {
+        fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|e| e.path().is_file())
+                    .filter(|e| !keep(&e.file_name().to_string_lossy()))
+                    .map(|e| e.path())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+    let mut removed = unreferenced(&dir, &|name| objects.contains(name));
+    removed.extend(unreferenced(&dir.join(THUMB_DIR), &|name| {
+        name.strip_suffix(".png")
+            .is_some_and(|h| hashes.contains(h))
+    }));
+    for path in &removed {
+        let _ = fs::remove_file(path);
+    }
+    if !removed.is_empty() {
+        println!("[attachments] Removed {} unused files", removed.len());
+    }
+}
+
+/// Mirrors the store (objects and thumbnails) into `dest`. Objects never
+/// change once written, so they are hard-linked where the filesystem allows
+/// and copied otherwise.
+pub(crate) fn copy_store(from: &Path, dest: &Path) -> std::io::Result<()> {
+    let Ok(entries) = fs::read_dir(from) else {
+        // Nothing has been attached yet
+        return Ok(());
+    };
+    fs::create_dir_all(dest)?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let target = dest.join(entry.file_name());
+        if path.is_dir() {
+            copy_store(&path, &target)?;
+        } else if path.is_file()
+            && !target.exists()
+            && !entry.file_name().to_string_lossy().ends_with(".partial")
+        {
+            fs::hard_link(&path, &target).or_else(|_| fs::copy(&path, &target).map(|_| ()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, migrations};
+
+    /// A journal file in its own temporary folder, with one win.
+    fn journal(name: &str, key: Option<&str>) -> (PathBuf, Connection) {
+        let dir = std::env::temp_dir().join(format!(
+            "quietwins-attachments-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut conn = encryption::open(&dir.join("wins.db"), key).unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        db::add_win(
+            &mut conn,
+            "2025-01-02",
+            "Framed it",
+            "",
+            db::Ratings::default(),
+        )
+        .unwrap();
+        (dir, conn)
+    }
+
+    fn win_id(conn: &Connection) -> i64 {
+        conn.query_row("SELECT MAX(id) FROM wins", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn locked_journals_refuse_attachments() {
+        let (dir, conn) = journal("locked", Some("correct horse"));
+        let source = dir.join("certificate.txt");
+        fs::write(&source, "Well done").unwrap();
+        let Err(err) = add_attachment(&conn, win_id(&conn), &source) else {
+            panic!("attached to a locked journal");
+        };
+        assert!(err.to_string().contains("Privacy Lock"), "{}", err);
+        assert_eq!(count(&conn).unwrap(), 0);
+        assert!(!dir.join(ATTACHMENT_DIR).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_copied_store_brings_files_back() {
+        let (dir, conn) = journal("copied", None);
+        let source = dir.join("certificate.txt");
+        fs::write(&source, "Well done").unwrap();
+        let attachment = add_attachment(&conn, win_id(&conn), &source).unwrap();
+        let object = object_path(&conn, &attachment).unwrap();
+        let copy = dir.join("snapshot.attachments");
+        copy_store(&store_dir(&conn).unwrap(), &copy).unwrap();
+
+        // Removing the attachment takes its file with it ...
+        remove_attachment(&conn, attachment.id).unwrap();
+        assert!(!object.exists());
+
+        // ... and a restore puts the row and the file back
+        conn.execute(
+            "INSERT INTO win_attachments (id, win_id, hash, file_name, mime, size_bytes, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            (
+                attachment.id,
+                attachment.win_id,
+                &attachment.hash,
+                &attachment.file_name,
+                &attachment.mime,
+                attachment.size_bytes,
+                attachment.created_at,
+            ),
+        )
+        .unwrap();
+        copy_store(&copy, &store_dir(&conn).unwrap()).unwrap();
+        collect_garbage(&conn);
+        assert_eq!(fs::read_to_string(&object).unwrap(), "Well done");
+        assert_eq!(attachment_file(&conn, attachment.id).unwrap(), object);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_stores_copy_as_nothing() {
+        let dir = std::env::temp_dir().join(format!(
+            "quietwins-attachments-{}-missing",
+            std::process::id()
+        ));
+        copy_store(&dir.join("nowhere"), &dir.join("copy")).unwrap();
+        assert!(!dir.join("copy").exists());
+    }
+}
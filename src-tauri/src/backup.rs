@@ -0,0 +1,475 @@
+// backup.rs - Rotating snapshots of the journal using SQLite's online backup API
+
+use crate::attachments;
+use crate::db::Db;
+use crate::encryption;
+use crate::errors;
+use crate::migrations;
+use chrono::{Datelike, Local, NaiveDateTime, TimeZone};
+use rusqlite::backup::Backup;
+use rusqlite::{ffi, Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const BACKUP_DIR: &str = "backups";
+const RETENTION_FILE: &str = "backup_retention.json";
+const SNAPSHOT_PREFIX: &str = "quietwins-";
+const SNAPSHOT_EXT: &str = "sqlite";
+/// Each snapshot's attachment files sit in a folder named like it with this
+/// extension
+const ATTACHMENTS_EXT: &str = "attachments";
+const STAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+/// The scheduler takes a new snapshot once the newest one is this old
+const SNAPSHOT_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+/// How many snapshots to keep: the newest one for each of the last `daily`
+/// days, `weekly` ISO weeks and `monthly` months that have snapshots. The
+/// newest snapshot is always kept.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            daily: 7,
+            weekly: 4,
+            monthly: 12,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct BackupInfo {
+    pub file_name: String,
+    pub path: String,
+    /// Local time the snapshot was taken, as a unix timestamp
+    pub created_at: i64,
+    pub size_bytes: u64,
+    pub encrypted: bool,
+}
+
+pub fn backup_dir(db: &Db) -> PathBuf {
+    db_dir(db.path()).join(BACKUP_DIR)
+}
+
+fn db_dir(db_path: &Path) -> &Path {
+    db_path.parent().unwrap_or_else(|| Path::new("."))
+}
+
+pub fn get_retention(db: &Db) -> RetentionPolicy {
+    fs::read_to_string(db_dir(db.path()).join(RETENTION_FILE))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Saves the policy next to the database (not inside it, so restoring an
+/// old snapshot doesn't roll the policy back) and prunes right away.
+pub fn set_retention(db: &Db, policy: RetentionPolicy) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&policy).map_err(|e| e.to_string())?;
+    fs::write(db_dir(db.path()).join(RETENTION_FILE), json).map_err(|e| e.to_string())?;
+    prune(&backup_dir(db), &policy);
+    Ok(())
+}
+
+/// Newest first.
+pub fn list_backups(db: &Db) -> Vec<BackupInfo> {
+    let dir = backup_dir(db);
+    let mut backups: Vec<BackupInfo> = fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| snapshot_info(&entry.path()))
+                .collect()
+        })
+        .unwrap_or_default();
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    backups
+}
+
+fn snapshot_info(path: &Path) -> Option<BackupInfo> {
+    let taken_at = snapshot_time(path)?;
+    Some(BackupInfo {
+        file_name: path.file_name()?.to_string_lossy().to_string(),
+        path: path.display().to_string(),
+        created_at: Local.from_local_datetime(&taken_at).earliest()?.timestamp(),
+        size_bytes: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        encrypted: encryption::is_encrypted(path),
+    })
+}
+
+/// Parses the local timestamp out of `quietwins-YYYYMMDD-HHMMSS.sqlite`.
+fn snapshot_time(path: &Path) -> Option<NaiveDateTime> {
+    if path.extension()? != SNAPSHOT_EXT {
+        return None;
+    }
+    let stamp = path.file_stem()?.to_str()?.strip_prefix(SNAPSHOT_PREFIX)?;
+    NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT).ok()
+}
+
+fn attachments_of(snapshot: &Path) -> PathBuf {
+    snapshot.with_extension(ATTACHMENTS_EXT)
+}
+
+/// Takes a snapshot, then prunes old ones per the retention policy.
+pub fn create_backup(db: &Db) -> Result<BackupInfo> {
+    let info = take_snapshot(db)?;
+    prune(&backup_dir(db), &get_retention(db));
+    Ok(info)
+}
+
+/// Copies the live database into a new snapshot with the online backup API,
+/// which pages through the file without blocking writers for long.
+/// Snapshots of an encrypted database are encrypted with the same key.
+/// Attachment files are copied alongside so a restore brings them back too.
+fn take_snapshot(db: &Db) -> Result<BackupInfo> {
+    let dir = backup_dir(db);
+    fs::create_dir_all(&dir).map_err(|e| errors::failure(ffi::SQLITE_CANTOPEN, &e.to_string()))?;
+    let path = dir.join(format!(
+        "{}{}.{}",
+        SNAPSHOT_PREFIX,
+        Local::now().format(STAMP_FORMAT),
+        SNAPSHOT_EXT
+    ));
+    if path.exists() {
+        // Two snapshots in the same second; the existing one is just as fresh
+        return snapshot_info(&path).ok_or(rusqlite::Error::InvalidPath(path));
+    }
+    let staged = encryption::sibling(&path, ".partial");
+    let files = attachments_of(&path);
+    let store = attachments::store_dir_for(db.path());
+    let key = db.key();
+    let copied = db.with(|conn| {
+        let mut dest = Connection::open(&staged)?;
+        if let Some(key) = &key {
+            dest.pragma_update(None, "key", key)?;
+        }
+        let backup = Backup::new(conn, &mut dest)?;
+        backup.run_to_completion(256, Duration::from_millis(5), None)?;
+        // Still holding the connection, so no attachment comes or goes in between
+        attachments::copy_store(&store, &files)
+            .map_err(|e| errors::failure(ffi::SQLITE_IOERR, &e.to_string()))
+    });
+    if let Err(e) = copied.and_then(|_| {
+        fs::rename(&staged, &path).map_err(|e| errors::failure(ffi::SQLITE_IOERR, &e.to_string()))
+    }) {
+        encryption::remove_db_files(&staged);
+        let _ = fs::remove_dir_all(&files);
+        return Err(e);
+    }
+    println!("[backup] Wrote {}", path.display());
+    snapshot_info(&path).ok_or(rusqlite::Error::InvalidPath(path))
+}
+
+/// Called hourly by the backup thread; snapshots once a day.
+pub fn run_scheduled(db: &Db) {
+    if db.is_locked() {
+        return;
+    }
+    let now = Local::now().timestamp();
+    let recent = list_backups(db)
+        .first()
+        .is_some_and(|newest| now - newest.created_at < SNAPSHOT_INTERVAL_SECS);
+    if !recent {
+        if let Err(e) = create_backup(db) {
+            println!("[backup] Scheduled snapshot failed: {}", e);
+        }
+    }
+}
+
+/// Deletes snapshots that no retention slot claims.
+fn prune(dir: &Path, policy: &RetentionPolicy) {
+    let snapshots: Vec<(NaiveDateTime, PathBuf)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| Some((snapshot_time(&entry.path())?, entry.path())))
+            .collect(),
+        Err(_) => return,
+    };
+    for path in expired(snapshots, policy) {
+        println!("[backup] Pruning {}", path.display());
+        encryption::remove_db_files(&path);
+        let _ = fs::remove_dir_all(attachments_of(&path));
+    }
+}
+
+/// The snapshots no retention slot claims, given when each was taken.
+fn expired<T>(mut snapshots: Vec<(NaiveDateTime, T)>, policy: &RetentionPolicy) -> Vec<T> {
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.0));
+    let mut expired = Vec::new();
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    let mut months = HashSet::new();
+    for (i, (taken_at, snapshot)) in snapshots.into_iter().enumerate() {
+        let date = taken_at.date();
+        let week = date.iso_week();
+        // Newest first, so the first snapshot seen in a bucket is its newest
+        let mut keep = i == 0;
+        if days.len() < policy.daily && days.insert(date) {
+            keep = true;
+        }
+        if weeks.len() < policy.weekly && weeks.insert((week.year(), week.week())) {
+            keep = true;
+        }
+        if months.len() < policy.monthly && months.insert((date.year(), date.month())) {
+            keep = true;
+        }
+        if !keep {
+            expired.push(snapshot);
+        }
+    }
+    expired
+}
+
+/// Replaces the live database with a snapshot. The snapshot is checked
+/// first (opens with the current key, passes quick_check, has a schema this
+/// build understands) and the current state is itself snapshotted, so a
+/// restore can always be undone by restoring that one.
+pub fn restore_backup(db: &Db, file_name: &str) -> Result<()> {
+    let path = backup_dir(db).join(file_name);
+    if Path::new(file_name).file_name() != Some(file_name.as_ref())
+        || snapshot_time(&path).is_none()
+        || !path.exists()
+    {
+        return Err(errors::failure(
+            ffi::SQLITE_NOTFOUND,
+            &format!("No backup named '{}'.", file_name),
+        ));
+    }
+    println!("[restore_backup] Restoring {}", path.display());
+    let snapshot = open_snapshot(db, &path)?;
+    validate_snapshot(&snapshot)?;
+    // Not pruned here: that could remove the snapshot being restored
+    let safety = take_snapshot(db)?;
+    println!(
+        "[restore_backup] Current state saved as {}",
+        safety.file_name
+    );
+    let store = attachments::store_dir_for(db.path());
+    db.with(|conn| {
+        Backup::new(&snapshot, conn)?.run_to_completion(256, Duration::from_millis(0), None)?;
+        // Snapshots from older builds are brought up to the current schema
+        migrations::run_migrations(conn)?;
+        // Put back the files the restored rows point to (the store only ever
+        // gains objects here), then drop the ones nothing points to any more
+        attachments::copy_store(&attachments_of(&path), &store)
+            .map_err(|e| errors::failure(ffi::SQLITE_IOERR, &e.to_string()))?;
+        attachments::collect_garbage(conn);
+        Ok(())
+    })?;
+    println!("[restore_backup] Restore complete");
+    Ok(())
+}
+
+fn open_snapshot(db: &Db, path: &Path) -> Result<Connection> {
+    let encrypted = encryption::is_encrypted(path);
+    if encrypted != encryption::is_encrypted(db.path()) {
+        return Err(errors::failure(
+            ffi::SQLITE_AUTH,
+            "This backup doesn't match the current privacy lock (it predates a lock change or is damaged).",
+        ));
+    }
+    let key = db.key();
+    let conn = encryption::open(path, if encrypted { key.as_deref() } else { None })?;
+    Ok(conn)
+}
+
+fn validate_snapshot(conn: &Connection) -> Result<()> {
+    let status: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    if status != "ok" {
+        return Err(errors::failure(
+            ffi::SQLITE_CORRUPT,
+            &format!("Backup failed its integrity check: {}", status),
+        ));
+    }
+    let version = migrations::schema_version(conn)?;
+    if version > migrations::latest_version() {
+        return Err(errors::failure(
+            ffi::SQLITE_CANTOPEN,
+            "Backup was made by a newer version of Quiet Wins.",
+        ));
+    }
+    let has_wins: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'wins')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_wins {
+        return Err(errors::failure(
+            ffi::SQLITE_CORRUPT,
+            "Backup does not contain a Quiet Wins journal.",
+        ));
+    }
+    Ok(())
+}
+
+/// Re-encrypts every snapshot after the privacy lock changes so old
+/// snapshots never stay readable. Snapshots that don't open with `old_key`
+/// (taken under an even older passphrase) are left alone.
+pub(crate) fn rekey_snapshots(db_path: &Path, old_key: Option<&str>, new_key: Option<&str>) {
+    let Ok(entries) = fs::read_dir(db_dir(db_path).join(BACKUP_DIR)) else {
+        return;
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+        if snapshot_time(&path).is_none() || encryption::is_encrypted(&path) != old_key.is_some() {
+            continue;
+        }
+        let staged = encryption::sibling(&path, ".rekey");
+        let rekeyed = encryption::open(&path, old_key)
+            .and_then(|conn| encryption::export_copy(&conn, &staged, new_key))
+            .and_then(|_| {
+                fs::rename(&staged, &path)
+                    .map_err(|e| errors::failure(ffi::SQLITE_IOERR, &e.to_string()))
+            });
+        if let Err(e) = rekeyed {
+            println!("[backup] Could not re-key {}: {}", path.display(), e);
+            encryption::remove_db_files(&staged);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(stamp: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(stamp, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// Which of `stamps` would be pruned, oldest first.
+    fn pruned(
+        stamps: &[&'static str],
+        daily: usize,
+        weekly: usize,
+        monthly: usize,
+    ) -> Vec<&'static str> {
+        let policy = RetentionPolicy {
+            daily,
+            weekly,
+            monthly,
+        };
+        let mut gone = expired(stamps.iter().map(|s| (at(s), *s)).collect(), &policy);
+        gone.sort();
+        gone
+    }
+
+    #[test]
+    fn keeps_the_newest_snapshot_per_day() {
+        let stamps = [
+            "2025-03-10 09:00",
+            "2025-03-10 21:00",
+            "2025-03-11 09:00",
+            "2025-03-12 09:00",
+            "2025-03-12 10:00",
+        ];
+        assert_eq!(
+            pruned(&stamps, 7, 0, 0),
+            vec!["2025-03-10 09:00", "2025-03-12 09:00"]
+        );
+        // Only the last two days with snapshots
+        assert_eq!(
+            pruned(&stamps, 2, 0, 0),
+            vec!["2025-03-10 09:00", "2025-03-10 21:00", "2025-03-12 09:00"]
+        );
+    }
+
+    #[test]
+    fn weekly_slots_follow_iso_weeks() {
+        // 2024-12-30 is a Monday and already in ISO week 1 of 2025, so the
+        // Sunday before it is the end of the previous week
+        let stamps = [
+            "2024-12-22 12:00",
+            "2024-12-29 12:00",
+            "2024-12-30 12:00",
+            "2025-01-04 12:00",
+        ];
+        assert_eq!(pruned(&stamps, 0, 4, 0), vec!["2024-12-30 12:00"]);
+        assert_eq!(
+            pruned(&stamps, 0, 2, 0),
+            vec!["2024-12-22 12:00", "2024-12-30 12:00"]
+        );
+    }
+
+    #[test]
+    fn monthly_slots_keep_each_months_newest() {
+        let stamps = [
+            "2024-11-03 08:00",
+            "2024-11-28 08:00",
+            "2024-12-31 23:59",
+            "2025-01-01 00:00",
+            "2025-01-15 08:00",
+        ];
+        assert_eq!(
+            pruned(&stamps, 0, 0, 12),
+            vec!["2024-11-03 08:00", "2025-01-01 00:00"]
+        );
+        assert_eq!(
+            pruned(&stamps, 0, 0, 2),
+            vec!["2024-11-03 08:00", "2024-11-28 08:00", "2025-01-01 00:00"]
+        );
+    }
+
+    #[test]
+    fn slots_add_up() {
+        // A year of daily snapshots under the default policy
+        let start = at("2024-01-01 03:00");
+        let snapshots: Vec<(NaiveDateTime, NaiveDateTime)> = (0..366)
+            .map(|d| {
+                let t = start + chrono::Duration::days(d);
+                (t, t)
+            })
+            .collect();
+        let policy = RetentionPolicy::default();
+        let gone: HashSet<NaiveDateTime> =
+            expired(snapshots.clone(), &policy).into_iter().collect();
+        let kept: Vec<String> = snapshots
+            .iter()
+            .filter(|(t, _)| !gone.contains(t))
+            .map(|(t, _)| t.date().to_string())
+            .collect();
+        // Dec 25-31 daily. Those are also the newest of two ISO weeks (Dec
+        // 30 and 31 fall in week 1 of 2025), leaving the Sundays Dec 15 and
+        // 22 for the other two weekly slots. Month ends Jan-Nov; Dec 31 is a
+        // daily already.
+        assert_eq!(
+            kept,
+            vec![
+                "2024-01-31",
+                "2024-02-29",
+                "2024-03-31",
+                "2024-04-30",
+                "2024-05-31",
+                "2024-06-30",
+                "2024-07-31",
+                "2024-08-31",
+                "2024-09-30",
+                "2024-10-31",
+                "2024-11-30",
+                "2024-12-15",
+                "2024-12-22",
+                "2024-12-25",
+                "2024-12-26",
+                "2024-12-27",
+                "2024-12-28",
+                "2024-12-29",
+                "2024-12-30",
+                "2024-12-31",
+            ]
+        );
+    }
+
+    #[test]
+    fn newest_is_kept_even_with_no_slots() {
+        let stamps = ["2025-01-01 10:00", "2025-01-02 10:00"];
+        assert_eq!(pruned(&stamps, 0, 0, 0), vec!["2025-01-01 10:00"]);
+        assert!(pruned(&[], 0, 0, 0).is_empty());
+    }
+}
@@ -0,0 +1,1010 @@
+// chains.rs - Win chains: related wins grouped over time, with stable ids
+
+use crate::db::{self, Db, Win};
+use crate::errors;
+use crate::query::{self, WinQuery};
+use crate::similarity::{self, Corpus, SharedTerm};
+use crate::stats::TagCount;
+use chrono::NaiveDate;
+use rusqlite::{ffi, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+use time::OffsetDateTime;
+
+const SETTINGS_FILE: &str = "chain_settings.json";
+
+/// Shared terms reported per chain
+const SHARED_TERMS: usize = 5;
+/// Tags listed on a chain timeline, and shared terms in its title
+const DOMINANT_TAGS: usize = 3;
+const TITLE_TERMS: usize = 2;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct ChainSettings {
+    /// Wins further apart than this never link directly; a chain can still
+    /// run longer through the wins in between
+    pub max_gap_days: i64,
+    /// TF-IDF cosine similarity, 0 to 1, two wins need to link. Lower
+    /// makes longer, looser chains
+    pub min_similarity: f64,
+    /// Smallest group of wins shown as a chain
+    pub min_length: usize,
+}
+
+impl Default for ChainSettings {
+    fn default() -> Self {
+        ChainSettings {
+            max_gap_days: 14,
+            min_similarity: 0.15,
+            min_length: 2,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct WinWithChain {
+    #[serde(flatten)]
+    pub win: Win,
+    /// `None` for wins outside any chain
+    pub chain_id: Option<i64>,
+}
+
+/// A stretch of days without wins inside a chain.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ChainGap {
+    /// Last win before the gap
+    pub after: String,
+    /// First win after it
+    pub before: String,
+    /// Days with no win in between
+    pub days: i64,
+}
+
+/// A chain laid out in time, for showing an effort like "thesis" as a whole.
+#[derive(Serialize)]
+pub struct ChainTimeline {
+    pub id: i64,
+    /// Built from the shared terms, e.g. "Thesis / chapter"
+    pub title: String,
+    /// Oldest first
+    pub wins: Vec<Win>,
+    pub start: String,
+    pub end: String,
+    /// Calendar days from the first win to the last, both included
+    pub span_days: i64,
+    /// Days with at least one win
+    pub active_days: usize,
+    /// Longest first
+    pub gaps: Vec<ChainGap>,
+    pub dominant_tags: Vec<TagCount>,
+    pub shared_terms: Vec<SharedTerm>,
+    /// Whether the last win is recent enough for the next one to still join
+    pub active: bool,
+}
+
+/// Why a chain's wins belong together.
+#[derive(Serialize, Clone, Debug)]
+pub struct ChainSummary {
+    pub id: i64,
+    pub length: usize,
+    /// Terms its wins share, strongest first
+    pub shared_terms: Vec<SharedTerm>,
+}
+
+/// One page of the log, with the chains its wins belong to.
+#[derive(Serialize)]
+pub struct ChainedWins {
+    pub wins: Vec<WinWithChain>,
+    /// Every chain with a win on the page, counted over all of its wins
+    pub chains: Vec<ChainSummary>,
+    /// See `query::WinPage`
+    pub next_cursor: Option<String>,
+}
+
+/// The live wins detection ran over, oldest first, and their vectors.
+pub(crate) struct Analysis {
+    /// Position of each win id in `corpus`
+    pub index: HashMap<i64, usize>,
+    pub corpus: Corpus,
+}
+
+impl Analysis {
+    /// The shared terms of a chain, given its win ids.
+    pub fn explain(&self, win_ids: &[i64]) -> Vec<SharedTerm> {
+        let members: Vec<usize> = win_ids
+            .iter()
+            .filter_map(|id| self.index.get(id).copied())
+            .collect();
+        self.corpus.shared_terms(&members, SHARED_TERMS)
+    }
+}
+
+fn settings_path(db: &Db) -> std::path::PathBuf {
+    db.path()
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(SETTINGS_FILE)
+}
+
+pub fn get_settings(db: &Db) -> ChainSettings {
+    fs::read_to_string(settings_path(db))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn set_settings(db: &Db, settings: &ChainSettings) -> Result<(), String> {
+    if settings.max_gap_days < 1 {
+        return Err("The gap must be at least one day.".to_string());
+    }
+    if !(settings.min_similarity > 0.0 && settings.min_similarity <= 1.0) {
+        return Err("The similarity threshold must be above 0 and at most 1.".to_string());
+    }
+    if settings.min_length < 2 {
+        return Err("A chain needs at least two wins.".to_string());
+    }
+    let json = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    fs::write(settings_path(db), json).map_err(|e| e.to_string())
+}
+
+/// One page of the live wins matching `query`, each with its chain, and what
+/// the wins of those chains share. Chains are brought up to date first.
+pub fn get_wins_with_chains(
+    conn: &mut Connection,
+    settings: &ChainSettings,
+    query: &WinQuery,
+) -> Result<ChainedWins> {
+    let analysis = refresh(conn, settings)?;
+    let page = query::query_wins(conn, query)?;
+    let page_ids: Vec<i64> = page.wins.iter().map(|w| w.id).collect();
+    let page_ids = serde_json::to_string(&page_ids).unwrap_or_else(|_| "[]".to_string());
+    let mut chain_of: HashMap<i64, i64> = HashMap::new();
+    let mut members: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT cw.win_id, cw.chain_id FROM chain_wins cw JOIN wins w ON w.id = cw.win_id
+             WHERE cw.chain_id IN (SELECT chain_id FROM chain_wins
+                                   WHERE win_id IN (SELECT value FROM json_each(?1)))",
+        )?;
+        let rows = stmt.query_map([&page_ids], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (win_id, chain_id) = row?;
+            chain_of.insert(win_id, chain_id);
+            members.entry(chain_id).or_default().push(win_id);
+        }
+    }
+    let wins = page
+        .wins
+        .into_iter()
+        .map(|win| WinWithChain {
+            chain_id: chain_of.get(&win.id).copied(),
+            win,
+        })
+        .collect();
+    let chains = members
+        .into_iter()
+        .map(|(id, win_ids)| ChainSummary {
+            id,
+            length: win_ids.len(),
+            shared_terms: analysis.explain(&win_ids),
+        })
+        .collect();
+    Ok(ChainedWins {
+        wins,
+        chains,
+        next_cursor: page.next_cursor,
+    })
+}
+
+/// Runs detection over the live wins and stores the result. Pinned rows are
+/// never changed. Each detected group keeps the chain id most of its wins
+/// already had, so ids survive new wins, edits and settings changes.
+pub(crate) fn refresh(conn: &mut Connection, settings: &ChainSettings) -> Result<Analysis> {
+    let tx = conn.transaction()?;
+    let mut ids: Vec<i64> = Vec::new();
+    let mut dates: Vec<NaiveDate> = Vec::new();
+    let mut docs: Vec<(String, Vec<String>)> = Vec::new();
+    {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT {} FROM wins w
+             WHERE NOT EXISTS (SELECT 1 FROM chain_wins cw
+                               WHERE cw.win_id = w.id AND cw.pinned AND cw.chain_id IS NULL)
+             ORDER BY w.date, w.id",
+            db::win_columns()
+        ))?;
+        let rows = stmt.query_map([], db::win_from_row)?;
+        for win in rows {
+            let win = win?;
+            let Ok(date) = NaiveDate::parse_from_str(&win.date, "%Y-%m-%d") else {
+                continue;
+            };
+            ids.push(win.id);
+            dates.push(date);
+            docs.push((win.text, win.tags));
+        }
+    }
+    let corpus = Corpus::build(
+        docs.iter()
+            .map(|(text, tags)| (text.as_str(), tags.as_slice())),
+    );
+    let mut current: HashMap<i64, (Option<i64>, bool)> = HashMap::new();
+    {
+        let mut stmt = tx.prepare("SELECT win_id, chain_id, pinned FROM chain_wins")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, (row.get(1)?, row.get(2)?)))
+        })?;
+        for row in rows {
+            let (win_id, assignment) = row?;
+            current.insert(win_id, assignment);
+        }
+    }
+    let pinned = |win_id: i64| current.get(&win_id).is_some_and(|(_, p)| *p);
+
+    let mut groups = detect(&dates, &corpus, settings);
+    groups.sort_by_key(|g| std::cmp::Reverse(g.len()));
+    let mut wanted: HashMap<i64, Option<i64>> = HashMap::new();
+    let mut claimed: HashSet<i64> = HashSet::new();
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    for group in groups {
+        let members: Vec<i64> = group.iter().map(|&i| ids[i]).collect();
+        let free: Vec<i64> = members.iter().copied().filter(|w| !pinned(*w)).collect();
+        if free.is_empty() {
+            continue;
+        }
+        // Wins the user placed decide first, then whatever the group had
+        let anchors: Vec<i64> = members.iter().copied().filter(|w| pinned(*w)).collect();
+        let voters = if anchors.is_empty() { &free } else { &anchors };
+        let mut votes: HashMap<i64, usize> = HashMap::new();
+        for w in voters {
+            if let Some((Some(chain), _)) = current.get(w) {
+                *votes.entry(*chain).or_insert(0) += 1;
+            }
+        }
+        let mut ranked: Vec<(i64, usize)> = votes.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let chain = match ranked.into_iter().find(|(c, _)| !claimed.contains(c)) {
+            _ if anchors.is_empty() && members.len() < settings.min_length => None,
+            Some((c, _)) => Some(c),
+            None if members.len() >= settings.min_length => {
+                tx.execute("INSERT INTO chains (created_at) VALUES (?1)", [now])?;
+                Some(tx.last_insert_rowid())
+            }
+            None => None,
+        };
+        if let Some(c) = chain {
+            claimed.insert(c);
+        }
+        for w in free {
+            wanted.insert(w, chain);
+        }
+    }
+
+    let mut changed = 0;
+    for win_id in ids.iter().copied().filter(|w| !pinned(*w)) {
+        let want = wanted.get(&win_id).copied().flatten();
+        let have = current.get(&win_id).and_then(|(c, _)| *c);
+        if want == have {
+            continue;
+        }
+        changed += 1;
+        match want {
+            Some(chain) => tx.execute(
+                "INSERT OR REPLACE INTO chain_wins (win_id, chain_id, pinned) VALUES (?1, ?2, 0)",
+                (win_id, chain),
+            )?,
+            None => tx.execute("DELETE FROM chain_wins WHERE win_id = ?1", [win_id])?,
+        };
+    }
+    tx.execute(
+        "DELETE FROM chains WHERE id NOT IN
+             (SELECT chain_id FROM chain_wins WHERE chain_id IS NOT NULL)",
+        [],
+    )?;
+    tx.commit()?;
+    if changed > 0 {
+        println!("[refresh_chains] {} wins changed chain", changed);
+    }
+    let index = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    Ok(Analysis { index, corpus })
+}
+
+/// Groups wins (sorted by `dates`) whose TF-IDF cosine similarity with a win
+/// no more than `max_gap_days` earlier reaches `min_similarity`. Each win is
+/// only scored against recent wins sharing a term, so the cost grows with the
+/// number of wins, not its square. Returns groups of indices, singletons
+/// included.
+pub(crate) fn detect(
+    dates: &[NaiveDate],
+    corpus: &Corpus,
+    settings: &ChainSettings,
+) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..dates.len()).collect();
+    // Per term, the recent wins containing it with its weight there
+    let mut recent: HashMap<usize, VecDeque<(usize, f64)>> = HashMap::new();
+    for (i, vector) in corpus.vectors.iter().enumerate() {
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for &(term, weight) in vector {
+            let queue = recent.entry(term).or_default();
+            while queue
+                .front()
+                .is_some_and(|&(j, _)| (dates[i] - dates[j]).num_days() > settings.max_gap_days)
+            {
+                queue.pop_front();
+            }
+            for &(j, other) in queue.iter() {
+                *scores.entry(j).or_insert(0.0) += weight * other;
+            }
+            queue.push_back((i, weight));
+        }
+        for (j, score) in scores {
+            if score >= settings.min_similarity {
+                union(&mut parent, i, j);
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..dates.len() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+    let mut groups: Vec<Vec<usize>> = groups.into_values().collect();
+    groups.sort_by_key(|g| g[0]);
+    groups
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    if a != b {
+        parent[a.max(b)] = a.min(b);
+    }
+}
+
+/// One chain's wins in order with its dates, gaps, tags and a title.
+/// Chains are brought up to date first, so `chain_id` must still exist.
+pub fn get_chain_timeline(
+    conn: &mut Connection,
+    chain_id: i64,
+    settings: &ChainSettings,
+    today: NaiveDate,
+) -> Result<ChainTimeline> {
+    let analysis = refresh(conn, settings)?;
+    ensure_chain(conn, chain_id)?;
+    timeline(conn, &analysis, chain_id, settings, today)
+}
+
+/// Every chain's timeline, ongoing chains first, then the most recently
+/// ended.
+pub fn get_chain_timelines(
+    conn: &mut Connection,
+    settings: &ChainSettings,
+    today: NaiveDate,
+) -> Result<Vec<ChainTimeline>> {
+    let analysis = refresh(conn, settings)?;
+    // Chains with a live win; the rest only hold trashed wins
+    let ids = conn
+        .prepare(
+            "SELECT DISTINCT cw.chain_id FROM chain_wins cw JOIN wins w ON w.id = cw.win_id
+             WHERE cw.chain_id IS NOT NULL",
+        )?
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>>>()?;
+    let mut timelines = Vec::new();
+    for id in ids {
+        timelines.push(timeline(conn, &analysis, id, settings, today)?);
+    }
+    timelines.sort_by(|a, b| b.active.cmp(&a.active).then_with(|| b.end.cmp(&a.end)));
+    Ok(timelines)
+}
+
+fn timeline(
+    conn: &Connection,
+    analysis: &Analysis,
+    chain_id: i64,
+    settings: &ChainSettings,
+    today: NaiveDate,
+) -> Result<ChainTimeline> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM wins w JOIN chain_wins cw ON cw.win_id = w.id
+         WHERE cw.chain_id = ?1",
+        db::win_columns()
+    ))?;
+    let wins = stmt
+        .query_map([chain_id], db::win_from_row)?
+        .collect::<Result<Vec<_>>>()?;
+    let ids: Vec<i64> = wins.iter().map(|w| w.id).collect();
+    lay_out(chain_id, &wins, analysis.explain(&ids), settings, today)
+        .ok_or_else(|| errors::failure(ffi::SQLITE_NOTFOUND, "This chain has no wins left."))
+}
+
+/// Lays a chain's wins out in time: ordered by date, then by when they were
+/// logged, with the gaps between active days, the dominant tags and a title.
+/// `None` for a chain with no wins.
+fn lay_out(
+    chain_id: i64,
+    wins: &[Win],
+    shared_terms: Vec<SharedTerm>,
+    settings: &ChainSettings,
+    today: NaiveDate,
+) -> Option<ChainTimeline> {
+    let mut wins = wins.to_vec();
+    wins.sort_by(|a, b| (&a.date, a.created_at, a.id).cmp(&(&b.date, b.created_at, b.id)));
+    let (start, end) = (wins.first()?.date.clone(), wins.last()?.date.clone());
+
+    let mut days: Vec<NaiveDate> = wins
+        .iter()
+        .filter_map(|w| NaiveDate::parse_from_str(&w.date, "%Y-%m-%d").ok())
+        .collect();
+    days.dedup();
+    let mut gaps: Vec<ChainGap> = days
+        .windows(2)
+        .filter(|pair| (pair[1] - pair[0]).num_days() > 1)
+        .map(|pair| ChainGap {
+            after: pair[0].format("%Y-%m-%d").to_string(),
+            before: pair[1].format("%Y-%m-%d").to_string(),
+            days: (pair[1] - pair[0]).num_days() - 1,
+        })
+        .collect();
+    gaps.sort_by(|a, b| b.days.cmp(&a.days).then_with(|| a.after.cmp(&b.after)));
+    let (span_days, active) = match (days.first(), days.last()) {
+        (Some(first), Some(last)) => (
+            (*last - *first).num_days() + 1,
+            (today - *last).num_days() <= settings.max_gap_days,
+        ),
+        _ => (0, false),
+    };
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for tag in wins.iter().flat_map(|w| w.tags.iter()) {
+        if !similarity::GENERIC_TAGS.contains(&tag.to_lowercase().as_str()) {
+            *counts.entry(tag.clone()).or_insert(0) += 1;
+        }
+    }
+    let mut dominant_tags: Vec<TagCount> = counts
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag, count })
+        .collect();
+    dominant_tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    dominant_tags.truncate(DOMINANT_TAGS);
+
+    let title = title(&shared_terms, &dominant_tags);
+    Some(ChainTimeline {
+        id: chain_id,
+        title,
+        start,
+        end,
+        span_days,
+        active_days: days.len(),
+        gaps,
+        dominant_tags,
+        shared_terms,
+        active,
+        wins,
+    })
+}
+
+/// "Marathon / training" from the strongest shared terms, falling back to
+/// the most used tag when the wins were grouped by hand and share no words.
+fn title(shared_terms: &[SharedTerm], tags: &[TagCount]) -> String {
+    let mut words: Vec<&str> = shared_terms
+        .iter()
+        .take(TITLE_TERMS)
+        .map(|t| t.term.as_str())
+        .collect();
+    if words.is_empty() {
+        words.extend(tags.first().map(|t| t.tag.as_str()));
+    }
+    let title = words.join(" / ");
+    let mut chars = title.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => "Untitled chain".to_string(),
+    }
+}
+
+/// Puts a win in a chain by hand. Detection won't move it again.
+pub fn add_to_chain(conn: &Connection, chain_id: i64, win_id: i64) -> Result<()> {
+    ensure_chain(conn, chain_id)?;
+    ensure_win(conn, win_id)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO chain_wins (win_id, chain_id, pinned) VALUES (?1, ?2, 1)",
+        (win_id, chain_id),
+    )?;
+    Ok(())
+}
+
+/// Takes a win out of its chain and keeps it out of every chain.
+pub fn remove_from_chain(conn: &Connection, win_id: i64) -> Result<()> {
+    ensure_win(conn, win_id)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO chain_wins (win_id, chain_id, pinned) VALUES (?1, NULL, 1)",
+        [win_id],
+    )?;
+    Ok(())
+}
+
+/// Starts a chain from the given wins. Returns the new chain id.
+pub fn new_chain(conn: &mut Connection, win_ids: &[i64]) -> Result<i64> {
+    if win_ids.len() < 2 {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "A chain needs at least two wins.",
+        ));
+    }
+    let tx = conn.transaction()?;
+    let chain = create(&tx)?;
+    for win_id in win_ids {
+        ensure_win(&tx, *win_id)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO chain_wins (win_id, chain_id, pinned) VALUES (?1, ?2, 1)",
+            (win_id, chain),
+        )?;
+    }
+    tx.commit()?;
+    Ok(chain)
+}
+
+/// Moves `from_win` and every later win of the chain into a new chain,
+/// in timeline order. Both halves stay as they are split. Returns the new
+/// chain id.
+pub fn split_chain(conn: &mut Connection, chain_id: i64, from_win: i64) -> Result<i64> {
+    let tx = conn.transaction()?;
+    ensure_chain(&tx, chain_id)?;
+    let at: Option<(String, i64, bool)> = tx
+        .query_row(
+            "SELECT w.date, w.created_at, EXISTS (
+                 SELECT 1 FROM wins e JOIN chain_wins ce ON ce.win_id = e.id
+                 WHERE ce.chain_id = ?2
+                     AND (e.date, e.created_at, e.id) < (w.date, w.created_at, w.id))
+             FROM wins w JOIN chain_wins cw ON cw.win_id = w.id
+             WHERE w.id = ?1 AND cw.chain_id = ?2",
+            (from_win, chain_id),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    let Some((date, created_at, has_earlier)) = at else {
+        return Err(errors::failure(
+            ffi::SQLITE_NOTFOUND,
+            "That win isn't in this chain.",
+        ));
+    };
+    if !has_earlier {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "Splitting at the first win would leave the chain empty. Pick a later win.",
+        ));
+    }
+    let chain = create(&tx)?;
+    tx.execute(
+        "UPDATE chain_wins SET pinned = 1 WHERE chain_id = ?1",
+        [chain_id],
+    )?;
+    // Same order as the timeline
+    let moved = tx.execute(
+        "UPDATE chain_wins SET chain_id = ?1 WHERE chain_id = ?2 AND win_id IN
+             (SELECT id FROM wins WHERE (date, created_at, id) >= (?3, ?4, ?5))",
+        (chain, chain_id, &date, created_at, from_win),
+    )?;
+    println!(
+        "[split_chain] {} wins from chain {} into {}",
+        moved, chain_id, chain
+    );
+    tx.commit()?;
+    Ok(chain)
+}
+
+/// Folds the other chains into the first one. Returns its id.
+pub fn merge_chains(conn: &mut Connection, chain_ids: &[i64]) -> Result<i64> {
+    let Some((&into, rest)) = chain_ids.split_first() else {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "Pick the chains to merge.",
+        ));
+    };
+    let tx = conn.transaction()?;
+    for id in chain_ids {
+        ensure_chain(&tx, *id)?;
+    }
+    for id in rest.iter().filter(|id| **id != into) {
+        tx.execute(
+            "UPDATE chain_wins SET chain_id = ?1 WHERE chain_id = ?2",
+            (into, id),
+        )?;
+        tx.execute("DELETE FROM chains WHERE id = ?1", [id])?;
+    }
+    tx.execute(
+        "UPDATE chain_wins SET pinned = 1 WHERE chain_id = ?1",
+        [into],
+    )?;
+    println!("[merge_chains] {:?} into {}", rest, into);
+    tx.commit()?;
+    Ok(into)
+}
+
+fn create(conn: &Connection) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO chains (created_at) VALUES (?1)",
+        [OffsetDateTime::now_utc().unix_timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn ensure_chain(conn: &Connection, chain_id: i64) -> Result<()> {
+    let found: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM chains WHERE id = ?1)",
+        [chain_id],
+        |row| row.get(0),
+    )?;
+    if found {
+        Ok(())
+    } else {
+        Err(errors::failure(ffi::SQLITE_NOTFOUND, "No such chain."))
+    }
+}
+
+/// Fails with NOTFOUND unless `win_id` is a live win; trashed wins can't be
+/// linked to anything.
+pub(crate) fn ensure_win(conn: &Connection, win_id: i64) -> Result<()> {
+    let found: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM wins WHERE id = ?1)",
+        [win_id],
+        |row| row.get(0),
+    )?;
+    if found {
+        Ok(())
+    } else {
+        Err(errors::failure(ffi::SQLITE_NOTFOUND, "No such win."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::LoggedZone;
+    use crate::migrations;
+    use chrono::Days;
+
+    fn journal() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        conn
+    }
+
+    fn add(conn: &mut Connection, date: &str, text: &str) -> i64 {
+        db::add_win(conn, date, text, "", db::Ratings::default()).unwrap();
+        conn.query_row("SELECT MAX(id) FROM wins", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn chain_of(conn: &Connection, win_id: i64) -> Option<i64> {
+        conn.query_row(
+            "SELECT chain_id FROM chain_wins WHERE win_id = ?1",
+            [win_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+        .flatten()
+    }
+
+    fn members(conn: &Connection, chain_id: i64) -> Vec<i64> {
+        let mut stmt = conn
+            .prepare("SELECT win_id FROM chain_wins WHERE chain_id = ?1 ORDER BY win_id")
+            .unwrap();
+        let ids = stmt.query_map([chain_id], |row| row.get(0)).unwrap();
+        ids.map(|id| id.unwrap()).collect()
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    const THESIS: &str = "Rewrote the thesis chapter outline";
+
+    #[test]
+    fn wins_link_up_to_the_gap_limit() {
+        let none: &[String] = &[];
+        let corpus = Corpus::build([(THESIS, none), (THESIS, none), (THESIS, none)]);
+        let start = date("2025-01-01");
+        let dates = [start, start + Days::new(14), start + Days::new(29)];
+        let settings = ChainSettings::default();
+        assert_eq!(settings.max_gap_days, 14);
+        // 14 days apart links, 15 doesn't
+        assert_eq!(
+            detect(&dates, &corpus, &settings),
+            vec![vec![0, 1], vec![2]]
+        );
+        let wider = ChainSettings {
+            max_gap_days: 15,
+            ..settings
+        };
+        assert_eq!(detect(&dates, &corpus, &wider), vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn unrelated_wins_stay_apart() {
+        let none: &[String] = &[];
+        let corpus = Corpus::build([(THESIS, none), ("Baked sourdough bread", none)]);
+        let day = date("2025-01-01");
+        assert_eq!(
+            detect(&[day, day], &corpus, &ChainSettings::default()),
+            vec![vec![0], vec![1]]
+        );
+    }
+
+    #[test]
+    fn chain_ids_survive_a_new_win() {
+        let mut conn = journal();
+        let settings = ChainSettings::default();
+        let a = add(&mut conn, "2025-01-01", THESIS);
+        let b = add(&mut conn, "2025-01-05", THESIS);
+        let bread = add(&mut conn, "2025-01-06", "Baked sourdough bread");
+        refresh(&mut conn, &settings).unwrap();
+        let chain = chain_of(&conn, a).expect("a chain");
+        assert_eq!(chain_of(&conn, b), Some(chain));
+        assert_eq!(chain_of(&conn, bread), None);
+
+        let c = add(&mut conn, "2025-01-09", THESIS);
+        refresh(&mut conn, &settings).unwrap();
+        assert_eq!(members(&conn, chain), vec![a, b, c]);
+    }
+
+    #[test]
+    fn chain_ids_survive_a_settings_change() {
+        let mut conn = journal();
+        let narrow = ChainSettings::default();
+        let early: Vec<i64> = ["2025-01-01", "2025-01-02", "2025-01-03"]
+            .iter()
+            .map(|d| add(&mut conn, d, THESIS))
+            .collect();
+        let late: Vec<i64> = ["2025-01-25", "2025-01-26"]
+            .iter()
+            .map(|d| add(&mut conn, d, THESIS))
+            .collect();
+        refresh(&mut conn, &narrow).unwrap();
+        let first = chain_of(&conn, early[0]).unwrap();
+        let second = chain_of(&conn, late[0]).unwrap();
+        assert_ne!(first, second);
+
+        // Widening the gap joins them under the bigger chain's id
+        let wide = ChainSettings {
+            max_gap_days: 30,
+            ..narrow
+        };
+        refresh(&mut conn, &wide).unwrap();
+        assert_eq!(
+            members(&conn, first),
+            [early.clone(), late.clone()].concat()
+        );
+
+        // Narrowing it again keeps that id on the earlier wins
+        refresh(&mut conn, &narrow).unwrap();
+        assert_eq!(members(&conn, first), early);
+        assert_ne!(chain_of(&conn, late[0]), Some(first));
+        assert_eq!(chain_of(&conn, late[0]), chain_of(&conn, late[1]));
+    }
+
+    /// Three same-day wins logged in the order b, c, a.
+    fn same_day_chain(conn: &mut Connection) -> (i64, [i64; 3]) {
+        let ids = [
+            add(conn, "2025-03-01", "Ran the first mile"),
+            add(conn, "2025-03-01", "Ran the second mile"),
+            add(conn, "2025-03-01", "Ran the third mile"),
+        ];
+        for (id, created_at) in ids.iter().zip([300, 100, 200]) {
+            conn.execute(
+                "UPDATE wins SET created_at = ?1 WHERE id = ?2",
+                (created_at, id),
+            )
+            .unwrap();
+        }
+        (new_chain(conn, &ids).unwrap(), ids)
+    }
+
+    #[test]
+    fn split_follows_the_timeline_order() {
+        let mut conn = journal();
+        let settings = ChainSettings::default();
+        let today = date("2025-03-02");
+        let (chain, [a, b, c]) = same_day_chain(&mut conn);
+        let order: Vec<i64> = get_chain_timeline(&mut conn, chain, &settings, today)
+            .unwrap()
+            .wins
+            .iter()
+            .map(|w| w.id)
+            .collect();
+        assert_eq!(order, vec![b, c, a]);
+
+        let later = split_chain(&mut conn, chain, c).unwrap();
+        assert_eq!(members(&conn, chain), vec![b]);
+        assert_eq!(members(&conn, later), vec![a, c]);
+        // Both halves are pinned, so detection leaves them as split
+        refresh(&mut conn, &settings).unwrap();
+        assert_eq!(members(&conn, chain), vec![b]);
+        assert_eq!(members(&conn, later), vec![a, c]);
+    }
+
+    #[test]
+    fn split_needs_a_win_after_the_first() {
+        let mut conn = journal();
+        let (chain, [_, b, _]) = same_day_chain(&mut conn);
+        let err = split_chain(&mut conn, chain, b).unwrap_err();
+        assert!(err.to_string().contains("first win"), "{}", err);
+        let outsider = add(&mut conn, "2025-03-01", "Something else");
+        let err = split_chain(&mut conn, chain, outsider).unwrap_err();
+        assert!(err.to_string().contains("isn't in this chain"), "{}", err);
+        // Nothing changed
+        assert_eq!(members(&conn, chain).len(), 3);
+        let chains: i64 = conn
+            .query_row("SELECT COUNT(*) FROM chains", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(chains, 1);
+    }
+
+    #[test]
+    fn merging_folds_chains_into_the_first() {
+        let mut conn = journal();
+        let ids: Vec<i64> = ["2025-01-01", "2025-01-02", "2025-06-01", "2025-06-02"]
+            .iter()
+            .zip([
+                "Painted the fence",
+                "Fixed the gate",
+                "Wrote a poem",
+                "Read a novel",
+            ])
+            .map(|(d, text)| add(&mut conn, d, text))
+            .collect();
+        let first = new_chain(&mut conn, &ids[..2]).unwrap();
+        let second = new_chain(&mut conn, &ids[2..]).unwrap();
+        assert_eq!(merge_chains(&mut conn, &[first, second]).unwrap(), first);
+        refresh(&mut conn, &ChainSettings::default()).unwrap();
+        assert_eq!(members(&conn, first), ids);
+        assert!(ensure_chain(&conn, second).is_err());
+        assert!(merge_chains(&mut conn, &[]).is_err());
+        assert!(merge_chains(&mut conn, &[first, 999]).is_err());
+    }
+
+    #[test]
+    fn removed_wins_stay_out_of_chains() {
+        let mut conn = journal();
+        let settings = ChainSettings::default();
+        let ids: Vec<i64> = ["2025-01-01", "2025-01-02", "2025-01-03"]
+            .iter()
+            .map(|d| add(&mut conn, d, THESIS))
+            .collect();
+        refresh(&mut conn, &settings).unwrap();
+        let chain = chain_of(&conn, ids[0]).unwrap();
+        remove_from_chain(&conn, ids[1]).unwrap();
+        refresh(&mut conn, &settings).unwrap();
+        assert_eq!(chain_of(&conn, ids[1]), None);
+        assert_eq!(members(&conn, chain), vec![ids[0], ids[2]]);
+        assert!(remove_from_chain(&conn, 999).is_err());
+    }
+
+    fn win(id: i64, date: &str, created_at: i64, tags: &[&str]) -> Win {
+        Win {
+            id,
+            date: date.to_string(),
+            text: format!("Win {}", id),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            created_at,
+            ratings: db::Ratings::default(),
+            zone: LoggedZone::default(),
+        }
+    }
+
+    fn term(term: &str, wins: usize, weight: f64) -> SharedTerm {
+        SharedTerm {
+            term: term.to_string(),
+            wins,
+            weight,
+        }
+    }
+
+    #[test]
+    fn timeline_orders_same_day_wins_by_when_they_were_logged() {
+        let wins = [
+            win(1, "2025-01-01", 300, &["thesis"]),
+            win(4, "2025-01-04", 60, &["thesis", "writing"]),
+            win(2, "2025-01-01", 100, &["thesis"]),
+            win(3, "2025-01-04", 60, &["reading"]),
+        ];
+        let settings = ChainSettings::default();
+        let t = lay_out(7, &wins, Vec::new(), &settings, date("2025-01-10")).unwrap();
+        assert_eq!(t.id, 7);
+        assert_eq!(
+            t.wins.iter().map(|w| w.id).collect::<Vec<_>>(),
+            vec![2, 1, 3, 4]
+        );
+        assert_eq!(
+            (t.start.as_str(), t.end.as_str()),
+            ("2025-01-01", "2025-01-04")
+        );
+        // Two wins a day still make two active days
+        assert_eq!((t.span_days, t.active_days), (4, 2));
+        assert_eq!(
+            t.gaps,
+            vec![ChainGap {
+                after: "2025-01-01".to_string(),
+                before: "2025-01-04".to_string(),
+                days: 2,
+            }]
+        );
+        assert_eq!(
+            t.dominant_tags
+                .iter()
+                .map(|t| (t.tag.as_str(), t.count))
+                .collect::<Vec<_>>(),
+            vec![("thesis", 3), ("reading", 1), ("writing", 1)]
+        );
+        assert!(t.active);
+        let later = lay_out(7, &wins, Vec::new(), &settings, date("2025-01-19")).unwrap();
+        assert!(!later.active);
+    }
+
+    #[test]
+    fn a_single_win_is_a_one_day_chain() {
+        let wins = [win(5, "2025-02-03", 0, &["swim"])];
+        let t = lay_out(
+            1,
+            &wins,
+            Vec::new(),
+            &ChainSettings::default(),
+            date("2025-02-03"),
+        )
+        .unwrap();
+        assert_eq!((t.span_days, t.active_days), (1, 1));
+        assert!(t.gaps.is_empty());
+        assert_eq!(t.start, t.end);
+        assert_eq!(t.title, "Swim");
+        assert!(lay_out(
+            1,
+            &[],
+            Vec::new(),
+            &ChainSettings::default(),
+            date("2025-02-03")
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn titles_come_from_shared_terms_then_tags() {
+        let wins = [
+            win(1, "2025-01-01", 0, &["misc", "running"]),
+            win(2, "2025-01-02", 0, &["misc", "running"]),
+        ];
+        let settings = ChainSettings::default();
+        let today = date("2025-01-02");
+        let terms = vec![
+            term("marathon", 2, 1.2),
+            term("training", 2, 0.9),
+            term("river", 2, 0.4),
+        ];
+        let t = lay_out(1, &wins, terms, &settings, today).unwrap();
+        assert_eq!(t.title, "Marathon / training");
+
+        // Grouped by hand with nothing in common: the most used specific tag
+        let t = lay_out(1, &wins, Vec::new(), &settings, today).unwrap();
+        assert_eq!(t.title, "Running");
+        assert!(t.shared_terms.is_empty());
+        assert!(!t.dominant_tags.iter().any(|t| t.tag == "misc"));
+
+        let untagged = [
+            win(1, "2025-01-01", 0, &["misc"]),
+            win(2, "2025-01-02", 0, &[]),
+        ];
+        let t = lay_out(1, &untagged, Vec::new(), &settings, today).unwrap();
+        assert_eq!(t.title, "Untitled chain");
+    }
+}
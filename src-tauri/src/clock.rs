@@ -0,0 +1,158 @@
+// clock.rs - The user's calendar: today's date and the zone a win is logged in
+
+use chrono::{DateTime, Local, NaiveDate, Offset, TimeZone};
+use serde::{Deserialize, Serialize};
+
+/// Where a win was logged: the UTC offset in minutes and the IANA zone name
+/// when the system reports one. Both are `None` for wins logged before
+/// zones were kept.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct LoggedZone {
+    pub utc_offset: Option<i32>,
+    pub time_zone: Option<String>,
+}
+
+impl LoggedZone {
+    /// The zone the device is in right now.
+    pub fn current() -> LoggedZone {
+        LoggedZone {
+            utc_offset: Some(Local::now().offset().fix().local_minus_utc() / 60),
+            time_zone: iana_time_zone::get_timezone().ok(),
+        }
+    }
+}
+
+/// The calendar day `now` falls on where the user is. A win's date is fixed
+/// when it's logged and never worked out again from `created_at`, so wins keep
+/// their day when the user travels; only "today" moves with them.
+pub fn local_date<Tz: TimeZone>(now: &DateTime<Tz>) -> NaiveDate {
+    now.date_naive()
+}
+
+/// Today in the zone the device is in now. Recaps, streaks, goals and new
+/// wins all start from this.
+pub fn today() -> NaiveDate {
+    local_date(&Local::now())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use chrono::{
+        Datelike, Days, Duration, FixedOffset, MappedLocalTime, NaiveDateTime, Utc, Weekday,
+    };
+
+    /// US Eastern time under the post-2007 rules, standing in for a tz
+    /// database: clocks go forward at 02:00 on the second Sunday of March and
+    /// back at 02:00 on the first Sunday of November.
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct UsEastern;
+
+    const EST: i32 = -5 * 3600;
+    const EDT: i32 = -4 * 3600;
+
+    impl UsEastern {
+        /// The UTC instants daylight time starts and ends in `year`.
+        fn daylight(year: i32) -> (NaiveDateTime, NaiveDateTime) {
+            let sunday = |month, n| {
+                NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, n).unwrap()
+            };
+            (
+                sunday(3, 2).and_hms_opt(7, 0, 0).unwrap(),
+                sunday(11, 1).and_hms_opt(6, 0, 0).unwrap(),
+            )
+        }
+    }
+
+    impl TimeZone for UsEastern {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            UsEastern
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &NaiveDateTime,
+        ) -> MappedLocalTime<FixedOffset> {
+            // An offset fits if the instant it gives maps back to it
+            let fits: Vec<FixedOffset> = [EDT, EST]
+                .into_iter()
+                .map(|secs| FixedOffset::east_opt(secs).unwrap())
+                .filter(|o| {
+                    self.offset_from_utc_datetime(
+                        &(*local - Duration::seconds(o.local_minus_utc() as i64)),
+                    ) == *o
+                })
+                .collect();
+            match fits[..] {
+                [] => MappedLocalTime::None,
+                [one] => MappedLocalTime::Single(one),
+                [earlier, later, ..] => MappedLocalTime::Ambiguous(earlier, later),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let (start, end) = UsEastern::daylight(utc.year());
+            let secs = if *utc >= start && *utc < end {
+                EDT
+            } else {
+                EST
+            };
+            FixedOffset::east_opt(secs).unwrap()
+        }
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn the_stand_in_zone_changes_offset() {
+        let before = UsEastern.with_ymd_and_hms(2025, 3, 9, 1, 59, 0).unwrap();
+        let after = before + Duration::minutes(1);
+        assert_eq!(after.format("%H:%M %z").to_string(), "03:00 -0400");
+        assert!(UsEastern
+            .with_ymd_and_hms(2025, 3, 9, 2, 30, 0)
+            .single()
+            .is_none());
+        let fold = UsEastern.with_ymd_and_hms(2025, 11, 2, 1, 30, 0);
+        assert!(matches!(fold, MappedLocalTime::Ambiguous(_, _)));
+    }
+
+    #[test]
+    fn today_follows_the_local_calendar() {
+        // 23:30 on Jan 31 in New York is already Feb 1 in UTC
+        let late = UsEastern.with_ymd_and_hms(2025, 1, 31, 23, 30, 0).unwrap();
+        assert_eq!(local_date(&late), date("2025-01-31"));
+        assert_eq!(local_date(&late.with_timezone(&Utc)), date("2025-02-01"));
+        // The same instant seen from Tokyo
+        let tokyo = FixedOffset::east_opt(9 * 3600).unwrap();
+        assert_eq!(local_date(&late.with_timezone(&tokyo)), date("2025-02-01"));
+    }
+
+    #[test]
+    fn days_change_at_local_midnight_across_dst() {
+        // Spring forward: Mar 9 is 23 hours long, Nov 2 is 25
+        for (day, hours) in [("2025-03-09", 23), ("2025-11-02", 25)] {
+            let day = date(day);
+            let midnight = UsEastern
+                .from_local_datetime(&day.and_hms_opt(0, 0, 0).unwrap())
+                .unwrap();
+            let next = midnight + Duration::hours(hours);
+            assert_eq!(local_date(&midnight), day);
+            assert_eq!(local_date(&(next - Duration::seconds(1))), day);
+            assert_eq!(local_date(&next), day + Days::new(1));
+            assert_eq!(next.format("%H:%M").to_string(), "00:00");
+        }
+    }
+}
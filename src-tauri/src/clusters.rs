@@ -0,0 +1,337 @@
+// clusters.rs - Community detection (Louvain) over the tag co-occurrence graph
+
+use crate::graph::{self, GraphFilter, TagGraph};
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Tags named in a cluster's label
+const LABEL_TAGS: usize = 2;
+
+#[derive(Serialize, Debug)]
+pub struct Cluster {
+    pub id: usize,
+    /// The cluster's most used tags, e.g. "run / health"
+    pub label: String,
+    /// Most used first
+    pub tags: Vec<String>,
+    /// Sum of the tag counts, a rough measure of how much of the year it covers
+    pub weight: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClusterEdge {
+    pub source: usize,
+    pub target: usize,
+    /// Co-occurrences between tags of the two clusters
+    pub weight: usize,
+}
+
+#[derive(Serialize)]
+pub struct TagClusters {
+    /// The filtered tag graph, with `cluster` set on every node
+    pub graph: TagGraph,
+    /// Largest first; ids are positions in this list
+    pub clusters: Vec<Cluster>,
+    /// Summary graph between clusters
+    pub links: Vec<ClusterEdge>,
+    /// Newman modularity of the partition, -0.5..1; higher is more clear-cut
+    pub modularity: f64,
+}
+
+pub fn get_tag_clusters(conn: &Connection, filter: &GraphFilter) -> Result<TagClusters> {
+    Ok(cluster(graph::get_tag_graph(conn, filter)?))
+}
+
+/// Partitions `graph` and builds the cluster summary. Deterministic: the
+/// same graph always gives the same clusters and ids.
+pub(crate) fn cluster(mut graph: TagGraph) -> TagClusters {
+    let index: HashMap<&str, usize> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (n.tag.as_str(), i))
+        .collect();
+    let edges: Vec<(usize, usize, f64)> = graph
+        .edges
+        .iter()
+        .map(|e| {
+            (
+                index[e.source.as_str()],
+                index[e.target.as_str()],
+                e.weight as f64,
+            )
+        })
+        .collect();
+    let (membership, modularity) = louvain(graph.nodes.len(), &edges);
+
+    // Renumber clusters by weight so ids are stable and meaningful
+    let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (node, community) in membership.iter().enumerate() {
+        members.entry(*community).or_default().push(node);
+    }
+    let mut groups: Vec<Vec<usize>> = members.into_values().collect();
+    let weight_of = |g: &Vec<usize>| g.iter().map(|&i| graph.nodes[i].count).sum::<usize>();
+    groups.sort_by(|a, b| {
+        weight_of(b)
+            .cmp(&weight_of(a))
+            .then_with(|| a[0].cmp(&b[0]))
+    });
+    let mut cluster_of = vec![0; graph.nodes.len()];
+    let clusters: Vec<Cluster> = groups
+        .iter()
+        .enumerate()
+        .map(|(id, group)| {
+            for &i in group {
+                cluster_of[i] = id;
+            }
+            // Nodes are already sorted most used first
+            let tags: Vec<String> = group.iter().map(|&i| graph.nodes[i].tag.clone()).collect();
+            Cluster {
+                id,
+                label: tags
+                    .iter()
+                    .take(LABEL_TAGS)
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(" / "),
+                weight: weight_of(group),
+                tags,
+            }
+        })
+        .collect();
+
+    let mut between: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    for e in &graph.edges {
+        let (a, b) = (
+            cluster_of[index[e.source.as_str()]],
+            cluster_of[index[e.target.as_str()]],
+        );
+        if a != b {
+            *between.entry((a.min(b), a.max(b))).or_insert(0) += e.weight;
+        }
+    }
+    let links = between
+        .into_iter()
+        .map(|((source, target), weight)| ClusterEdge {
+            source,
+            target,
+            weight,
+        })
+        .collect();
+    for (node, cluster) in graph.nodes.iter_mut().zip(&cluster_of) {
+        node.cluster = Some(*cluster);
+    }
+    TagClusters {
+        graph,
+        clusters,
+        links,
+        modularity,
+    }
+}
+
+/// Louvain method: move nodes between communities while modularity improves,
+/// then collapse each community into a node and repeat. Returns each node's
+/// community (arbitrary ids) and the final modularity.
+fn louvain(n: usize, edges: &[(usize, usize, f64)]) -> (Vec<usize>, f64) {
+    // Self loops hold the weight inside collapsed communities
+    let mut adj = adjacency(n, edges);
+    let mut membership: Vec<usize> = (0..n).collect();
+    loop {
+        let level = move_nodes(&adj);
+        let count = level.iter().max().map_or(0, |c| c + 1);
+        if count == adj.len() {
+            break;
+        }
+        for m in membership.iter_mut() {
+            *m = level[*m];
+        }
+        let mut collapsed: Vec<BTreeMap<usize, f64>> = vec![BTreeMap::new(); count];
+        for (i, row) in adj.iter().enumerate() {
+            for (&j, &w) in row {
+                *collapsed[level[i]].entry(level[j]).or_insert(0.0) += w;
+            }
+        }
+        adj = collapsed;
+    }
+    let q = modularity(&adjacency(n, edges), &membership);
+    (membership, q)
+}
+
+/// One level of local moving. Returns communities numbered 0.. in order of
+/// first appearance.
+fn move_nodes(adj: &[BTreeMap<usize, f64>]) -> Vec<usize> {
+    let n = adj.len();
+    let degree: Vec<f64> = adj.iter().map(|row| row.values().sum()).collect();
+    let total: f64 = degree.iter().sum();
+    let mut community: Vec<usize> = (0..n).collect();
+    if total == 0.0 {
+        return community;
+    }
+    let mut tot = degree.clone();
+    let mut moved = true;
+    while moved {
+        moved = false;
+        for i in 0..n {
+            let own = community[i];
+            tot[own] -= degree[i];
+            let mut links: BTreeMap<usize, f64> = BTreeMap::new();
+            for (&j, &w) in &adj[i] {
+                if j != i {
+                    *links.entry(community[j]).or_insert(0.0) += w;
+                }
+            }
+            let gain = |c: usize, k_in: f64| k_in - tot[c] * degree[i] / total;
+            let mut best = own;
+            let mut best_gain = gain(own, links.get(&own).copied().unwrap_or(0.0));
+            for (&c, &k_in) in &links {
+                let g = gain(c, k_in);
+                if g > best_gain + 1e-12 {
+                    best = c;
+                    best_gain = g;
+                }
+            }
+            tot[best] += degree[i];
+            if best != own {
+                community[i] = best;
+                moved = true;
+            }
+        }
+    }
+    let mut renumber: HashMap<usize, usize> = HashMap::new();
+    community
+        .iter()
+        .map(|c| {
+            let next = renumber.len();
+            *renumber.entry(*c).or_insert(next)
+        })
+        .collect()
+}
+
+/// Symmetric weighted adjacency lists.
+fn adjacency(n: usize, edges: &[(usize, usize, f64)]) -> Vec<BTreeMap<usize, f64>> {
+    let mut adj = vec![BTreeMap::new(); n];
+    for &(a, b, w) in edges {
+        *adj[a].entry(b).or_insert(0.0) += w;
+        *adj[b].entry(a).or_insert(0.0) += w;
+    }
+    adj
+}
+
+fn modularity(adj: &[BTreeMap<usize, f64>], membership: &[usize]) -> f64 {
+    let total: f64 = adj.iter().flat_map(|row| row.values()).sum();
+    if total == 0.0 {
+        return 0.0;
+    }
+    let mut inside: HashMap<usize, f64> = HashMap::new();
+    let mut degree: HashMap<usize, f64> = HashMap::new();
+    for (i, row) in adj.iter().enumerate() {
+        for (&j, &w) in row {
+            *degree.entry(membership[i]).or_insert(0.0) += w;
+            if membership[i] == membership[j] {
+                *inside.entry(membership[i]).or_insert(0.0) += w;
+            }
+        }
+    }
+    degree
+        .iter()
+        .map(|(c, d)| inside.get(c).copied().unwrap_or(0.0) / total - (d / total).powi(2))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::Taxonomy;
+
+    /// Two triangles of tags that turn up together, joined by one win.
+    fn two_cliques() -> Vec<Vec<String>> {
+        let mut wins: Vec<&[&str]> = Vec::new();
+        for _ in 0..3 {
+            wins.push(&["run", "gym", "health"]);
+            wins.push(&["code", "review", "ship"]);
+        }
+        wins.push(&["run", "gym"]);
+        wins.push(&["health", "code"]);
+        wins.iter()
+            .map(|tags| tags.iter().map(|t| t.to_string()).collect())
+            .collect()
+    }
+
+    fn clusters_of(wins: &[Vec<String>]) -> TagClusters {
+        cluster(graph::build(
+            wins,
+            &GraphFilter::default(),
+            &Taxonomy::default(),
+        ))
+    }
+
+    fn sorted(tags: &[String]) -> Vec<&str> {
+        let mut tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+        tags.sort_unstable();
+        tags
+    }
+
+    #[test]
+    fn two_cliques_make_two_clusters() {
+        let result = clusters_of(&two_cliques());
+        assert_eq!(result.clusters.len(), 2);
+        // The heavier clique comes first
+        assert_eq!(
+            sorted(&result.clusters[0].tags),
+            vec!["gym", "health", "run"]
+        );
+        assert_eq!(
+            sorted(&result.clusters[1].tags),
+            vec!["code", "review", "ship"]
+        );
+        assert_eq!(
+            (result.clusters[0].weight, result.clusters[1].weight),
+            (12, 10)
+        );
+        // Equal counts, so the label goes alphabetical
+        assert_eq!(result.clusters[0].label, "gym / health");
+        assert!(result.modularity > 0.0, "{}", result.modularity);
+
+        // The bridging win is the only link between them
+        assert_eq!(result.links.len(), 1);
+        assert_eq!(
+            (
+                result.links[0].source,
+                result.links[0].target,
+                result.links[0].weight
+            ),
+            (0, 1, 1)
+        );
+        for node in &result.graph.nodes {
+            let expected = usize::from(["code", "review", "ship"].contains(&node.tag.as_str()));
+            assert_eq!(node.cluster, Some(expected), "{}", node.tag);
+        }
+    }
+
+    #[test]
+    fn ids_are_stable_across_runs() {
+        let summary = |result: &TagClusters| -> Vec<(usize, String, Vec<String>)> {
+            result
+                .clusters
+                .iter()
+                .map(|c| (c.id, c.label.clone(), c.tags.clone()))
+                .collect()
+        };
+        let wins = two_cliques();
+        let first = summary(&clusters_of(&wins));
+        assert_eq!(summary(&clusters_of(&wins)), first);
+        // Nor does the order the wins come in matter
+        let reversed: Vec<Vec<String>> = wins.into_iter().rev().collect();
+        assert_eq!(summary(&clusters_of(&reversed)), first);
+    }
+
+    #[test]
+    fn tags_without_edges_are_their_own_clusters() {
+        let wins = vec![vec!["alone".to_string()], vec!["apart".to_string()]];
+        let result = clusters_of(&wins);
+        assert_eq!(result.clusters.len(), 2);
+        assert!(result.links.is_empty());
+        assert_eq!(result.modularity, 0.0);
+    }
+}
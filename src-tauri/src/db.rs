@@ -1,102 +1,22 @@
-#[derive(Serialize, Clone)]
-pub struct WinWithChain {
-    pub id: i64,
-    pub date: String,
-    pub text: String,
-    pub tags: String,
-    pub created_at: i64,
-    pub chain_id: usize,
-}
-
-/// Simple win chain detection: group wins by shared entity/keyword and sequence
-pub fn get_wins_with_chains(app_handle: &tauri::AppHandle) -> Result<Vec<WinWithChain>> {
-    let wins = get_wins(app_handle)?;
-    // Extract chain keys (e.g., main entity or project keyword)
-    let mut chains: Vec<Vec<Win>> = Vec::new();
-    let mut assigned = vec![false; wins.len()];
-    for (i, win) in wins.iter().enumerate() {
-        if assigned[i] {
-            continue;
-        }
-        let mut chain: Vec<Win> = vec![win.clone()];
-        assigned[i] = true;
-        let key = extract_chain_key(&win.text, &win.tags);
-        if key.is_empty() {
-            continue;
-        }
-        // Group other wins with same key (and within 30 days)
-        for (j, other) in wins.iter().enumerate() {
-            if i == j || assigned[j] {
-                continue;
-            }
-            let other_key = extract_chain_key(&other.text, &other.tags);
-            if !other_key.is_empty() && other_key == key {
-                // Optionally, check date proximity
-                chain.push(other.clone());
-                assigned[j] = true;
-            }
-        }
-        chains.push(chain);
-    }
-    // Assign chain IDs
-    let mut win_with_chain = Vec::new();
-    for (chain_id, chain) in chains.iter().enumerate() {
-        for win in chain {
-            win_with_chain.push(WinWithChain {
-                id: win.id,
-                date: win.date.clone(),
-                text: win.text.clone(),
-                tags: win.tags.clone(),
-                created_at: win.created_at,
-                chain_id,
-            });
-        }
-    }
-    // Add unchained wins (chain_id = usize::MAX)
-    for (i, win) in wins.iter().enumerate() {
-        if !assigned[i] {
-            win_with_chain.push(WinWithChain {
-                id: win.id,
-                date: win.date.clone(),
-                text: win.text.clone(),
-                tags: win.tags.clone(),
-                created_at: win.created_at,
-                chain_id: usize::MAX,
-            });
-        }
-    }
-    Ok(win_with_chain)
-}
-
-/// Extract a chain key from text/tags (e.g., main entity, project, or noun)
-fn extract_chain_key(text: &str, tags: &str) -> String {
-    // Simple: use first WORK_OF_ART/PRODUCT/ORG tag, or longest word > 5 chars
-    let tag_candidates: Vec<&str> = tags.split(',').map(|t| t.trim()).collect();
-    for t in &tag_candidates {
-        if t == &"work_of_art" || t == &"product" || t == &"org" {
-            return t.to_string();
-        }
-    }
-    // Fallback: use longest word in text
-    let mut longest = "";
-    for word in text.split_whitespace() {
-        if word.len() > longest.len() && word.len() > 5 {
-            longest = word;
-        }
-    }
-    longest.to_string()
-}
 // use super::mock_data;
+use crate::clock::{self, LoggedZone};
+use crate::encryption;
+use crate::errors;
+use crate::history;
+use crate::migrations;
 use crate::nlp;
+use crate::recovery;
+use crate::tags;
 use rusqlite::{Connection, Result};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
 use tauri::Manager;
 use time::OffsetDateTime;
 
-/// Inserts mock wins for development/testing
+// Inserts mock wins for development/testing
 /*
 pub fn insert_mock_data(app_handle: &tauri::AppHandle) -> Result<()> {
     let db_path = get_db_path(app_handle);
@@ -123,106 +43,151 @@ pub fn insert_mock_data(app_handle: &tauri::AppHandle) -> Result<()> {
 }
 */
 
-#[derive(Serialize)]
-pub struct TagGraph {
-    pub nodes: Vec<String>,
-    pub edges: Vec<(String, String)>,
+pub fn get_db_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    app_handle
+        .path()
+        .app_data_dir()
+        .expect("Failed to get app data dir")
+        .join("quietwins.sqlite")
 }
 
-pub fn get_tag_graph(app_handle: &tauri::AppHandle) -> Result<TagGraph> {
-    use std::collections::HashSet;
-    let conn = init_db(app_handle)?;
-    let mut stmt = conn.prepare("SELECT tags FROM wins")?;
-    let tag_rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
-    let mut tag_sets: Vec<HashSet<String>> = Vec::new();
-    let mut all_tags = HashSet::new();
-    for tag_str in tag_rows.flatten() {
-        let cleaned = tag_str.trim_matches(|c| c == '[' || c == ']' || c == '"');
-        let tags: HashSet<String> = cleaned
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
-        if !tags.is_empty() {
-            all_tags.extend(tags.iter().cloned());
-            tag_sets.push(tags);
+/// The app's single SQLite connection, opened once in `run()` and held in
+/// Tauri managed state. Commands, the cleanup thread and the notification
+/// scheduler all go through `with`, which serializes access. An encrypted
+/// database starts out locked (no connection) until `unlock` succeeds.
+pub struct Db {
+    conn: Mutex<Option<Connection>>,
+    path: PathBuf,
+    /// Passphrase of an encrypted database, kept so backups can be keyed
+    key: Mutex<Option<String>>,
+    /// Set when startup found a corrupt file and salvaged what it could
+    recovery: Mutex<Option<recovery::RecoveryReport>>,
+}
+
+impl Db {
+    pub fn open(app_handle: &tauri::AppHandle) -> Result<Db> {
+        Db::open_at(get_db_path(app_handle))
+    }
+
+    /// Opens the database file at `path`; `open` resolves it from the app's
+    /// data directory.
+    pub(crate) fn open_at(path: PathBuf) -> Result<Db> {
+        let (conn, report) = if encryption::is_encrypted(&path) {
+            println!("[Db::open] Database is encrypted, waiting for passphrase");
+            (None, None)
+        } else {
+            let (conn, report) = init_db(&path)?;
+            (Some(conn), report)
+        };
+        Ok(Db {
+            conn: Mutex::new(conn),
+            path,
+            key: Mutex::new(None),
+            recovery: Mutex::new(report),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.slot().is_none()
+    }
+
+    /// Opens the encrypted database with `passphrase`. A wrong passphrase
+    /// leaves the database locked and the file untouched.
+    pub fn unlock(&self, passphrase: &str) -> Result<()> {
+        let mut slot = self.slot();
+        if slot.is_none() {
+            let conn = encryption::open(&self.path, Some(passphrase))?;
+            *slot = Some(prepare_connection(conn)?);
+            self.set_key(Some(passphrase));
+            println!("[Db::unlock] Database unlocked");
         }
+        Ok(())
     }
-    // Build edges: for each win, connect all pairs of tags in that win
-    let mut edge_set = HashSet::new();
-    for tags in &tag_sets {
-        let tags_vec: Vec<&String> = tags.iter().collect();
-        for i in 0..tags_vec.len() {
-            for j in (i + 1)..tags_vec.len() {
-                let a = tags_vec[i].clone();
-                let b = tags_vec[j].clone();
-                // Store edges in sorted order to avoid duplicates
-                if a < b {
-                    edge_set.insert((a.clone(), b.clone()));
-                } else {
-                    edge_set.insert((b.clone(), a.clone()));
-                }
-            }
+
+    pub fn with<T>(&self, f: impl FnOnce(&mut Connection) -> Result<T>) -> Result<T> {
+        match self.slot().as_mut() {
+            Some(conn) => f(conn),
+            None => Err(encryption::locked_error()),
         }
     }
-    Ok(TagGraph {
-        nodes: all_tags.into_iter().collect(),
-        edges: edge_set.into_iter().collect(),
-    })
+
+    pub fn recovery_report(&self) -> Option<recovery::RecoveryReport> {
+        self.recovery
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn dismiss_recovery_report(&self) {
+        *self.recovery.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    pub(crate) fn key(&self) -> Option<String> {
+        self.key.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn set_key(&self, key: Option<&str>) {
+        *self.key.lock().unwrap_or_else(|e| e.into_inner()) = key.map(str::to_string);
+    }
+
+    /// Direct access to the connection slot, for callers that need to close
+    /// and reopen the database file (re-keying).
+    pub(crate) fn slot(&self) -> MutexGuard<'_, Option<Connection>> {
+        // A panic in another caller doesn't invalidate the connection itself
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
 }
 
-pub fn get_db_path(app_handle: &tauri::AppHandle) -> PathBuf {
-    app_handle
-        .path()
-        .app_data_dir()
-        .expect("Failed to get app data dir")
-        .join("quietwins.sqlite")
+/// Applies the per-connection settings, brings the schema up to date and
+/// seeds the welcome win on first run.
+pub(crate) fn prepare_connection(conn: Connection) -> Result<Connection> {
+    let conn = configure_connection(conn)?;
+    // Seed with a default win if table is empty (first run)
+    let _ = seed_default_win(&conn);
+    Ok(conn)
 }
 
-pub fn init_db(app_handle: &tauri::AppHandle) -> Result<Connection> {
-    let db_path = get_db_path(app_handle);
+fn configure_connection(mut conn: Connection) -> Result<Connection> {
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    conn.busy_timeout(std::time::Duration::from_secs(5))?;
+    migrations::run_migrations(&mut conn)?;
+    Ok(conn)
+}
+
+fn init_db(db_path: &Path) -> Result<(Connection, Option<recovery::RecoveryReport>)> {
+    println!("[init_db] Opening {}", db_path.display());
     if let Some(parent) = db_path.parent() {
         std::fs::create_dir_all(parent).ok();
     }
-    let mut conn = match Connection::open(&db_path) {
+    let mut corrupt_copy = None;
+    let mut conn = match Connection::open(db_path) {
         Ok(c) => c,
         Err(e) => {
             println!("[init_db] Open error: {}. Attempting repair.", e);
-            backup_corrupt_db(&db_path);
-            Connection::open(&db_path)?
+            corrupt_copy = backup_corrupt_db(db_path);
+            Connection::open(db_path)?
         }
     };
 
-    if let Err(e) = ensure_db_integrity(&db_path, &conn) {
-        println!("[init_db] Integrity check failed: {}. Recreating DB.", e);
-        backup_corrupt_db(&db_path);
-        conn = Connection::open(&db_path)?;
-    }
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS wins (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            date TEXT NOT NULL,
-            text TEXT NOT NULL,
-            tags TEXT,
-            created_at INTEGER NOT NULL
-        )",
-        [],
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS deleted_wins (
-            id INTEGER PRIMARY KEY,
-            date TEXT NOT NULL,
-            text TEXT NOT NULL,
-            tags TEXT,
-            created_at INTEGER NOT NULL,
-            deleted_at INTEGER NOT NULL
-        )",
-        [],
-    )?;
-    // Seed with a default win if table is empty (first run)
+    if corrupt_copy.is_none() {
+        if let Err(e) = ensure_db_integrity(&conn) {
+            println!("[init_db] Integrity check failed: {}. Recreating DB.", e);
+            drop(conn);
+            corrupt_copy = backup_corrupt_db(db_path);
+            conn = Connection::open(db_path)?;
+        }
+    }
+
+    let mut conn = configure_connection(conn)?;
+    // Salvage before seeding so recovered wins keep their original ids
+    let report = corrupt_copy.map(|copy| recovery::salvage(&copy, &mut conn));
     let _ = seed_default_win(&conn);
-    Ok(conn)
+    Ok((conn, report))
 }
 
 fn seed_default_win(conn: &Connection) -> Result<()> {
@@ -232,37 +197,46 @@ fn seed_default_win(conn: &Connection) -> Result<()> {
     if count == 0 {
         println!("[seed_default_win] DB is empty, inserting default welcome win");
         let now = OffsetDateTime::now_utc().unix_timestamp();
-        let today = OffsetDateTime::now_utc()
-            .format(&time::format_description::parse("[year]-[month]-[day]").unwrap())
-            .unwrap_or_else(|_| "2025-01-01".to_string());
+        let today = clock::today().format("%Y-%m-%d").to_string();
+        let zone = LoggedZone::current();
         conn.execute(
-            "INSERT INTO wins (date, text, tags, created_at) VALUES (?1, ?2, ?3, ?4)",
+            "INSERT INTO wins (date, text, created_at, utc_offset, time_zone)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
             (
                 today,
                 "Welcome to Quiet Wins! Log your first win here.",
-                "welcome,start",
                 now,
+                zone.utc_offset,
+                zone.time_zone,
             ),
         )?;
+        set_win_tags(
+            conn,
+            conn.last_insert_rowid(),
+            &["welcome".to_string(), "start".to_string()],
+        )?;
         println!("[seed_default_win] Default win inserted");
     }
     Ok(())
 }
 
-fn ensure_db_integrity(db_path: &PathBuf, conn: &Connection) -> Result<()> {
-    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+fn ensure_db_integrity(conn: &Connection) -> Result<()> {
+    // Runs once at startup; quick_check skips the index cross-checks that make
+    // a full integrity_check slow on large journals
+    let mut stmt = conn.prepare("PRAGMA quick_check")?;
     let status: String = stmt.query_row([], |row| row.get(0))?;
     if status.to_lowercase() != "ok" {
-        println!("[init_db] integrity_check returned '{}'.", status);
-        backup_corrupt_db(db_path);
+        println!("[init_db] quick_check returned '{}'.", status);
         return Err(rusqlite::Error::InvalidQuery);
     }
     Ok(())
 }
 
-fn backup_corrupt_db(db_path: &PathBuf) {
+/// Moves the corrupt file (and its WAL, which may hold the newest rows) aside
+/// and returns where it went.
+fn backup_corrupt_db(db_path: &Path) -> Option<PathBuf> {
     if !db_path.exists() {
-        return;
+        return None;
     }
     let ts = OffsetDateTime::now_utc().unix_timestamp();
     let stem = db_path
@@ -273,33 +247,66 @@ fn backup_corrupt_db(db_path: &PathBuf) {
     let backup_path = db_path.with_file_name(backup_name);
     if let Err(e) = fs::rename(db_path, &backup_path) {
         println!("[init_db] Failed to back up corrupt DB: {}", e);
-    } else {
-        println!(
-            "[init_db] Corrupt DB backed up to {}",
-            backup_path.display()
-        );
+        return None;
     }
+    for suffix in ["-wal", "-shm"] {
+        let sidecar = encryption::sibling(db_path, suffix);
+        if sidecar.exists() {
+            let _ = fs::rename(&sidecar, encryption::sibling(&backup_path, suffix));
+        }
+    }
+    println!(
+        "[init_db] Corrupt DB backed up to {}",
+        backup_path.display()
+    );
+    Some(backup_path)
 }
 
-pub fn add_win(app_handle: &tauri::AppHandle, date: &str, text: &str, tags: &str) -> Result<()> {
-    let db_path = get_db_path(app_handle);
-    println!("[add_win] Using DB path: {}", db_path.display());
-    let conn = init_db(app_handle)?;
+pub fn add_win(
+    conn: &mut Connection,
+    date: &str,
+    text: &str,
+    tags: &str,
+    ratings: Ratings,
+) -> Result<()> {
+    ratings.validate()?;
     let now = OffsetDateTime::now_utc().unix_timestamp();
-    let inferred_tags = infer_tags(text);
-    let all_tags = if tags.trim().is_empty() {
-        inferred_tags
-    } else {
-        format!("[{}]", [tags, &inferred_tags].join(", "))
-    };
+    let zone = LoggedZone::current();
+    let mut all_tags = parse_tag_string(tags);
+    for tag in infer_tags(text) {
+        if !all_tags.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+            all_tags.push(tag);
+        }
+    }
     println!(
         "[add_win] Inserting win: {} | {} | {}",
-        date, text, all_tags
-    );
-    let res = conn.execute(
-        "INSERT INTO wins (date, text, tags, created_at) VALUES (?1, ?2, ?3, ?4)",
-        (date, text, all_tags, now),
+        date,
+        text,
+        all_tags.join(", ")
     );
+    let tx = conn.transaction()?;
+    let res = tx
+        .execute(
+            "INSERT INTO wins (date, text, created_at, mood, energy, impact, utc_offset, time_zone)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                date,
+                text,
+                now,
+                ratings.mood,
+                ratings.energy,
+                ratings.impact,
+                zone.utc_offset,
+                zone.time_zone,
+            ),
+        )
+        .and_then(|_| {
+            let id = tx.last_insert_rowid();
+            set_win_tags(&tx, id, &all_tags)?;
+            let after = history::snapshot(&tx, "wins", id)?;
+            history::record(&tx, id, "create", None, after.as_ref())
+        })
+        .and_then(|_| tx.commit());
     match res {
         Ok(_) => {
             println!("[add_win] Insert success");
@@ -313,19 +320,34 @@ pub fn add_win(app_handle: &tauri::AppHandle, date: &str, text: &str, tags: &str
 }
 
 pub fn update_win(
-    app_handle: &tauri::AppHandle,
+    conn: &mut Connection,
     id: i64,
     date: &str,
     text: &str,
     tags: &str,
+    ratings: Ratings,
 ) -> Result<()> {
-    let db_path = get_db_path(app_handle);
-    println!("[update_win] Using DB path: {}", db_path.display());
-    let conn = init_db(app_handle)?;
-    let res = conn.execute(
-        "UPDATE wins SET date = ?1, text = ?2, tags = ?3 WHERE id = ?4",
-        (date, text, tags, id),
-    );
+    ratings.validate()?;
+    let tx = conn.transaction()?;
+    let before = history::snapshot(&tx, "wins", id)?;
+    let res = tx
+        .execute(
+            "UPDATE wins SET date = ?1, text = ?2, mood = ?3, energy = ?4, impact = ?5
+             WHERE id = ?6",
+            (date, text, ratings.mood, ratings.energy, ratings.impact, id),
+        )
+        .and_then(|updated| {
+            // Trashed and unknown ids keep their tags untouched
+            if updated == 0 {
+                return Err(rusqlite::Error::QueryReturnedNoRows);
+            }
+            set_win_tags(&tx, id, &parse_tag_string(tags))
+        })
+        .and_then(|_| {
+            let after = history::snapshot(&tx, "wins", id)?;
+            history::record(&tx, id, "edit", before.as_ref(), after.as_ref())
+        })
+        .and_then(|_| tx.commit());
     match res {
         Ok(_) => {
             println!("[update_win] Update success for id {}", id);
@@ -338,65 +360,65 @@ pub fn update_win(
     }
 }
 
-pub fn delete_win(app_handle: &tauri::AppHandle, id: i64) -> Result<()> {
+pub fn delete_win(conn: &mut Connection, id: i64) -> Result<()> {
     println!("[delete_win] Soft delete for id {}", id);
-    let conn = init_db(app_handle)?;
+    let tx = conn.transaction()?;
+    move_to_trash(&tx, id)?;
+    tx.commit()
+}
+
+/// The body of `delete_win`, for callers that already hold a transaction.
+pub(crate) fn move_to_trash(tx: &Connection, id: i64) -> Result<()> {
     let now = OffsetDateTime::now_utc().unix_timestamp();
-    // Move to deleted_wins instead of hard delete
-    let mut stmt = conn.prepare("SELECT date, text, tags, created_at FROM wins WHERE id = ?1")?;
-    let win = stmt.query_row([id], |row| {
-        Ok((
-            row.get::<_, String>(0)?,
-            row.get::<_, String>(1)?,
-            row.get::<_, String>(2)?,
-            row.get::<_, i64>(3)?,
-        ))
-    })?;
-    let (date, text, tags, created_at) = win;
-    conn.execute(
-        "INSERT INTO deleted_wins (id, date, text, tags, created_at, deleted_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        (id, date, text, tags, created_at, now),
+    let before = history::snapshot(tx, "wins", id)?;
+    // Move to deleted_wins instead of hard delete; win_tags rows stay keyed by id
+    let moved = tx.execute(
+        &format!(
+            "INSERT INTO deleted_wins ({cols}, deleted_at) SELECT {cols}, ?2 FROM wins WHERE id = ?1",
+            cols = WIN_FIELDS
+        ),
+        (id, now),
     )?;
-    conn.execute("DELETE FROM wins WHERE id = ?1", [id])?;
-    Ok(())
+    if moved == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    tx.execute("DELETE FROM wins WHERE id = ?1", [id])?;
+    let after = history::snapshot(tx, "deleted_wins", id)?;
+    history::record(tx, id, "delete", before.as_ref(), after.as_ref())
 }
 
-pub fn restore_win(app_handle: &tauri::AppHandle, id: i64) -> Result<()> {
+pub fn restore_win(conn: &mut Connection, id: i64) -> Result<()> {
     println!("[restore_win] Restoring win id {}", id);
-    let conn = init_db(app_handle)?;
+    let tx = conn.transaction()?;
+    move_from_trash(&tx, id)?;
+    tx.commit()
+}
+
+/// The body of `restore_win`, for callers that already hold a transaction.
+pub(crate) fn move_from_trash(tx: &Connection, id: i64) -> Result<()> {
+    let before = history::snapshot(tx, "deleted_wins", id)?;
     // Move back from deleted_wins to wins
-    let mut stmt =
-        conn.prepare("SELECT date, text, tags, created_at FROM deleted_wins WHERE id = ?1")?;
-    let win = stmt.query_row([id], |row| {
-        Ok((
-            row.get::<_, String>(0)?,
-            row.get::<_, String>(1)?,
-            row.get::<_, String>(2)?,
-            row.get::<_, i64>(3)?,
-        ))
-    })?;
-    let (date, text, tags, created_at) = win;
-    conn.execute(
-        "INSERT OR REPLACE INTO wins (id, date, text, tags, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-        (id, date, text, tags, created_at),
+    let moved = tx.execute(
+        &format!(
+            "INSERT OR REPLACE INTO wins ({cols}) SELECT {cols} FROM deleted_wins WHERE id = ?1",
+            cols = WIN_FIELDS
+        ),
+        [id],
     )?;
-    conn.execute("DELETE FROM deleted_wins WHERE id = ?1", [id])?;
-    Ok(())
+    if moved == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    tx.execute("DELETE FROM deleted_wins WHERE id = ?1", [id])?;
+    let after = history::snapshot(tx, "wins", id)?;
+    history::record(tx, id, "restore", before.as_ref(), after.as_ref())
 }
 
-pub fn get_deleted_wins(app_handle: &tauri::AppHandle) -> Result<Vec<Win>> {
-    let conn = init_db(app_handle)?;
-    let mut stmt =
-        conn.prepare("SELECT id, date, text, tags, created_at FROM deleted_wins ORDER BY id DESC")?;
-    let wins = stmt.query_map([], |row| {
-        Ok(Win {
-            id: row.get(0)?,
-            date: row.get(1)?,
-            text: row.get(2)?,
-            tags: row.get(3)?,
-            created_at: row.get(4)?,
-        })
-    })?;
+pub fn get_deleted_wins(conn: &Connection) -> Result<Vec<Win>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM deleted_wins w ORDER BY w.id DESC",
+        win_columns()
+    ))?;
+    let wins = stmt.query_map([], win_from_row)?;
     let mut result = Vec::new();
     for win in wins {
         result.push(win?);
@@ -404,16 +426,7 @@ pub fn get_deleted_wins(app_handle: &tauri::AppHandle) -> Result<Vec<Win>> {
     Ok(result)
 }
 
-pub fn cleanup_old_deletions(app_handle: &tauri::AppHandle, retention_hours: i64) -> Result<()> {
-    let conn = init_db(app_handle)?;
-    let now = OffsetDateTime::now_utc().unix_timestamp();
-    let cutoff = now - (retention_hours * 3600);
-    let res = conn.execute("DELETE FROM deleted_wins WHERE deleted_at < ?1", [cutoff])?;
-    println!("[cleanup_old_deletions] Deleted {} old entries", res);
-    Ok(())
-}
-
-fn infer_tags(text: &str) -> String {
+pub(crate) fn infer_tags(text: &str) -> Vec<String> {
     // Use rule-based tag suggestion first
     let dict = [
         (
@@ -506,46 +519,147 @@ fn infer_tags(text: &str) -> String {
     if tags.is_empty() {
         tags.insert("misc".to_string());
     }
-    tags.into_iter().collect::<Vec<_>>().join(", ")
+    let mut tags: Vec<String> = tags.into_iter().collect();
+    tags.sort();
+    tags
 }
 
 #[derive(Serialize, Clone)]
 pub struct Win {
     pub id: i64,
+    /// The local calendar day it was logged for
     pub date: String,
     pub text: String,
-    pub tags: String,
+    pub tags: Vec<String>,
     pub created_at: i64,
+    #[serde(flatten)]
+    pub ratings: Ratings,
+    #[serde(flatten)]
+    pub zone: LoggedZone,
 }
 
-pub fn get_wins(app_handle: &tauri::AppHandle) -> Result<Vec<Win>> {
-    let db_path = get_db_path(app_handle);
-    println!("[get_wins] Using DB path: {}", db_path.display());
-    let conn = match init_db(app_handle) {
-        Ok(c) => c,
-        Err(e) => {
-            println!("[get_wins] DB init error: {}", e);
-            return Err(e);
+/// Optional self-assessment of a win, each on a 1–5 scale.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct Ratings {
+    pub mood: Option<i64>,
+    pub energy: Option<i64>,
+    pub impact: Option<i64>,
+}
+
+pub const RATING_MIN: i64 = 1;
+pub const RATING_MAX: i64 = 5;
+
+impl Ratings {
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("Mood", self.mood),
+            ("Energy", self.energy),
+            ("Impact", self.impact),
+        ] {
+            if value.is_some_and(|v| !(RATING_MIN..=RATING_MAX).contains(&v)) {
+                return Err(errors::failure(
+                    rusqlite::ffi::SQLITE_CONSTRAINT,
+                    &format!(
+                        "{} must be between {} and {}.",
+                        name, RATING_MIN, RATING_MAX
+                    ),
+                ));
+            }
         }
-    };
-    let mut stmt = match conn
-        .prepare("SELECT id, date, text, tags, created_at FROM wins ORDER BY created_at DESC")
-    {
+        Ok(())
+    }
+}
+
+/// Stored columns shared by `wins` and `deleted_wins`, for copying a row
+/// between them.
+pub(crate) const WIN_FIELDS: &str =
+    "id, date, text, created_at, mood, energy, impact, utc_offset, time_zone";
+
+/// The select list `win_from_row` expects, for a win row aliased `w`.
+/// Extra columns a query needs go after these, from `WIN_COLUMN_COUNT` on.
+pub(crate) fn win_columns() -> String {
+    format!(
+        "w.id, w.date, w.text, {}, w.created_at, w.mood, w.energy, w.impact, \
+         w.utc_offset, w.time_zone",
+        TAGS_SUBQUERY
+    )
+}
+
+pub(crate) const WIN_COLUMN_COUNT: usize = 10;
+
+/// Comma-joined tag names for a win row aliased `w` (in `wins` or `deleted_wins`).
+pub(crate) const TAGS_SUBQUERY: &str = "(SELECT group_concat(t.name, ',') FROM win_tags wt \
+     JOIN tags t ON t.id = wt.tag_id WHERE wt.win_id = w.id)";
+
+/// Maps the `win_columns` select list to a `Win`.
+pub(crate) fn win_from_row(row: &rusqlite::Row) -> Result<Win> {
+    Ok(Win {
+        id: row.get(0)?,
+        date: row.get(1)?,
+        text: row.get(2)?,
+        tags: split_tag_list(row.get(3)?),
+        created_at: row.get(4)?,
+        ratings: Ratings {
+            mood: row.get(5)?,
+            energy: row.get(6)?,
+            impact: row.get(7)?,
+        },
+        zone: LoggedZone {
+            utc_offset: row.get(8)?,
+            time_zone: row.get(9)?,
+        },
+    })
+}
+
+fn split_tag_list(list: Option<String>) -> Vec<String> {
+    let mut tags: Vec<String> = list
+        .unwrap_or_default()
+        .split(',')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect();
+    tags.sort_by_key(|t| t.to_lowercase());
+    tags
+}
+
+/// Parses comma-separated tag input and every historical `wins.tags` format
+/// (`a, b`, `[user, inferred]`, `["a","b"]`) into a deduplicated tag list.
+pub fn parse_tag_string(raw: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    raw.split(',')
+        .map(|t| t.trim_matches(|c: char| c == '[' || c == ']' || c == '"' || c.is_whitespace()))
+        .filter(|t| !t.is_empty() && seen.insert(t.to_lowercase()))
+        .map(|t| t.to_string())
+        .collect()
+}
+
+/// Replaces the tags linked to a win, creating any tag names not seen before.
+/// Aliases are stored as the tag they stand for.
+pub(crate) fn set_win_tags(conn: &Connection, win_id: i64, tags: &[String]) -> Result<()> {
+    conn.execute("DELETE FROM win_tags WHERE win_id = ?1", [win_id])?;
+    for name in tags {
+        let tag_id = tags::resolve_or_create(conn, name)?;
+        conn.execute(
+            "INSERT OR IGNORE INTO win_tags (win_id, tag_id) VALUES (?1, ?2)",
+            (win_id, tag_id),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn get_wins(conn: &Connection) -> Result<Vec<Win>> {
+    let mut stmt = match conn.prepare(&format!(
+        "SELECT {} FROM wins w ORDER BY w.created_at DESC",
+        win_columns()
+    )) {
         Ok(s) => s,
         Err(e) => {
             println!("[get_wins] Prepare error: {}", e);
             return Err(e);
         }
     };
-    let wins_iter = stmt.query_map([], |row| {
-        Ok(Win {
-            id: row.get(0)?,
-            date: row.get(1)?,
-            text: row.get(2)?,
-            tags: row.get(3)?,
-            created_at: row.get(4)?,
-        })
-    });
+    let wins_iter = stmt.query_map([], win_from_row);
     let mut wins = Vec::new();
     match wins_iter {
         Ok(iter) => {
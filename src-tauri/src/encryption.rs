@@ -0,0 +1,358 @@
+// encryption.rs - SQLCipher encryption at rest for the privacy lock
+
+use crate::attachments;
+use crate::backup;
+use crate::db::{self, Db};
+use crate::errors::failure;
+use crate::migrations;
+use rusqlite::{ffi, Connection, DatabaseName, Result};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Every plaintext SQLite file starts with this header; SQLCipher files start
+/// with a random salt instead.
+const PLAINTEXT_HEADER: &[u8; 16] = b"SQLite format 3\0";
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+#[derive(Serialize)]
+pub struct EncryptionStatus {
+    pub encrypted: bool,
+    /// True until `unlock_db` has been called with the right passphrase
+    pub locked: bool,
+}
+
+pub fn status(db: &Db) -> EncryptionStatus {
+    EncryptionStatus {
+        encrypted: is_encrypted(db.path()),
+        locked: db.is_locked(),
+    }
+}
+
+/// A missing or empty file is treated as plaintext: it's a fresh database.
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    match File::open(path).and_then(|mut f| f.read_exact(&mut header)) {
+        Ok(()) => &header != PLAINTEXT_HEADER,
+        Err(_) => false,
+    }
+}
+
+/// Opens `path`, keying the connection when a passphrase is given, and reads
+/// the schema so a wrong passphrase fails here rather than on first use.
+pub fn open(path: &Path, passphrase: Option<&str>) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    if let Some(passphrase) = passphrase {
+        // SQLCipher 4 derives the page key with PBKDF2-HMAC-SHA512 (256,000
+        // iterations) over a random per-file salt
+        conn.pragma_update(None, "key", passphrase)?;
+    }
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })
+    .map_err(|_| failure(ffi::SQLITE_NOTADB, "Incorrect passphrase."))?;
+    Ok(conn)
+}
+
+pub fn enable_encryption(db: &Db, passphrase: &str) -> Result<()> {
+    if is_encrypted(db.path()) {
+        return Err(failure(
+            ffi::SQLITE_MISUSE,
+            "The database is already encrypted.",
+        ));
+    }
+    check_passphrase(passphrase)?;
+    // Attachment files are stored in the clear and would sit beside the
+    // locked journal unprotected
+    if db.with(|conn| attachments::count(conn))? > 0 {
+        return Err(failure(
+            ffi::SQLITE_MISUSE,
+            "Remove all attachments (including those on wins in the trash) before turning on the Privacy Lock; attachment files can't be encrypted.",
+        ));
+    }
+    rekey(db, None, Some(passphrase))
+}
+
+pub fn change_passphrase(db: &Db, current: &str, new: &str) -> Result<()> {
+    if !is_encrypted(db.path()) {
+        return Err(failure(
+            ffi::SQLITE_MISUSE,
+            "The database is not encrypted.",
+        ));
+    }
+    check_passphrase(new)?;
+    verify(db.path(), current)?;
+    rekey(db, Some(current), Some(new))
+}
+
+pub fn disable_encryption(db: &Db, passphrase: &str) -> Result<()> {
+    if !is_encrypted(db.path()) {
+        return Ok(());
+    }
+    verify(db.path(), passphrase)?;
+    rekey(db, Some(passphrase), None)
+}
+
+pub fn locked_error() -> rusqlite::Error {
+    failure(
+        ffi::SQLITE_AUTH,
+        "Quiet Wins is locked. Enter your passphrase to unlock it.",
+    )
+}
+
+fn check_passphrase(passphrase: &str) -> Result<()> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(failure(
+            ffi::SQLITE_MISUSE,
+            &format!(
+                "Passphrase must be at least {} characters.",
+                MIN_PASSPHRASE_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Change and disable require the current passphrase even while unlocked, so
+/// an unattended session can't be used to strip the lock.
+fn verify(path: &Path, passphrase: &str) -> Result<()> {
+    open(path, Some(passphrase)).map(|_| ())
+}
+
+/// Rewrites the whole database under `new_key` (`None` writes plaintext) and
+/// swaps it in. The copy goes to a side file and is checked before the live
+/// file is replaced, so a failure at any point leaves the old file intact.
+fn rekey(db: &Db, old_key: Option<&str>, new_key: Option<&str>) -> Result<()> {
+    let path = db.path().to_path_buf();
+    let staged = sibling(&path, ".rekey");
+    let previous = sibling(&path, ".previous");
+    let mut slot = db.slot();
+    let conn = slot.as_mut().ok_or_else(locked_error)?;
+    println!(
+        "[rekey] Rewriting {} ({})",
+        path.display(),
+        if new_key.is_some() {
+            "encrypted"
+        } else {
+            "plaintext"
+        }
+    );
+
+    // Fold the WAL into the main file so nothing is left behind after close
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    export_copy(conn, &staged, new_key)?;
+
+    // Close the live connection, then swap files: live -> previous, staged -> live
+    if let Some(old) = slot.take() {
+        if let Err((old, e)) = old.close() {
+            *slot = Some(old);
+            remove_db_files(&staged);
+            return Err(e);
+        }
+    }
+    remove_sidecars(&path);
+    let swapped = fs::rename(&path, &previous)
+        .and_then(|_| fs::rename(&staged, &path))
+        .map_err(|e| failure(ffi::SQLITE_IOERR, &e.to_string()));
+    let reopened = swapped.and_then(|_| open(&path, new_key).and_then(db::prepare_connection));
+    match reopened {
+        Ok(conn) => {
+            *slot = Some(conn);
+            db.set_key(new_key);
+            // The previous file holds the old (possibly plaintext) pages
+            remove_db_files(&previous);
+            // Snapshots must not keep a readable copy of the journal around
+            backup::rekey_snapshots(&path, old_key, new_key);
+            println!("[rekey] Done");
+            Ok(())
+        }
+        Err(e) => {
+            println!("[rekey] Swap failed: {}. Restoring previous file.", e);
+            if previous.exists() {
+                remove_db_files(&path);
+                let _ = fs::rename(&previous, &path);
+            }
+            remove_db_files(&staged);
+            *slot = open(&path, old_key).and_then(db::prepare_connection).ok();
+            Err(e)
+        }
+    }
+}
+
+/// Writes a full copy of the database open on `conn` to `dest`, encrypted
+/// under `key` (`None` for plaintext), and verifies it. Nothing is left at
+/// `dest` on failure.
+pub(crate) fn export_copy(conn: &Connection, dest: &Path, key: Option<&str>) -> Result<()> {
+    remove_db_files(dest);
+    conn.execute(
+        "ATTACH DATABASE ?1 AS rekeyed KEY ?2",
+        (dest.to_string_lossy(), key.unwrap_or("")),
+    )?;
+    let exported = conn
+        .query_row("SELECT sqlcipher_export('rekeyed')", [], |_| Ok(()))
+        .and_then(|_| migrations::schema_version(conn))
+        .and_then(|version| {
+            conn.pragma_update(
+                Some(DatabaseName::Attached("rekeyed")),
+                "user_version",
+                version,
+            )
+        });
+    conn.execute("DETACH DATABASE rekeyed", [])?;
+    if let Err(e) = exported.and_then(|_| check_copy(conn, dest, key)) {
+        remove_db_files(dest);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// The staged copy must open with the new key, pass quick_check and hold the
+/// same number of wins as the live database.
+fn check_copy(conn: &Connection, staged: &Path, new_key: Option<&str>) -> Result<()> {
+    let copy = open(staged, new_key)?;
+    let status: String = copy.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    let count =
+        |c: &Connection| c.query_row("SELECT COUNT(*) FROM wins", [], |row| row.get::<_, i64>(0));
+    if status != "ok" || count(&copy)? != count(conn)? {
+        return Err(failure(
+            ffi::SQLITE_CORRUPT,
+            "The re-keyed copy did not verify; the database was left unchanged.",
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn remove_sidecars(path: &Path) {
+    for suffix in ["-wal", "-shm", "-journal"] {
+        let _ = fs::remove_file(sibling(path, suffix));
+    }
+}
+
+pub(crate) fn remove_db_files(path: &Path) {
+    let _ = fs::remove_file(path);
+    remove_sidecars(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "correct horse";
+
+    /// A fresh unencrypted journal in its own temporary folder.
+    fn journal(name: &str) -> Db {
+        let dir = std::env::temp_dir().join(format!(
+            "quietwins-encryption-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Db::open_at(dir.join("quietwins.sqlite")).unwrap()
+    }
+
+    fn win_count(db: &Db) -> i64 {
+        db.with(|conn| conn.query_row("SELECT COUNT(*) FROM wins", [], |row| row.get(0)))
+            .unwrap()
+    }
+
+    fn header(path: &Path) -> [u8; 16] {
+        let mut header = [0u8; 16];
+        File::open(path)
+            .and_then(|mut f| f.read_exact(&mut header))
+            .unwrap();
+        header
+    }
+
+    #[test]
+    fn an_encrypted_journal_needs_its_passphrase() {
+        let db = journal("enable");
+        let wins = win_count(&db);
+        enable_encryption(&db, KEY).unwrap();
+        assert!(is_encrypted(db.path()));
+        let path = db.path().to_path_buf();
+        drop(db);
+
+        let db = Db::open_at(path.clone()).unwrap();
+        assert!(db.is_locked());
+        assert!(db.with(|_| Ok(())).is_err());
+        assert!(Connection::open(&path)
+            .and_then(|c| c.query_row("SELECT COUNT(*) FROM wins", [], |row| row.get::<_, i64>(0)))
+            .is_err());
+
+        let err = db.unlock("wrong horse").unwrap_err();
+        assert!(err.to_string().contains("Incorrect passphrase"));
+        assert!(db.is_locked());
+        db.unlock(KEY).unwrap();
+        assert_eq!(win_count(&db), wins);
+    }
+
+    #[test]
+    fn changing_the_passphrase_retires_the_old_one() {
+        let db = journal("change");
+        enable_encryption(&db, KEY).unwrap();
+        assert!(change_passphrase(&db, "wrong horse", "battery staple").is_err());
+        change_passphrase(&db, KEY, "battery staple").unwrap();
+        assert!(open(db.path(), Some(KEY)).is_err());
+        assert!(open(db.path(), Some("battery staple")).is_ok());
+
+        // And back again
+        change_passphrase(&db, "battery staple", KEY).unwrap();
+        assert!(open(db.path(), Some(KEY)).is_ok());
+        assert_eq!(db.key().as_deref(), Some(KEY));
+    }
+
+    #[test]
+    fn disabling_writes_a_plain_sqlite_file() {
+        let db = journal("disable");
+        let wins = win_count(&db);
+        enable_encryption(&db, KEY).unwrap();
+        assert_ne!(&header(db.path()), PLAINTEXT_HEADER);
+        assert!(disable_encryption(&db, "wrong horse").is_err());
+        disable_encryption(&db, KEY).unwrap();
+        assert_eq!(&header(db.path()), PLAINTEXT_HEADER);
+        assert!(!is_encrypted(db.path()));
+        assert_eq!(win_count(&db), wins);
+        assert_eq!(db.key(), None);
+    }
+
+    #[test]
+    fn snapshots_follow_the_journals_key() {
+        let db = journal("snapshots");
+        let dir = db.path().parent().unwrap().join("backups");
+        fs::create_dir_all(&dir).unwrap();
+        let snapshot = dir.join("quietwins-20250101-000000.sqlite");
+        db.with(|conn| export_copy(conn, &snapshot, None)).unwrap();
+        assert!(!is_encrypted(&snapshot));
+
+        enable_encryption(&db, KEY).unwrap();
+        assert!(is_encrypted(&snapshot));
+        assert!(open(&snapshot, Some(KEY)).is_ok());
+
+        change_passphrase(&db, KEY, "battery staple").unwrap();
+        assert!(open(&snapshot, Some(KEY)).is_err());
+        assert!(open(&snapshot, Some("battery staple")).is_ok());
+
+        disable_encryption(&db, "battery staple").unwrap();
+        assert_eq!(&header(&snapshot), PLAINTEXT_HEADER);
+    }
+
+    #[test]
+    fn short_passphrases_are_refused() {
+        let db = journal("short");
+        let err = enable_encryption(&db, "1234567").unwrap_err();
+        assert!(err.to_string().contains("at least 8 characters"));
+        assert!(!is_encrypted(db.path()));
+
+        enable_encryption(&db, KEY).unwrap();
+        assert!(change_passphrase(&db, KEY, "short").is_err());
+        assert!(open(db.path(), Some(KEY)).is_ok());
+    }
+}
@@ -0,0 +1,9 @@
+// errors.rs - Building errors for commands that report problems as SQLite errors
+
+use rusqlite::ffi;
+
+/// A `rusqlite::Error` carrying `code` and a message meant for the user, so
+/// checks that aren't about SQL fail the same way queries do.
+pub(crate) fn failure(code: std::os::raw::c_int, message: &str) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(ffi::Error::new(code), Some(message.to_string()))
+}
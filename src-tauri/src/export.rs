@@ -0,0 +1,312 @@
+// export.rs - Export the journal to versioned JSON, RFC 4180 CSV or Markdown
+
+use crate::attachments;
+use crate::clock::LoggedZone;
+use crate::db::Ratings;
+use crate::query::{self, SortOrder, WinFilter};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use time::OffsetDateTime;
+
+pub const EXPORT_FORMAT_NAME: &str = "quietwins-export";
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+/// Starts the Markdown line that links an attachment
+pub const ATTACHMENT_MARK: &str = "📎";
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Markdown,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ExportOptions {
+    #[serde(flatten)]
+    pub filter: WinFilter,
+    /// Also write the `deleted_wins` trash (filtered the same way)
+    pub include_deleted: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportedWin {
+    #[serde(default)]
+    pub id: Option<i64>,
+    pub date: String,
+    pub text: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    #[serde(flatten)]
+    pub ratings: Ratings,
+    #[serde(flatten)]
+    pub zone: LoggedZone,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<ExportedAttachment>,
+}
+
+/// An attached file, copied next to the export. `path` is relative to the
+/// export file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportedAttachment {
+    pub file_name: String,
+    pub mime: String,
+    pub sha256: String,
+    pub path: String,
+}
+
+/// Top-level JSON document. Bump `EXPORT_FORMAT_VERSION` on breaking changes.
+#[derive(Serialize, Deserialize)]
+pub struct ExportDocument {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: i64,
+    pub wins: Vec<ExportedWin>,
+    #[serde(default)]
+    pub deleted_wins: Vec<ExportedWin>,
+}
+
+#[derive(Serialize)]
+pub struct ExportSummary {
+    pub path: String,
+    pub wins: usize,
+    pub deleted_wins: usize,
+    pub attachments: usize,
+}
+
+pub const CSV_HEADER: [&str; 12] = [
+    "id",
+    "date",
+    "text",
+    "tags",
+    "created_at",
+    "deleted_at",
+    "mood",
+    "energy",
+    "impact",
+    "utc_offset",
+    "time_zone",
+    "attachments",
+];
+
+pub fn export_wins(
+    conn: &Connection,
+    path: &Path,
+    format: ExportFormat,
+    options: &ExportOptions,
+) -> Result<ExportSummary, String> {
+    println!("[export_wins] Exporting {:?} to {}", format, path.display());
+    let mut wins = collect(conn, "wins", &options.filter)?;
+    let mut deleted_wins = if options.include_deleted {
+        collect(conn, "deleted_wins", &options.filter)?
+    } else {
+        Vec::new()
+    };
+    let attachments = copy_attachments(conn, path, wins.iter_mut().chain(&mut deleted_wins))?;
+    let contents = match format {
+        ExportFormat::Json => to_json(&wins, &deleted_wins)?,
+        ExportFormat::Csv => to_csv(&wins, &deleted_wins),
+        ExportFormat::Markdown => to_markdown(&wins, &deleted_wins),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::write(path, contents).map_err(|e| e.to_string())?;
+    println!(
+        "[export_wins] Wrote {} wins, {} deleted wins and {} attachments",
+        wins.len(),
+        deleted_wins.len(),
+        attachments
+    );
+    Ok(ExportSummary {
+        path: path.display().to_string(),
+        wins: wins.len(),
+        deleted_wins: deleted_wins.len(),
+        attachments,
+    })
+}
+
+/// Copies every attachment of the exported wins into a `<name>-attachments`
+/// folder beside the export file and fills in `ExportedWin::attachments`.
+/// Returns the number of files copied.
+fn copy_attachments<'a>(
+    conn: &Connection,
+    export_path: &Path,
+    wins: impl Iterator<Item = &'a mut ExportedWin>,
+) -> Result<usize, String> {
+    let stem = export_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "export".to_string());
+    let folder = format!("{}-attachments", stem);
+    let dir = export_path.with_file_name(&folder);
+    let mut copied = 0;
+    for win in wins {
+        let Some(id) = win.id else { continue };
+        for attachment in attachments::list_attachments(conn, id).map_err(|e| e.to_string())? {
+            let source = attachments::object_path(conn, &attachment).map_err(|e| e.to_string())?;
+            let name = source
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if !source.exists() {
+                println!("[export_wins] Missing file for {}", attachment.file_name);
+                continue;
+            }
+            fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+            fs::copy(&source, dir.join(&name)).map_err(|e| e.to_string())?;
+            copied += 1;
+            win.attachments.push(ExportedAttachment {
+                file_name: attachment.file_name,
+                mime: attachment.mime,
+                sha256: attachment.hash,
+                path: format!("{}/{}", folder, name),
+            });
+        }
+    }
+    Ok(copied)
+}
+
+fn collect(conn: &Connection, table: &str, filter: &WinFilter) -> Result<Vec<ExportedWin>, String> {
+    let wins = query::filtered_wins(conn, table, filter, SortOrder::Oldest, None, None)
+        .map_err(|e| e.to_string())?;
+    let deleted_at = if table == "deleted_wins" {
+        deleted_timestamps(conn).map_err(|e| e.to_string())?
+    } else {
+        HashMap::new()
+    };
+    Ok(wins
+        .into_iter()
+        .map(|w| ExportedWin {
+            deleted_at: deleted_at.get(&w.id).copied(),
+            id: Some(w.id),
+            date: w.date,
+            text: w.text,
+            tags: w.tags,
+            created_at: Some(w.created_at),
+            ratings: w.ratings,
+            zone: w.zone,
+            attachments: Vec::new(),
+        })
+        .collect())
+}
+
+fn deleted_timestamps(conn: &Connection) -> rusqlite::Result<HashMap<i64, i64>> {
+    let mut stmt = conn.prepare("SELECT id, deleted_at FROM deleted_wins")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+pub(crate) fn to_json(
+    wins: &[ExportedWin],
+    deleted_wins: &[ExportedWin],
+) -> Result<String, String> {
+    let doc = ExportDocument {
+        format: EXPORT_FORMAT_NAME.to_string(),
+        version: EXPORT_FORMAT_VERSION,
+        exported_at: OffsetDateTime::now_utc().unix_timestamp(),
+        wins: wins.to_vec(),
+        deleted_wins: deleted_wins.to_vec(),
+    };
+    serde_json::to_string_pretty(&doc).map_err(|e| e.to_string())
+}
+
+/// RFC 4180: CRLF line endings, fields quoted when they contain a comma,
+/// quote or line break, and embedded quotes doubled. Tags are joined with
+/// ", " inside a single field; trashed rows have `deleted_at` filled in.
+pub(crate) fn to_csv(wins: &[ExportedWin], deleted_wins: &[ExportedWin]) -> String {
+    let mut out = String::new();
+    push_csv_record(&mut out, CSV_HEADER.iter().map(|h| h.to_string()));
+    for win in wins.iter().chain(deleted_wins) {
+        push_csv_record(
+            &mut out,
+            [
+                win.id.map(|id| id.to_string()).unwrap_or_default(),
+                win.date.clone(),
+                win.text.clone(),
+                win.tags.join(", "),
+                win.created_at.map(|t| t.to_string()).unwrap_or_default(),
+                win.deleted_at.map(|t| t.to_string()).unwrap_or_default(),
+                rating_field(win.ratings.mood),
+                rating_field(win.ratings.energy),
+                rating_field(win.ratings.impact),
+                win.zone
+                    .utc_offset
+                    .map(|m| m.to_string())
+                    .unwrap_or_default(),
+                win.zone.time_zone.clone().unwrap_or_default(),
+                win.attachments
+                    .iter()
+                    .map(|a| a.path.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ]
+            .into_iter(),
+        );
+    }
+    out
+}
+
+fn rating_field(rating: Option<i64>) -> String {
+    rating.map(|r| r.to_string()).unwrap_or_default()
+}
+
+fn push_csv_record(out: &mut String, fields: impl Iterator<Item = String>) {
+    let fields: Vec<String> = fields.map(|f| csv_field(&f)).collect();
+    out.push_str(&fields.join(","));
+    out.push_str("\r\n");
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One `## YYYY-MM-DD` heading per day and one bullet per win, with tags in
+/// italics after an em dash and one indented `📎` link per attachment.
+/// `import_wins` reads this layout back.
+pub(crate) fn to_markdown(wins: &[ExportedWin], deleted_wins: &[ExportedWin]) -> String {
+    let mut out = String::from("# Quiet Wins\n");
+    push_markdown_days(&mut out, wins, "##");
+    if !deleted_wins.is_empty() {
+        out.push_str("\n## Trash\n");
+        push_markdown_days(&mut out, deleted_wins, "###");
+    }
+    out
+}
+
+fn push_markdown_days(out: &mut String, wins: &[ExportedWin], heading: &str) {
+    let mut by_day: BTreeMap<&str, Vec<&ExportedWin>> = BTreeMap::new();
+    for win in wins {
+        by_day.entry(win.date.as_str()).or_default().push(win);
+    }
+    for (day, day_wins) in by_day {
+        out.push_str(&format!("\n{} {}\n\n", heading, day));
+        for win in day_wins {
+            // Continuation lines are indented so they stay inside the bullet
+            let text = win.text.trim().replace('\n', "\n  ");
+            if win.tags.is_empty() {
+                out.push_str(&format!("- {}\n", text));
+            } else {
+                out.push_str(&format!("- {} — *{}*\n", text, win.tags.join(", ")));
+            }
+            for attachment in &win.attachments {
+                out.push_str(&format!(
+                    "  {} [{}](<{}>)\n",
+                    ATTACHMENT_MARK, attachment.file_name, attachment.path
+                ));
+            }
+        }
+    }
+}
@@ -0,0 +1,585 @@
+// goals.rs - Goals and projects that wins count toward, and progress per period
+
+use crate::chains;
+use crate::clock;
+use crate::db::{self, Win};
+use crate::errors;
+use crate::tags;
+use chrono::{DateTime, Datelike, Days, Local, Months, NaiveDate};
+use rusqlite::{ffi, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum GoalPeriod {
+    Day,
+    Week,
+    Month,
+    /// Once over the whole life of the goal
+    Total,
+}
+
+impl GoalPeriod {
+    fn as_str(self) -> &'static str {
+        match self {
+            GoalPeriod::Day => "day",
+            GoalPeriod::Week => "week",
+            GoalPeriod::Month => "month",
+            GoalPeriod::Total => "total",
+        }
+    }
+
+    fn parse(s: &str) -> Option<GoalPeriod> {
+        match s {
+            "day" => Some(GoalPeriod::Day),
+            "week" => Some(GoalPeriod::Week),
+            "month" => Some(GoalPeriod::Month),
+            "total" => Some(GoalPeriod::Total),
+            _ => None,
+        }
+    }
+
+    /// First day of the period containing `date`. Weeks start on Monday.
+    fn start_of(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            GoalPeriod::Day | GoalPeriod::Total => date,
+            GoalPeriod::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+            GoalPeriod::Month => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    fn next(self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            GoalPeriod::Day => start.checked_add_days(Days::new(1)),
+            GoalPeriod::Week => start.checked_add_days(Days::new(7)),
+            GoalPeriod::Month => start.checked_add_months(Months::new(1)),
+            GoalPeriod::Total => None,
+        }
+    }
+}
+
+/// e.g. "exercise 3x per week" is `{ count: 3, period: Week }`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct GoalTarget {
+    pub count: i64,
+    pub period: GoalPeriod,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Goal {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub target: Option<GoalTarget>,
+    /// YYYY-MM-DD
+    pub deadline: Option<String>,
+    /// Wins with any of these tags, or a tag under one, count toward the goal
+    pub rule_tags: Vec<String>,
+    pub created_at: i64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct GoalInput {
+    pub name: String,
+    pub description: String,
+    pub target: Option<GoalTarget>,
+    pub deadline: Option<String>,
+    pub rule_tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct WinGoal {
+    pub goal_id: i64,
+    /// Linked by hand with `link_win`
+    pub linked: bool,
+    /// Carries one of the goal's rule tags
+    pub by_tag: bool,
+}
+
+#[derive(Serialize)]
+pub struct PeriodProgress {
+    /// First and last day of the period, inclusive
+    pub start: String,
+    pub end: String,
+    pub count: usize,
+    /// Share of the target reached, capped at 1.0; `None` without a target
+    pub ratio: Option<f64>,
+    pub met: bool,
+}
+
+#[derive(Serialize)]
+pub struct GoalProgress {
+    pub goal: Goal,
+    pub periods: Vec<PeriodProgress>,
+    /// Contributing wins in the whole range, newest first
+    pub wins: Vec<Win>,
+    pub periods_met: usize,
+    /// Consecutive met periods ending with the latest complete or met one
+    pub current_run: usize,
+    pub days_left: Option<i64>,
+}
+
+pub fn list_goals(conn: &Connection) -> Result<Vec<Goal>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, name, description, target_count, target_period, deadline, created_at
+         FROM goals ORDER BY name",
+    )?;
+    let goals = stmt.query_map([], goal_from_row)?;
+    goals
+        .map(|g| g.and_then(|g| with_rule_tags(conn, g)))
+        .collect()
+}
+
+pub fn get_goal(conn: &Connection, id: i64) -> Result<Option<Goal>> {
+    conn.query_row(
+        "SELECT id, name, description, target_count, target_period, deadline, created_at
+         FROM goals WHERE id = ?1",
+        [id],
+        goal_from_row,
+    )
+    .optional()?
+    .map(|g| with_rule_tags(conn, g))
+    .transpose()
+}
+
+fn goal_from_row(row: &rusqlite::Row) -> Result<Goal> {
+    let count: Option<i64> = row.get(3)?;
+    let period: Option<String> = row.get(4)?;
+    Ok(Goal {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        description: row.get(2)?,
+        target: count
+            .zip(period.as_deref().and_then(GoalPeriod::parse))
+            .map(|(count, period)| GoalTarget { count, period }),
+        deadline: row.get(5)?,
+        rule_tags: Vec::new(),
+        created_at: row.get(6)?,
+    })
+}
+
+fn with_rule_tags(conn: &Connection, mut goal: Goal) -> Result<Goal> {
+    let mut stmt = conn.prepare(
+        "SELECT t.name FROM goal_tag_rules r JOIN tags t ON t.id = r.tag_id
+         WHERE r.goal_id = ?1 ORDER BY t.name",
+    )?;
+    let tags = stmt.query_map([goal.id], |row| row.get(0))?;
+    goal.rule_tags = tags.collect::<Result<_>>()?;
+    Ok(goal)
+}
+
+fn validate(input: &GoalInput) -> Result<()> {
+    let invalid = |msg: &str| Err(errors::failure(ffi::SQLITE_CONSTRAINT, msg));
+    if input.name.trim().is_empty() {
+        return invalid("A goal needs a name.");
+    }
+    if input.target.is_some_and(|t| t.count < 1) {
+        return invalid("A target must be at least 1.");
+    }
+    if let Some(deadline) = input.deadline.as_deref().filter(|d| !d.is_empty()) {
+        if NaiveDate::parse_from_str(deadline, "%Y-%m-%d").is_err() {
+            return invalid("Deadline must be a YYYY-MM-DD date.");
+        }
+    }
+    Ok(())
+}
+
+pub fn create_goal(conn: &mut Connection, input: &GoalInput) -> Result<Goal> {
+    validate(input)?;
+    println!("[create_goal] {}", input.name.trim());
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO goals (name, description, target_count, target_period, deadline, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (
+            input.name.trim(),
+            input.description.trim(),
+            input.target.map(|t| t.count),
+            input.target.map(|t| t.period.as_str()),
+            deadline(input),
+            OffsetDateTime::now_utc().unix_timestamp(),
+        ),
+    )?;
+    let id = tx.last_insert_rowid();
+    set_rule_tags(&tx, id, &input.rule_tags)?;
+    tx.commit()?;
+    get_goal(conn, id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+pub fn update_goal(conn: &mut Connection, id: i64, input: &GoalInput) -> Result<Goal> {
+    validate(input)?;
+    println!("[update_goal] Updating goal {}", id);
+    let tx = conn.transaction()?;
+    let changed = tx.execute(
+        "UPDATE goals SET name = ?1, description = ?2, target_count = ?3, target_period = ?4,
+             deadline = ?5
+         WHERE id = ?6",
+        (
+            input.name.trim(),
+            input.description.trim(),
+            input.target.map(|t| t.count),
+            input.target.map(|t| t.period.as_str()),
+            deadline(input),
+            id,
+        ),
+    )?;
+    if changed == 0 {
+        return Err(no_such_goal());
+    }
+    set_rule_tags(&tx, id, &input.rule_tags)?;
+    tx.commit()?;
+    get_goal(conn, id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+/// Removes the goal and its links; the wins themselves are untouched.
+pub fn delete_goal(conn: &Connection, id: i64) -> Result<()> {
+    println!("[delete_goal] Deleting goal {}", id);
+    if conn.execute("DELETE FROM goals WHERE id = ?1", [id])? == 0 {
+        return Err(no_such_goal());
+    }
+    Ok(())
+}
+
+fn deadline(input: &GoalInput) -> Option<&str> {
+    input.deadline.as_deref().filter(|d| !d.is_empty())
+}
+
+fn no_such_goal() -> rusqlite::Error {
+    errors::failure(ffi::SQLITE_NOTFOUND, "No such goal.")
+}
+
+fn set_rule_tags(conn: &Connection, goal_id: i64, tags: &[String]) -> Result<()> {
+    conn.execute("DELETE FROM goal_tag_rules WHERE goal_id = ?1", [goal_id])?;
+    for name in db::parse_tag_string(&tags.join(",")) {
+        let tag_id = tags::resolve_or_create(conn, &name)?;
+        conn.execute(
+            "INSERT OR IGNORE INTO goal_tag_rules (goal_id, tag_id) VALUES (?1, ?2)",
+            (goal_id, tag_id),
+        )?;
+    }
+    Ok(())
+}
+
+/// Counts a win toward a goal regardless of its tags.
+pub fn link_win(conn: &Connection, goal_id: i64, win_id: i64) -> Result<()> {
+    if get_goal(conn, goal_id)?.is_none() {
+        return Err(no_such_goal());
+    }
+    chains::ensure_win(conn, win_id)?;
+    conn.execute(
+        "INSERT OR IGNORE INTO goal_wins (goal_id, win_id) VALUES (?1, ?2)",
+        (goal_id, win_id),
+    )?;
+    Ok(())
+}
+
+/// Only removes a manual link; a win matching a rule tag still counts.
+pub fn unlink_win(conn: &Connection, goal_id: i64, win_id: i64) -> Result<()> {
+    conn.execute(
+        "DELETE FROM goal_wins WHERE goal_id = ?1 AND win_id = ?2",
+        (goal_id, win_id),
+    )?;
+    Ok(())
+}
+
+/// The goals `win_id` counts toward and why.
+pub fn goals_for_win(conn: &Connection, win_id: i64) -> Result<Vec<WinGoal>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, linked, by_tag FROM (
+             SELECT g.id, g.name,
+                 EXISTS (SELECT 1 FROM goal_wins gw WHERE gw.goal_id = g.id AND gw.win_id = ?1)
+                     AS linked,
+                 EXISTS (SELECT 1 FROM goal_tag_rules r
+                         JOIN win_tags_rollup wt ON wt.tag_id = r.tag_id
+                         WHERE r.goal_id = g.id AND wt.win_id = ?1) AS by_tag
+             FROM goals g)
+         WHERE linked OR by_tag ORDER BY name",
+    )?;
+    let rows = stmt.query_map([win_id], |row| {
+        Ok(WinGoal {
+            goal_id: row.get(0)?,
+            linked: row.get(1)?,
+            by_tag: row.get(2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Live wins counting toward the goal, newest first.
+fn contributing_wins(conn: &Connection, goal_id: i64) -> Result<Vec<Win>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM wins w
+         WHERE w.id IN (SELECT win_id FROM goal_wins WHERE goal_id = ?1)
+            OR EXISTS (SELECT 1 FROM win_tags_rollup wt
+                       JOIN goal_tag_rules r ON r.tag_id = wt.tag_id
+                       WHERE wt.win_id = w.id AND r.goal_id = ?1)
+         ORDER BY w.date DESC, w.id DESC",
+        db::win_columns()
+    ))?;
+    let wins = stmt.query_map([goal_id], db::win_from_row)?;
+    wins.collect()
+}
+
+/// Splits `from..=to` into the goal's periods and counts contributing wins in
+/// each. The range defaults to the goal's creation (or its earliest win, if
+/// older) through today, or through the deadline once it has passed.
+pub fn get_goal_progress(
+    conn: &Connection,
+    goal_id: i64,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<GoalProgress> {
+    let goal = get_goal(conn, goal_id)?.ok_or_else(no_such_goal)?;
+    let all_wins = contributing_wins(conn, goal_id)?;
+    let parse = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok();
+    let today = clock::today();
+    let deadline = goal.deadline.as_deref().and_then(parse);
+    let created = DateTime::from_timestamp(goal.created_at, 0)
+        .map_or(today, |t| t.with_timezone(&Local).date_naive());
+    let earliest = all_wins
+        .iter()
+        .filter_map(|w| parse(&w.date))
+        .min()
+        .map_or(created, |d| d.min(created));
+    let from = from.and_then(parse).unwrap_or(earliest);
+    let to = to
+        .and_then(parse)
+        .unwrap_or_else(|| deadline.map_or(today, |d| d.min(today).max(from)));
+
+    let wins: Vec<Win> = all_wins
+        .into_iter()
+        .filter(|w| parse(&w.date).is_some_and(|d| d >= from && d <= to))
+        .collect();
+    let dates: Vec<NaiveDate> = wins.iter().filter_map(|w| parse(&w.date)).collect();
+    let periods = split_periods(goal.target, &dates, from, to);
+    let periods_met = periods.iter().filter(|p| p.met).count();
+    let current_run = current_run(&periods, today);
+    let days_left = deadline.map(|d| (d - today).num_days());
+    Ok(GoalProgress {
+        goal,
+        periods,
+        wins,
+        periods_met,
+        current_run,
+        days_left,
+    })
+}
+
+/// Cuts `from..=to` into calendar periods of the target (one period without
+/// a target) and counts the `dates` in each. The first and last periods are
+/// trimmed to the range, so a range starting mid-week doesn't report days
+/// before it.
+fn split_periods(
+    target: Option<GoalTarget>,
+    dates: &[NaiveDate],
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<PeriodProgress> {
+    let period = target.map_or(GoalPeriod::Total, |t| t.period);
+    let mut periods = Vec::new();
+    let mut start = period.start_of(from).max(from);
+    while start <= to {
+        let next = period.next(period.start_of(start));
+        let end = next.and_then(|n| n.pred_opt()).map_or(to, |e| e.min(to));
+        let count = dates.iter().filter(|d| **d >= start && **d <= end).count();
+        periods.push(PeriodProgress {
+            start: start.format("%Y-%m-%d").to_string(),
+            end: end.format("%Y-%m-%d").to_string(),
+            count,
+            met: target.is_some_and(|t| count as i64 >= t.count),
+            ratio: target.map(|t| (count as f64 / t.count as f64).min(1.0)),
+        });
+        match next {
+            Some(n) => start = n,
+            None => break,
+        }
+    }
+    periods
+}
+
+/// Met periods in a row, counting back from the newest. The period still in
+/// progress doesn't break a run until it's over.
+fn current_run(periods: &[PeriodProgress], today: NaiveDate) -> usize {
+    let in_progress = periods
+        .last()
+        .is_some_and(|p| !p.met && p.end >= today.to_string());
+    periods
+        .iter()
+        .rev()
+        .skip(usize::from(in_progress))
+        .take_while(|p| p.met)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn target(count: i64, period: GoalPeriod) -> Option<GoalTarget> {
+        Some(GoalTarget { count, period })
+    }
+
+    fn spans(periods: &[PeriodProgress]) -> Vec<(String, String, usize)> {
+        periods
+            .iter()
+            .map(|p| (p.start.clone(), p.end.clone(), p.count))
+            .collect()
+    }
+
+    fn span(start: &str, end: &str, count: usize) -> (String, String, usize) {
+        (start.to_string(), end.to_string(), count)
+    }
+
+    #[test]
+    fn weeks_are_trimmed_to_the_range() {
+        // 2025-01-01 is a Wednesday; the Monday before it isn't in range
+        let dates = [
+            day("2024-12-30"),
+            day("2025-01-01"),
+            day("2025-01-06"),
+            day("2025-01-15"),
+        ];
+        let periods = split_periods(
+            target(1, GoalPeriod::Week),
+            &dates,
+            day("2025-01-01"),
+            day("2025-01-14"),
+        );
+        assert_eq!(
+            spans(&periods),
+            vec![
+                span("2025-01-01", "2025-01-05", 1),
+                span("2025-01-06", "2025-01-12", 1),
+                span("2025-01-13", "2025-01-14", 0),
+            ]
+        );
+        assert_eq!(
+            periods.iter().map(|p| p.met).collect::<Vec<_>>(),
+            vec![true, true, false]
+        );
+    }
+
+    #[test]
+    fn months_follow_the_calendar() {
+        let dates = [
+            day("2025-01-31"),
+            day("2025-02-01"),
+            day("2025-02-28"),
+            day("2025-03-01"),
+        ];
+        let periods = split_periods(
+            target(2, GoalPeriod::Month),
+            &dates,
+            day("2025-01-20"),
+            day("2025-03-10"),
+        );
+        assert_eq!(
+            spans(&periods),
+            vec![
+                span("2025-01-20", "2025-01-31", 1),
+                span("2025-02-01", "2025-02-28", 2),
+                span("2025-03-01", "2025-03-10", 1),
+            ]
+        );
+        assert_eq!(periods[0].ratio, Some(0.5));
+        assert_eq!(periods[1].ratio, Some(1.0));
+    }
+
+    #[test]
+    fn days_and_totals() {
+        let dates = [day("2025-01-01"), day("2025-01-01"), day("2025-01-03")];
+        let days = split_periods(
+            target(1, GoalPeriod::Day),
+            &dates,
+            day("2025-01-01"),
+            day("2025-01-03"),
+        );
+        assert_eq!(
+            days.iter().map(|p| p.count).collect::<Vec<_>>(),
+            vec![2, 0, 1]
+        );
+
+        // Without a target the whole range is one period that's never met
+        let total = split_periods(None, &dates, day("2025-01-02"), day("2025-01-03"));
+        assert_eq!(spans(&total), vec![span("2025-01-02", "2025-01-03", 1)]);
+        assert!(!total[0].met && total[0].ratio.is_none());
+
+        // An empty range has no periods
+        assert!(split_periods(None, &dates, day("2025-01-03"), day("2025-01-02")).is_empty());
+    }
+
+    fn run(pattern: &[bool], today: &str) -> usize {
+        // Daily periods ending on 2025-01-10
+        let periods: Vec<PeriodProgress> = pattern
+            .iter()
+            .enumerate()
+            .map(|(i, &met)| {
+                let date = day("2025-01-10") - Days::new((pattern.len() - 1 - i) as u64);
+                PeriodProgress {
+                    start: date.to_string(),
+                    end: date.to_string(),
+                    count: usize::from(met),
+                    ratio: Some(if met { 1.0 } else { 0.0 }),
+                    met,
+                }
+            })
+            .collect();
+        current_run(&periods, day(today))
+    }
+
+    #[test]
+    fn runs_count_back_from_the_newest_period() {
+        assert_eq!(run(&[true, false, true, true], "2025-01-20"), 2);
+        assert_eq!(run(&[true, true, false], "2025-01-20"), 0);
+        assert_eq!(run(&[], "2025-01-20"), 0);
+    }
+
+    #[test]
+    fn the_period_in_progress_does_not_break_a_run() {
+        assert_eq!(run(&[true, true, false], "2025-01-10"), 2);
+        assert_eq!(run(&[true, true, true], "2025-01-10"), 3);
+        // Once it's over, an unmet period ends the run
+        assert_eq!(run(&[true, true, false], "2025-01-11"), 0);
+    }
+
+    #[test]
+    fn only_live_wins_can_be_linked() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::migrations::run_migrations(&mut conn).unwrap();
+        db::add_win(&mut conn, "2025-01-02", "Kept", "", db::Ratings::default()).unwrap();
+        db::add_win(
+            &mut conn,
+            "2025-01-03",
+            "Binned",
+            "",
+            db::Ratings::default(),
+        )
+        .unwrap();
+        db::delete_win(&mut conn, 2).unwrap();
+        conn.execute(
+            "INSERT INTO goals (name, created_at) VALUES ('Goal', 0)",
+            [],
+        )
+        .unwrap();
+
+        link_win(&conn, 1, 1).unwrap();
+        for (goal, win) in [(1, 2), (1, 99), (7, 1)] {
+            let err = link_win(&conn, goal, win).unwrap_err();
+            assert_eq!(err.sqlite_error_code(), Some(rusqlite::ErrorCode::NotFound));
+        }
+        let linked: Vec<i64> = conn
+            .prepare("SELECT win_id FROM goal_wins")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(linked, vec![1]);
+    }
+}
@@ -0,0 +1,397 @@
+// graph.rs - Weighted tag co-occurrence graph with filtering
+
+use crate::tags::Taxonomy;
+use rusqlite::{Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// Which wins and tags go into the graph. Every field is optional; the
+/// default is every live win and every tag.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct GraphFilter {
+    /// Inclusive YYYY-MM-DD bounds on the win date
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Only wins carrying all of these tags
+    pub with_tags: Vec<String>,
+    /// Edges seen in fewer wins than this are dropped
+    pub min_weight: usize,
+    /// Keep only the N most used tags
+    pub top_n: Option<usize>,
+    /// Ego graph: only tags within `depth` edges of this one
+    pub focus: Option<String>,
+    pub depth: Option<usize>,
+    /// Collapse the tag hierarchy: tags deeper than this count as their
+    /// ancestor at this level (0 = top-level tags only)
+    pub level: Option<usize>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TagNode {
+    pub tag: String,
+    /// Number of wins carrying the tag
+    pub count: usize,
+    /// Set by `clusters::get_tag_clusters`
+    pub cluster: Option<usize>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TagEdge {
+    pub source: String,
+    pub target: String,
+    /// Number of wins carrying both tags
+    pub weight: usize,
+    /// weight / wins carrying either tag, 0..1
+    pub jaccard: f64,
+    /// Pointwise mutual information, ln(P(a,b) / (P(a) P(b))). Positive when
+    /// the tags turn up together more often than chance.
+    pub pmi: f64,
+}
+
+#[derive(Serialize)]
+pub struct TagGraph {
+    /// Most used first
+    pub nodes: Vec<TagNode>,
+    /// Heaviest first
+    pub edges: Vec<TagEdge>,
+    /// Tagged wins the graph was built from
+    pub win_count: usize,
+}
+
+/// The tags of each live win matching `filter`, one list per win. Every tag
+/// brings its ancestors along, so `yoga` also counts toward `exercise`.
+pub(crate) fn tag_sets(
+    conn: &Connection,
+    filter: &GraphFilter,
+    taxonomy: &Taxonomy,
+) -> Result<Vec<Vec<String>>> {
+    let mut stmt = conn.prepare(
+        "SELECT wt.win_id, t.name FROM win_tags wt
+         JOIN tags t ON t.id = wt.tag_id
+         JOIN wins w ON w.id = wt.win_id
+         WHERE (?1 IS NULL OR w.date >= ?1) AND (?2 IS NULL OR w.date <= ?2)
+         ORDER BY wt.win_id, t.name",
+    )?;
+    let rows = stmt.query_map([filter.from.as_deref(), filter.to.as_deref()], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut by_win: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+    for row in rows {
+        let (win_id, tag) = row?;
+        by_win.entry(win_id).or_default().push(tag);
+    }
+    let required: Vec<String> = filter.with_tags.iter().map(|t| t.to_lowercase()).collect();
+    Ok(by_win
+        .into_values()
+        .map(|tags| taxonomy.expand(&tags))
+        .filter(|tags| {
+            required
+                .iter()
+                .all(|r| tags.iter().any(|t| t.to_lowercase() == *r))
+        })
+        .map(|tags| match filter.level {
+            Some(level) => {
+                let mut collapsed: Vec<String> = Vec::new();
+                for tag in &tags {
+                    let top = taxonomy.at_level(tag, level);
+                    if !collapsed.iter().any(|t| t == top) {
+                        collapsed.push(top.to_string());
+                    }
+                }
+                collapsed
+            }
+            None => tags,
+        })
+        .collect())
+}
+
+pub fn get_tag_graph(conn: &Connection, filter: &GraphFilter) -> Result<TagGraph> {
+    let taxonomy = Taxonomy::load(conn)?;
+    Ok(build(
+        &tag_sets(conn, filter, &taxonomy)?,
+        filter,
+        &taxonomy,
+    ))
+}
+
+/// Counts tags and co-occurring pairs over `wins`, then applies the edge,
+/// ego-graph and top-N filters in that order. The focus tag is always kept.
+/// A tag and its own ancestor always appear together, so that pair gets no
+/// edge.
+pub(crate) fn build(wins: &[Vec<String>], filter: &GraphFilter, taxonomy: &Taxonomy) -> TagGraph {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut pairs: HashMap<(&str, &str), usize> = HashMap::new();
+    for tags in wins {
+        let unique: Vec<&str> = {
+            let mut seen = HashSet::new();
+            let mut t: Vec<&str> = tags
+                .iter()
+                .map(|t| t.as_str())
+                .filter(|t| seen.insert(*t))
+                .collect();
+            t.sort_unstable();
+            t
+        };
+        for (i, a) in unique.iter().enumerate() {
+            *counts.entry(a).or_insert(0) += 1;
+            for b in &unique[i + 1..] {
+                *pairs.entry((a, b)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let n = wins.len() as f64;
+    let min_weight = filter.min_weight.max(1);
+    let mut edges: Vec<TagEdge> = pairs
+        .into_iter()
+        .filter(|((a, b), weight)| *weight >= min_weight && !taxonomy.related(a, b))
+        .map(|((a, b), weight)| {
+            let (ca, cb) = (counts[a] as f64, counts[b] as f64);
+            let w = weight as f64;
+            TagEdge {
+                source: a.to_string(),
+                target: b.to_string(),
+                weight,
+                jaccard: w / (ca + cb - w),
+                pmi: (w * n / (ca * cb)).ln(),
+            }
+        })
+        .collect();
+
+    let mut keep: HashSet<&str> = counts.keys().copied().collect();
+    let focus = filter.focus.as_deref().and_then(|f| {
+        counts
+            .keys()
+            .copied()
+            .find(|t| t.eq_ignore_ascii_case(f.trim()))
+    });
+    if let Some(focus) = focus {
+        keep = neighbourhood(focus, &edges, filter.depth.unwrap_or(1));
+    } else if filter.focus.is_some() {
+        keep.clear();
+    }
+    let mut nodes: Vec<TagNode> = counts
+        .iter()
+        .filter(|(tag, _)| keep.contains(*tag))
+        .map(|(tag, count)| TagNode {
+            tag: tag.to_string(),
+            count: *count,
+            cluster: None,
+        })
+        .collect();
+    nodes.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    if let Some(limit) = filter.top_n {
+        let mut kept = 0;
+        nodes.retain(|node| {
+            let is_focus = focus == Some(node.tag.as_str());
+            if is_focus || kept < limit {
+                kept += usize::from(!is_focus);
+                return true;
+            }
+            false
+        });
+    }
+
+    let names: HashSet<&str> = nodes.iter().map(|n| n.tag.as_str()).collect();
+    edges.retain(|e| names.contains(e.source.as_str()) && names.contains(e.target.as_str()));
+    edges.sort_by(|a, b| {
+        b.weight
+            .cmp(&a.weight)
+            .then_with(|| a.source.cmp(&b.source))
+            .then_with(|| a.target.cmp(&b.target))
+    });
+    TagGraph {
+        nodes,
+        edges,
+        win_count: wins.len(),
+    }
+}
+
+/// Tags reachable from `focus` in at most `depth` steps.
+fn neighbourhood<'a>(focus: &'a str, edges: &'a [TagEdge], depth: usize) -> HashSet<&'a str> {
+    let mut adjacent: HashMap<&str, Vec<&str>> = HashMap::new();
+    for e in edges {
+        adjacent.entry(&e.source).or_default().push(&e.target);
+        adjacent.entry(&e.target).or_default().push(&e.source);
+    }
+    let mut seen = HashSet::from([focus]);
+    let mut queue = VecDeque::from([(focus, 0)]);
+    while let Some((tag, d)) = queue.pop_front() {
+        if d == depth {
+            continue;
+        }
+        for next in adjacent.get(tag).into_iter().flatten() {
+            if seen.insert(next) {
+                queue.push_back((next, d + 1));
+            }
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wins(sets: &[&[&str]]) -> Vec<Vec<String>> {
+        sets.iter()
+            .map(|tags| tags.iter().map(|t| t.to_string()).collect())
+            .collect()
+    }
+
+    fn graph(sets: &[&[&str]], filter: &GraphFilter) -> TagGraph {
+        build(&wins(sets), filter, &Taxonomy::default())
+    }
+
+    fn node_names(graph: &TagGraph) -> Vec<&str> {
+        graph.nodes.iter().map(|n| n.tag.as_str()).collect()
+    }
+
+    fn edge_names(graph: &TagGraph) -> Vec<(&str, &str)> {
+        graph
+            .edges
+            .iter()
+            .map(|e| (e.source.as_str(), e.target.as_str()))
+            .collect()
+    }
+
+    const FOUR_WINS: &[&[&str]] = &[&["a", "b"], &["b", "a"], &["a", "c"], &["d"]];
+
+    #[test]
+    fn edges_are_weighted_by_jaccard_and_pmi() {
+        let g = graph(FOUR_WINS, &GraphFilter::default());
+        assert_eq!(g.win_count, 4);
+        assert_eq!(node_names(&g), vec!["a", "b", "c", "d"]);
+        assert_eq!(edge_names(&g), vec![("a", "b"), ("a", "c")]);
+        let (ab, ac) = (&g.edges[0], &g.edges[1]);
+        assert_eq!((ab.weight, ac.weight), (2, 1));
+        // a is on 3 wins, b on 2 and c on 1, out of 4
+        assert!((ab.jaccard - 2.0 / 3.0).abs() < 1e-9);
+        assert!((ac.jaccard - 1.0 / 3.0).abs() < 1e-9);
+        assert!((ab.pmi - (2.0 * 4.0 / (3.0 * 2.0_f64)).ln()).abs() < 1e-9);
+        assert!((ac.pmi - (4.0_f64 / 3.0).ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tags_always_together_are_a_perfect_pair() {
+        let g = graph(&[&["x", "y"], &["x", "y"], &["z"]], &GraphFilter::default());
+        assert_eq!(g.edges[0].jaccard, 1.0);
+        assert!(g.edges[0].pmi > 0.0);
+        // Repeating a tag on one win doesn't count it twice
+        let g = graph(&[&["x", "x", "y"]], &GraphFilter::default());
+        assert_eq!(g.nodes[0].count, 1);
+        assert_eq!(g.edges[0].weight, 1);
+    }
+
+    #[test]
+    fn min_weight_drops_edges_but_not_tags() {
+        let filter = GraphFilter {
+            min_weight: 2,
+            ..Default::default()
+        };
+        let g = graph(FOUR_WINS, &filter);
+        assert_eq!(edge_names(&g), vec![("a", "b")]);
+        assert_eq!(g.nodes.len(), 4);
+    }
+
+    #[test]
+    fn top_n_keeps_the_most_used_tags_and_the_focus() {
+        let filter = GraphFilter {
+            top_n: Some(2),
+            ..Default::default()
+        };
+        let g = graph(FOUR_WINS, &filter);
+        assert_eq!(node_names(&g), vec!["a", "b"]);
+        assert_eq!(edge_names(&g), vec![("a", "b")]);
+
+        // The focus isn't one of the top tags but stays, without using a slot
+        let filter = GraphFilter {
+            top_n: Some(1),
+            focus: Some("C".to_string()),
+            ..Default::default()
+        };
+        let g = graph(FOUR_WINS, &filter);
+        assert_eq!(node_names(&g), vec!["a", "c"]);
+        assert_eq!(edge_names(&g), vec![("a", "c")]);
+    }
+
+    #[test]
+    fn focus_depth_walks_the_neighbourhood() {
+        let path: &[&[&str]] = &[&["p", "q"], &["q", "r"], &["r", "s"], &["t"]];
+        let around = |depth| {
+            let filter = GraphFilter {
+                focus: Some("p".to_string()),
+                depth,
+                ..Default::default()
+            };
+            let g = graph(path, &filter);
+            let mut names: Vec<String> = g.nodes.iter().map(|n| n.tag.clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(around(None), vec!["p", "q"]);
+        assert_eq!(around(Some(0)), vec!["p"]);
+        assert_eq!(around(Some(2)), vec!["p", "q", "r"]);
+        assert_eq!(around(Some(5)), vec!["p", "q", "r", "s"]);
+
+        // An unknown focus gives an empty graph rather than everything
+        let filter = GraphFilter {
+            focus: Some("nowhere".to_string()),
+            ..Default::default()
+        };
+        let g = graph(path, &filter);
+        assert!(g.nodes.is_empty() && g.edges.is_empty());
+    }
+
+    #[test]
+    fn levels_merge_child_tags_into_their_parent() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::migrations::run_migrations(&mut conn).unwrap();
+        // yoga and swim sit under exercise, which sits under health; cook is under life
+        for (id, tags) in [(1, "yoga, cook"), (2, "swim, cook"), (3, "exercise")] {
+            conn.execute(
+                "INSERT INTO wins (id, date, text, created_at) VALUES (?1, '2025-01-02', '', 0)",
+                [id],
+            )
+            .unwrap();
+            crate::db::set_win_tags(&conn, id, &crate::db::parse_tag_string(tags)).unwrap();
+        }
+        let at = |level| {
+            let filter = GraphFilter {
+                level,
+                ..Default::default()
+            };
+            get_tag_graph(&conn, &filter).unwrap()
+        };
+        let counts = |g: &TagGraph| -> Vec<(String, usize)> {
+            g.nodes.iter().map(|n| (n.tag.clone(), n.count)).collect()
+        };
+
+        let top = at(Some(0));
+        assert_eq!(
+            counts(&top),
+            vec![("health".to_string(), 3), ("life".to_string(), 2)]
+        );
+        assert_eq!(edge_names(&top), vec![("health", "life")]);
+        assert_eq!(top.edges[0].weight, 2);
+
+        // One level down, exercise absorbs yoga and swim but stays apart from health
+        let mid = at(Some(1));
+        let names = node_names(&mid);
+        assert!(names.contains(&"exercise") && names.contains(&"cook"));
+        assert!(!names.contains(&"yoga") && !names.contains(&"swim"));
+        assert_eq!(
+            mid.nodes
+                .iter()
+                .find(|n| n.tag == "exercise")
+                .unwrap()
+                .count,
+            3
+        );
+        assert!(!edge_names(&mid).contains(&("exercise", "health")));
+
+        // No level keeps every tag
+        assert!(node_names(&at(None)).contains(&"yoga"));
+    }
+}
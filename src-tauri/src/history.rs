@@ -0,0 +1,502 @@
+// history.rs - Persistent win revisions backing undo, redo and version history
+
+use crate::attachments;
+use crate::clock::LoggedZone;
+use crate::db;
+use crate::errors;
+use rusqlite::{ffi, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// The parts of a win a revision can bring back.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct WinSnapshot {
+    pub date: String,
+    pub text: String,
+    pub tags: Vec<String>,
+    pub created_at: i64,
+    /// Missing from revisions recorded before ratings existed
+    #[serde(default, flatten)]
+    pub ratings: db::Ratings,
+    /// Missing from revisions recorded before zones were kept
+    #[serde(default, flatten)]
+    pub zone: LoggedZone,
+}
+
+#[derive(Serialize)]
+pub struct WinRevision {
+    pub id: i64,
+    pub win_id: i64,
+    /// "create", "edit", "delete" or "restore"
+    pub action: String,
+    pub before: Option<WinSnapshot>,
+    pub after: Option<WinSnapshot>,
+    pub created_at: i64,
+    /// "done", "undone" (can be redone) or "discarded"
+    pub state: String,
+}
+
+#[derive(Serialize)]
+pub struct UndoState {
+    /// The revision that was just undone or redone, if any
+    pub applied: Option<WinRevision>,
+    pub can_undo: bool,
+    pub can_redo: bool,
+}
+
+/// Where a win lives on one side of a revision.
+enum Place {
+    Gone,
+    Log,
+    Trash,
+}
+
+fn places(action: &str) -> (Place, Place) {
+    match action {
+        "create" => (Place::Gone, Place::Log),
+        "delete" => (Place::Log, Place::Trash),
+        "restore" => (Place::Trash, Place::Log),
+        _ => (Place::Log, Place::Log),
+    }
+}
+
+/// Reads the current state of a win from `wins` or `deleted_wins`.
+pub(crate) fn snapshot(conn: &Connection, table: &str, id: i64) -> Result<Option<WinSnapshot>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM {} w WHERE w.id = ?1",
+            db::win_columns(),
+            table
+        ),
+        [id],
+        db::win_from_row,
+    )
+    .optional()
+    .map(|win| {
+        win.map(|w| WinSnapshot {
+            date: w.date,
+            text: w.text,
+            tags: w.tags,
+            created_at: w.created_at,
+            ratings: w.ratings,
+            zone: w.zone,
+        })
+    })
+}
+
+/// Records a change made inside the caller's transaction. Anything waiting
+/// to be redone is discarded, as in any editor.
+pub(crate) fn record(
+    conn: &Connection,
+    win_id: i64,
+    action: &str,
+    before: Option<&WinSnapshot>,
+    after: Option<&WinSnapshot>,
+) -> Result<()> {
+    if action == "edit" && before == after {
+        // Saving without changes shouldn't take an undo step
+        return Ok(());
+    }
+    conn.execute(
+        "UPDATE win_revisions SET state = 'discarded' WHERE state = 'undone'",
+        [],
+    )?;
+    conn.execute(
+        "INSERT INTO win_revisions (win_id, action, before, after, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        (
+            win_id,
+            action,
+            to_json(before),
+            to_json(after),
+            OffsetDateTime::now_utc().unix_timestamp(),
+        ),
+    )?;
+    Ok(())
+}
+
+/// Reverts the most recent change still in effect.
+pub fn undo(conn: &mut Connection) -> Result<UndoState> {
+    let tx = conn.transaction()?;
+    let revision = load(&tx, "WHERE state = 'done' ORDER BY id DESC LIMIT 1", [])?;
+    if let Some(rev) = &revision {
+        println!("[undo] Undoing {} of win {}", rev.action, rev.win_id);
+        let (before, _) = places(&rev.action);
+        apply(&tx, rev.win_id, before, rev.before.as_ref())?;
+        tx.execute(
+            "UPDATE win_revisions SET state = 'undone' WHERE id = ?1",
+            [rev.id],
+        )?;
+    }
+    tx.commit()?;
+    // Undoing a create may have left attachment files nothing points to
+    attachments::collect_garbage(conn);
+    finish(conn, revision)
+}
+
+/// Re-applies the oldest undone change.
+pub fn redo(conn: &mut Connection) -> Result<UndoState> {
+    let tx = conn.transaction()?;
+    let revision = load(&tx, "WHERE state = 'undone' ORDER BY id ASC LIMIT 1", [])?;
+    if let Some(rev) = &revision {
+        println!("[redo] Redoing {} of win {}", rev.action, rev.win_id);
+        let (_, after) = places(&rev.action);
+        apply(&tx, rev.win_id, after, rev.after.as_ref())?;
+        tx.execute(
+            "UPDATE win_revisions SET state = 'done' WHERE id = ?1",
+            [rev.id],
+        )?;
+    }
+    tx.commit()?;
+    finish(conn, revision)
+}
+
+pub fn undo_state(conn: &Connection) -> Result<UndoState> {
+    finish(conn, None)
+}
+
+/// Every recorded revision of one win, newest first.
+pub fn get_win_history(conn: &Connection, win_id: i64) -> Result<Vec<WinRevision>> {
+    let mut stmt = conn.prepare(&format!(
+        "{} WHERE win_id = ?1 ORDER BY id DESC",
+        REVISION_SELECT
+    ))?;
+    let revisions = stmt.query_map([win_id], revision_from_row)?;
+    revisions.collect()
+}
+
+/// Puts a win in the log back to the version saved in `revision_id` (its
+/// `after` side, or `before` for a delete). Recorded as a normal edit, so it
+/// can itself be undone.
+pub fn restore_version(conn: &mut Connection, revision_id: i64) -> Result<()> {
+    let tx = conn.transaction()?;
+    let rev =
+        load(&tx, "WHERE id = ?1", [revision_id])?.ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+    let version = rev
+        .after
+        .as_ref()
+        .or(rev.before.as_ref())
+        .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+    let Some(current) = snapshot(&tx, "wins", rev.win_id)? else {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "Restore this win from the trash before going back to an older version.",
+        ));
+    };
+    println!(
+        "[restore_version] Win {} back to revision {}",
+        rev.win_id, rev.id
+    );
+    let restored = WinSnapshot {
+        created_at: current.created_at,
+        zone: current.zone.clone(),
+        ..version.clone()
+    };
+    apply(&tx, rev.win_id, Place::Log, Some(&restored))?;
+    record(&tx, rev.win_id, "edit", Some(&current), Some(&restored))?;
+    tx.commit()
+}
+
+/// Moves a win to `place` with the contents of `snapshot`, whatever state it
+/// is in now (it may have been purged from the trash since). A win that is
+/// gone takes its attachments and goal and chain links with it; redoing its
+/// create brings back the win as it was logged.
+fn apply(conn: &Connection, id: i64, place: Place, snapshot: Option<&WinSnapshot>) -> Result<()> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    match (place, snapshot) {
+        (Place::Log, Some(s)) => {
+            conn.execute("DELETE FROM deleted_wins WHERE id = ?1", [id])?;
+            conn.execute(
+                "INSERT INTO wins
+                     (id, date, text, created_at, mood, energy, impact, utc_offset, time_zone)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                 ON CONFLICT(id) DO UPDATE SET date = excluded.date, text = excluded.text,
+                     mood = excluded.mood, energy = excluded.energy, impact = excluded.impact",
+                (
+                    id,
+                    &s.date,
+                    &s.text,
+                    s.created_at,
+                    s.ratings.mood,
+                    s.ratings.energy,
+                    s.ratings.impact,
+                    s.zone.utc_offset,
+                    &s.zone.time_zone,
+                ),
+            )?;
+            db::set_win_tags(conn, id, &s.tags)
+        }
+        (Place::Trash, Some(s)) => {
+            conn.execute("DELETE FROM wins WHERE id = ?1", [id])?;
+            conn.execute(
+                "INSERT OR REPLACE INTO deleted_wins (id, date, text, created_at,
+                     mood, energy, impact, utc_offset, time_zone, deleted_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                (
+                    id,
+                    &s.date,
+                    &s.text,
+                    s.created_at,
+                    s.ratings.mood,
+                    s.ratings.energy,
+                    s.ratings.impact,
+                    s.zone.utc_offset,
+                    &s.zone.time_zone,
+                    now,
+                ),
+            )?;
+            db::set_win_tags(conn, id, &s.tags)
+        }
+        _ => {
+            conn.execute("DELETE FROM wins WHERE id = ?1", [id])?;
+            conn.execute("DELETE FROM deleted_wins WHERE id = ?1", [id])?;
+            conn.execute("DELETE FROM win_tags WHERE win_id = ?1", [id])?;
+            conn.execute("DELETE FROM win_attachments WHERE win_id = ?1", [id])?;
+            conn.execute("DELETE FROM goal_wins WHERE win_id = ?1", [id])?;
+            conn.execute("DELETE FROM chain_wins WHERE win_id = ?1", [id])?;
+            Ok(())
+        }
+    }
+}
+
+const REVISION_SELECT: &str =
+    "SELECT id, win_id, action, before, after, created_at, state FROM win_revisions";
+
+fn load<P: rusqlite::Params>(
+    conn: &Connection,
+    clause: &str,
+    params: P,
+) -> Result<Option<WinRevision>> {
+    conn.query_row(
+        &format!("{} {}", REVISION_SELECT, clause),
+        params,
+        revision_from_row,
+    )
+    .optional()
+}
+
+fn revision_from_row(row: &rusqlite::Row) -> Result<WinRevision> {
+    Ok(WinRevision {
+        id: row.get(0)?,
+        win_id: row.get(1)?,
+        action: row.get(2)?,
+        before: from_json(row.get(3)?),
+        after: from_json(row.get(4)?),
+        created_at: row.get(5)?,
+        state: row.get(6)?,
+    })
+}
+
+fn finish(conn: &Connection, applied: Option<WinRevision>) -> Result<UndoState> {
+    let exists = |state: &str| -> Result<bool> {
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM win_revisions WHERE state = ?1)",
+            [state],
+            |row| row.get(0),
+        )
+    };
+    Ok(UndoState {
+        applied,
+        can_undo: exists("done")?,
+        can_redo: exists("undone")?,
+    })
+}
+
+fn to_json(snapshot: Option<&WinSnapshot>) -> Option<String> {
+    snapshot.and_then(|s| serde_json::to_string(s).ok())
+}
+
+fn from_json(json: Option<String>) -> Option<WinSnapshot> {
+    json.and_then(|s| serde_json::from_str(&s).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn journal() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        conn
+    }
+
+    fn add(conn: &mut Connection, text: &str) -> i64 {
+        db::add_win(conn, "2025-01-02", text, "mine", db::Ratings::default()).unwrap();
+        conn.query_row("SELECT MAX(id) FROM wins", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    /// Changes the text and keeps the tags, inferred ones included.
+    fn edit(conn: &mut Connection, id: i64, text: &str) {
+        let tags = snapshot(conn, "wins", id).unwrap().unwrap().tags.join(",");
+        db::update_win(conn, id, "2025-01-02", text, &tags, db::Ratings::default()).unwrap();
+    }
+
+    /// Where the win is now and its text.
+    fn state(conn: &Connection, id: i64) -> Option<(&'static str, String)> {
+        if let Some(s) = snapshot(conn, "wins", id).unwrap() {
+            return Some(("log", s.text));
+        }
+        snapshot(conn, "deleted_wins", id)
+            .unwrap()
+            .map(|s| ("trash", s.text))
+    }
+
+    fn log(text: &str) -> Option<(&'static str, String)> {
+        Some(("log", text.to_string()))
+    }
+
+    #[test]
+    fn undo_and_redo_walk_the_history() {
+        let mut conn = journal();
+        let id = add(&mut conn, "First go");
+        edit(&mut conn, id, "Second go");
+        db::delete_win(&mut conn, id).unwrap();
+        assert_eq!(state(&conn, id), Some(("trash", "Second go".to_string())));
+
+        let undone = undo(&mut conn).unwrap();
+        assert_eq!(undone.applied.unwrap().action, "delete");
+        assert_eq!(state(&conn, id), log("Second go"));
+        undo(&mut conn).unwrap();
+        assert_eq!(state(&conn, id), log("First go"));
+        let last = undo(&mut conn).unwrap();
+        assert_eq!(state(&conn, id), None);
+        assert!(!last.can_undo && last.can_redo);
+        assert!(undo(&mut conn).unwrap().applied.is_none());
+
+        // Redo replays oldest first
+        redo(&mut conn).unwrap();
+        assert_eq!(state(&conn, id), log("First go"));
+        let tags = snapshot(&conn, "wins", id).unwrap().unwrap().tags;
+        assert_eq!(tags, vec!["mine", "misc"]);
+        redo(&mut conn).unwrap();
+        redo(&mut conn).unwrap();
+        assert_eq!(state(&conn, id), Some(("trash", "Second go".to_string())));
+        let done = undo_state(&conn).unwrap();
+        assert!(done.can_undo && !done.can_redo);
+    }
+
+    #[test]
+    fn a_new_change_discards_what_could_be_redone() {
+        let mut conn = journal();
+        let id = add(&mut conn, "One");
+        edit(&mut conn, id, "Two");
+        undo(&mut conn).unwrap();
+        assert!(undo_state(&conn).unwrap().can_redo);
+        edit(&mut conn, id, "Three");
+        assert!(!undo_state(&conn).unwrap().can_redo);
+        assert!(redo(&mut conn).unwrap().applied.is_none());
+        let states: Vec<String> = get_win_history(&conn, id)
+            .unwrap()
+            .into_iter()
+            .map(|r| format!("{} {}", r.action, r.state))
+            .collect();
+        assert_eq!(states, vec!["edit done", "edit discarded", "create done"]);
+    }
+
+    #[test]
+    fn saving_without_changes_takes_no_step() {
+        let mut conn = journal();
+        let id = add(&mut conn, "Same");
+        edit(&mut conn, id, "Same");
+        assert_eq!(get_win_history(&conn, id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn restore_version_is_an_undoable_edit() {
+        let mut conn = journal();
+        let id = add(&mut conn, "Original");
+        edit(&mut conn, id, "Changed");
+        let create = get_win_history(&conn, id).unwrap().pop().unwrap();
+        assert_eq!(create.action, "create");
+        restore_version(&mut conn, create.id).unwrap();
+        assert_eq!(state(&conn, id), log("Original"));
+        let history = get_win_history(&conn, id).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].action, "edit");
+        undo(&mut conn).unwrap();
+        assert_eq!(state(&conn, id), log("Changed"));
+
+        // Not while the win is in the trash
+        db::delete_win(&mut conn, id).unwrap();
+        let err = restore_version(&mut conn, create.id).unwrap_err();
+        assert!(err.to_string().contains("from the trash"));
+    }
+
+    #[test]
+    fn undoing_a_create_leaves_nothing_behind() {
+        let mut conn = journal();
+        let id = add(&mut conn, "Attached and linked");
+        conn.execute_batch(&format!(
+            "INSERT INTO win_attachments (win_id, hash, file_name, mime, size_bytes, created_at)
+                 VALUES ({id}, 'abc', 'a.txt', 'text/plain', 1, 0);
+             INSERT INTO goals (name, created_at) VALUES ('Goal', 0);
+             INSERT INTO goal_wins (goal_id, win_id) VALUES (1, {id});
+             INSERT INTO chains (created_at) VALUES (0);
+             INSERT INTO chain_wins (win_id, chain_id, pinned) VALUES ({id}, 1, 1);"
+        ))
+        .unwrap();
+        undo(&mut conn).unwrap();
+        for table in ["win_tags", "win_attachments", "goal_wins", "chain_wins"] {
+            let left: i64 = conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE win_id = ?1", table),
+                    [id],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(left, 0, "{}", table);
+        }
+        // Ids are never handed out twice, so the undone win's revisions
+        // can't be mistaken for a new win's
+        let next = add(&mut conn, "Next");
+        assert!(next > id);
+        assert_eq!(get_win_history(&conn, next).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn editing_a_trashed_win_changes_nothing() {
+        let mut conn = journal();
+        let id = add(&mut conn, "Binned");
+        db::delete_win(&mut conn, id).unwrap();
+        let tags = snapshot(&conn, "deleted_wins", id).unwrap().unwrap().tags;
+        let revisions = get_win_history(&conn, id).unwrap().len();
+
+        let err = db::update_win(
+            &mut conn,
+            id,
+            "2025-01-03",
+            "Edited",
+            "brand-new",
+            db::Ratings::default(),
+        );
+        assert!(matches!(err, Err(rusqlite::Error::QueryReturnedNoRows)));
+        assert_eq!(
+            snapshot(&conn, "deleted_wins", id).unwrap().unwrap().tags,
+            tags
+        );
+        assert_eq!(get_win_history(&conn, id).unwrap().len(), revisions);
+        assert!(!undo_state(&conn).unwrap().can_redo);
+        let created: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM tags WHERE name = 'brand-new'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(created, 0);
+
+        // Nor does an id that never existed
+        let err = db::update_win(
+            &mut conn,
+            id + 1,
+            "2025-01-03",
+            "Ghost",
+            "mine",
+            db::Ratings::default(),
+        );
+        assert!(matches!(err, Err(rusqlite::Error::QueryReturnedNoRows)));
+        assert!(get_win_history(&conn, id + 1).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,685 @@
+// import.rs - Import wins from exported JSON/CSV files and Markdown journals
+
+use crate::clock::LoggedZone;
+use crate::db;
+use crate::export::{
+    self, ExportDocument, ExportFormat, EXPORT_FORMAT_NAME, EXPORT_FORMAT_VERSION,
+};
+use crate::history;
+use chrono::NaiveDate;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use time::OffsetDateTime;
+
+#[derive(Serialize)]
+pub struct ImportRow {
+    /// 1-based line (Markdown/CSV) or entry index (JSON) in the source file
+    pub line: usize,
+    pub date: String,
+    pub text: String,
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub ratings: db::Ratings,
+    /// True when the source had no tags and `infer_tags` supplied them
+    pub tags_inferred: bool,
+    pub error: Option<String>,
+    pub duplicate: bool,
+}
+
+#[derive(Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: Vec<ImportRow>,
+    pub valid: usize,
+    pub invalid: usize,
+    pub duplicates: usize,
+    pub imported: usize,
+}
+
+struct ParsedRow {
+    line: usize,
+    date: String,
+    text: String,
+    tags: Vec<String>,
+    ratings: db::Ratings,
+    created_at: Option<i64>,
+    zone: LoggedZone,
+    error: Option<String>,
+}
+
+impl ParsedRow {
+    fn new(line: usize, date: &str, text: &str, tags: Vec<String>) -> ParsedRow {
+        ParsedRow {
+            line,
+            date: date.trim().to_string(),
+            text: text.trim().to_string(),
+            tags,
+            ratings: db::Ratings::default(),
+            created_at: None,
+            zone: LoggedZone::default(),
+            error: None,
+        }
+    }
+}
+
+/// Parses `path`, validates and dedupes every row, and unless `dry_run` is set
+/// inserts all valid rows in a single transaction. Rows that fail validation
+/// or already exist (same date and text) are reported but never inserted.
+pub fn import_wins(
+    conn: &mut Connection,
+    path: &Path,
+    format: ExportFormat,
+    dry_run: bool,
+) -> Result<ImportReport, String> {
+    println!(
+        "[import_wins] {} {:?} from {}",
+        if dry_run { "Previewing" } else { "Importing" },
+        format,
+        path.display()
+    );
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let parsed = match format {
+        ExportFormat::Json => parse_json(&contents)?,
+        ExportFormat::Csv => parse_csv(&contents)?,
+        ExportFormat::Markdown => parse_markdown(&contents),
+    };
+
+    let mut existing = existing_keys(conn).map_err(|e| e.to_string())?;
+    let mut rows = Vec::with_capacity(parsed.len());
+    let mut to_insert = Vec::new();
+    for mut row in parsed {
+        if row.error.is_none() {
+            row.error = validate(&row);
+        }
+        let duplicate = row.error.is_none() && !existing.insert(dedupe_key(&row.date, &row.text));
+        let tags_inferred = row.tags.is_empty() && row.error.is_none();
+        if tags_inferred {
+            row.tags = db::infer_tags(&row.text);
+        }
+        if row.error.is_none() && !duplicate {
+            to_insert.push(rows.len());
+        }
+        rows.push((
+            (row.created_at, row.zone),
+            ImportRow {
+                line: row.line,
+                date: row.date,
+                text: row.text,
+                tags: row.tags,
+                ratings: row.ratings,
+                tags_inferred,
+                error: row.error,
+                duplicate,
+            },
+        ));
+    }
+
+    let mut imported = 0;
+    if !dry_run && !to_insert.is_empty() {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        for &i in &to_insert {
+            let ((created_at, zone), row) = &rows[i];
+            tx.execute(
+                "INSERT INTO wins
+                 (date, text, created_at, mood, energy, impact, utc_offset, time_zone)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                (
+                    &row.date,
+                    &row.text,
+                    created_at.unwrap_or(now),
+                    row.ratings.mood,
+                    row.ratings.energy,
+                    row.ratings.impact,
+                    zone.utc_offset,
+                    &zone.time_zone,
+                ),
+            )
+            .and_then(|_| {
+                // Each imported win is its own create, so undo takes them back
+                // one at a time like wins logged by hand
+                let id = tx.last_insert_rowid();
+                db::set_win_tags(&tx, id, &row.tags)?;
+                let after = history::snapshot(&tx, "wins", id)?;
+                history::record(&tx, id, "create", None, after.as_ref())
+            })
+            .map_err(|e| format!("line {}: {}", row.line, e))?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        imported = to_insert.len();
+    }
+
+    let rows: Vec<ImportRow> = rows.into_iter().map(|(_, row)| row).collect();
+    let invalid = rows.iter().filter(|r| r.error.is_some()).count();
+    let duplicates = rows.iter().filter(|r| r.duplicate).count();
+    println!(
+        "[import_wins] {} rows: {} invalid, {} duplicates, {} imported",
+        rows.len(),
+        invalid,
+        duplicates,
+        imported
+    );
+    Ok(ImportReport {
+        dry_run,
+        valid: rows.len() - invalid - duplicates,
+        rows,
+        invalid,
+        duplicates,
+        imported,
+    })
+}
+
+fn validate(row: &ParsedRow) -> Option<String> {
+    if NaiveDate::parse_from_str(&row.date, "%Y-%m-%d").is_err() {
+        return Some(format!("invalid date '{}', expected YYYY-MM-DD", row.date));
+    }
+    if row.text.is_empty() {
+        return Some("win text is empty".to_string());
+    }
+    row.ratings.validate().err().map(|e| e.to_string())
+}
+
+fn dedupe_key(date: &str, text: &str) -> (String, String) {
+    (date.trim().to_string(), text.trim().to_lowercase())
+}
+
+fn existing_keys(conn: &Connection) -> rusqlite::Result<HashSet<(String, String)>> {
+    let mut stmt = conn.prepare("SELECT date, text FROM wins")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(dedupe_key(
+            &row.get::<_, String>(0)?,
+            &row.get::<_, String>(1)?,
+        ))
+    })?;
+    rows.collect()
+}
+
+fn parse_json(contents: &str) -> Result<Vec<ParsedRow>, String> {
+    let doc: ExportDocument =
+        serde_json::from_str(contents).map_err(|e| format!("not a Quiet Wins export: {}", e))?;
+    if doc.format != EXPORT_FORMAT_NAME {
+        return Err(format!("unknown export format '{}'", doc.format));
+    }
+    if doc.version > EXPORT_FORMAT_VERSION {
+        return Err(format!(
+            "export version {} is newer than this app supports ({})",
+            doc.version, EXPORT_FORMAT_VERSION
+        ));
+    }
+    // Trashed wins in the export stay trashed; only live wins are imported
+    Ok(doc
+        .wins
+        .into_iter()
+        .enumerate()
+        .map(|(i, win)| {
+            let tags = db::parse_tag_string(&win.tags.join(","));
+            let mut row = ParsedRow::new(i + 1, &win.date, &win.text, tags);
+            row.created_at = win.created_at;
+            row.ratings = win.ratings;
+            row.zone = win.zone;
+            row
+        })
+        .collect())
+}
+
+/// Reads RFC 4180 CSV with a header row. Only `date` and `text` columns are
+/// required; `tags` may be separated by commas or semicolons.
+fn parse_csv(contents: &str) -> Result<Vec<ParsedRow>, String> {
+    let mut records = read_csv_records(contents)?.into_iter();
+    let (_, header) = records.next().ok_or("CSV file is empty")?;
+    let columns: HashMap<String, usize> = header
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.trim().trim_start_matches('\u{feff}').to_lowercase(), i))
+        .collect();
+    let date_col = *columns
+        .get("date")
+        .ok_or("CSV is missing a 'date' column")?;
+    let text_col = *columns
+        .get("text")
+        .ok_or("CSV is missing a 'text' column")?;
+    let field = |record: &[String], name: &str| -> String {
+        columns
+            .get(name)
+            .and_then(|&i| record.get(i))
+            .cloned()
+            .unwrap_or_default()
+    };
+    let mut rows = Vec::new();
+    for (line, record) in records {
+        if record.iter().all(|f| f.trim().is_empty()) {
+            continue;
+        }
+        let tags = db::parse_tag_string(&field(&record, "tags").replace(';', ","));
+        let mut row = ParsedRow::new(
+            line,
+            record.get(date_col).map(String::as_str).unwrap_or(""),
+            record.get(text_col).map(String::as_str).unwrap_or(""),
+            tags,
+        );
+        row.created_at = field(&record, "created_at").trim().parse().ok();
+        let number = |name: &str| -> Result<Option<i64>, String> {
+            let value = field(&record, name);
+            let value = value.trim();
+            if value.is_empty() {
+                return Ok(None);
+            }
+            value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{} '{}' is not a number", name, value))
+        };
+        match (number("mood"), number("energy"), number("impact")) {
+            (Ok(mood), Ok(energy), Ok(impact)) => {
+                row.ratings = db::Ratings {
+                    mood,
+                    energy,
+                    impact,
+                }
+            }
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => row.error = Some(e),
+        }
+        // Minutes east of UTC and the IANA name, as exported
+        match number("utc_offset") {
+            Ok(offset) => row.zone.utc_offset = offset.and_then(|m| i32::try_from(m).ok()),
+            Err(e) => row.error = Some(e),
+        }
+        row.zone.time_zone =
+            Some(field(&record, "time_zone").trim().to_string()).filter(|z| !z.is_empty());
+        if !field(&record, "deleted_at").trim().is_empty() {
+            row.error = Some("row was in the trash when exported; skipped".to_string());
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Splits CSV text into records, each paired with the line it starts on.
+fn read_csv_records(contents: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!(
+            "unterminated quoted field starting on line {}",
+            record_line
+        ));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+    Ok(records)
+}
+
+/// Reads heading-per-date journals: any heading containing a YYYY-MM-DD date
+/// starts a day, and each bullet or plain line below it is one win. Tags come
+/// from a trailing ` — *a, b*` (as written by the exporter) or trailing
+/// `#hashtags` (but not numbers like `#1`). A `Trash` heading ends the import.
+fn parse_markdown(contents: &str) -> Vec<ParsedRow> {
+    let mut rows: Vec<ParsedRow> = Vec::new();
+    let mut current_date: Option<String> = None;
+    let mut open_entry = false;
+    for (i, raw) in contents.lines().enumerate() {
+        let line_no = i + 1;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() {
+            // The exporter indents blank lines inside a win too
+            match rows.last_mut() {
+                Some(last) if open_entry && raw.starts_with("  ") => last.text.push('\n'),
+                _ => open_entry = false,
+            }
+            continue;
+        }
+        if is_heading(trimmed) {
+            let heading = trimmed.trim_start_matches('#').trim();
+            if heading.eq_ignore_ascii_case("trash") {
+                break;
+            }
+            if let Some(date) = find_date(heading) {
+                current_date = Some(date);
+            }
+            open_entry = false;
+            continue;
+        }
+        // Indented lines continue the previous bullet; attachment links are
+        // written by the exporter and aren't part of the text
+        if open_entry && (raw.starts_with("  ") || raw.starts_with('\t')) {
+            if trimmed.starts_with(export::ATTACHMENT_MARK) {
+                continue;
+            }
+            if let Some(last) = rows.last_mut() {
+                last.text.push('\n');
+                last.text.push_str(trimmed);
+            }
+            continue;
+        }
+        let date = current_date.as_deref().unwrap_or("");
+        let mut row = ParsedRow::new(line_no, date, strip_bullet(trimmed), Vec::new());
+        if current_date.is_none() {
+            row.error = Some("entry is not under a dated heading".to_string());
+        }
+        rows.push(row);
+        open_entry = true;
+    }
+    for row in &mut rows {
+        let (text, tags) = split_markdown_tags(&row.text);
+        row.text = text;
+        row.tags = tags;
+    }
+    rows
+}
+
+fn is_heading(line: &str) -> bool {
+    let rest = line.trim_start_matches('#');
+    line.starts_with('#') && (rest.is_empty() || rest.starts_with(' '))
+}
+
+fn find_date(heading: &str) -> Option<String> {
+    heading
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '(' || c == ')')
+        .find(|word| NaiveDate::parse_from_str(word, "%Y-%m-%d").is_ok())
+        .map(|word| word.to_string())
+}
+
+fn strip_bullet(line: &str) -> &str {
+    let body = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|b| line.strip_prefix(b))
+        .unwrap_or(line);
+    ["[ ] ", "[x] ", "[X] "]
+        .iter()
+        .find_map(|b| body.strip_prefix(b))
+        .unwrap_or(body)
+}
+
+fn split_markdown_tags(text: &str) -> (String, Vec<String>) {
+    let text = text.trim();
+    if let Some((body, tail)) = text.rsplit_once(" — ") {
+        let tail = tail.trim();
+        if tail.len() > 2 && tail.starts_with('*') && tail.ends_with('*') {
+            let tags = db::parse_tag_string(tail.trim_matches('*'));
+            return (body.trim().to_string(), tags);
+        }
+    }
+    let mut words: Vec<&str> = text.split(' ').collect();
+    let mut tags = Vec::new();
+    while let Some(word) = words.last() {
+        match word.strip_prefix('#') {
+            // All-digit hashtags like "#1" are numbers, not tags
+            Some(tag)
+                if !tag.starts_with('#')
+                    && !tag.chars().all(|c| c.is_ascii_digit())
+                    && words.len() > 1 =>
+            {
+                tags.push(tag.to_string());
+                words.pop();
+            }
+            _ => break,
+        }
+    }
+    tags.reverse();
+    (words.join(" ").trim().to_string(), tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{ExportedAttachment, ExportedWin};
+    use crate::migrations;
+
+    fn exported(id: i64, date: &str, text: &str, tags: &[&str]) -> ExportedWin {
+        ExportedWin {
+            id: Some(id),
+            date: date.to_string(),
+            text: text.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            created_at: Some(1_735_800_000 + id),
+            deleted_at: None,
+            ratings: db::Ratings::default(),
+            zone: LoggedZone::default(),
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Wins whose text the CSV and Markdown layouts have to work around.
+    fn awkward_wins() -> Vec<ExportedWin> {
+        let mut rated = exported(2, "2025-01-02", "Said \"no\" to a meeting", &["work"]);
+        rated.ratings = db::Ratings {
+            mood: Some(4),
+            energy: None,
+            impact: Some(5),
+        };
+        rated.zone = LoggedZone {
+            utc_offset: Some(-300),
+            time_zone: Some("America/New_York".to_string()),
+        };
+        let mut attached = exported(5, "2025-01-03", "Got the certificate", &[]);
+        attached.attachments.push(ExportedAttachment {
+            file_name: "cert.pdf".to_string(),
+            mime: "application/pdf".to_string(),
+            sha256: "abc".to_string(),
+            path: "wins-attachments/abc.pdf".to_string(),
+        });
+        vec![
+            exported(
+                1,
+                "2025-01-02",
+                "Ran 5k, then stretched",
+                &["run", "health"],
+            ),
+            rated,
+            exported(
+                3,
+                "2025-01-03",
+                "First line\nsecond line\n\nafter a gap",
+                &["writing"],
+            ),
+            exported(4, "2025-01-03", "Plain win, no tags", &[]),
+            attached,
+        ]
+    }
+
+    fn summary(rows: &[ParsedRow]) -> Vec<(String, String, Vec<String>)> {
+        rows.iter()
+            .map(|r| (r.date.clone(), r.text.clone(), r.tags.clone()))
+            .collect()
+    }
+
+    fn expected(wins: &[ExportedWin]) -> Vec<(String, String, Vec<String>)> {
+        wins.iter()
+            .map(|w| (w.date.clone(), w.text.clone(), w.tags.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let wins = awkward_wins();
+        let trashed = ExportedWin {
+            deleted_at: Some(1_735_900_000),
+            ..exported(6, "2025-01-04", "Trashed, \"quoted\"", &[])
+        };
+        let csv = export::to_csv(&wins, &[trashed]);
+        let rows = parse_csv(&csv).unwrap();
+        assert_eq!(rows.len(), 6);
+        assert_eq!(summary(&rows[..5]), expected(&wins));
+        assert!(rows[..5].iter().all(|r| r.error.is_none()));
+        assert_eq!(rows[1].ratings, wins[1].ratings);
+        assert_eq!(rows[1].zone, wins[1].zone);
+        assert_eq!(rows[0].zone, LoggedZone::default());
+        assert_eq!(rows[0].created_at, Some(1_735_800_001));
+        // The header is line 1; the multi-line win starts on line 4 and takes
+        // four lines, so the win after it is on line 8
+        assert_eq!(rows[2].line, 4);
+        assert_eq!(rows[3].line, 8);
+        assert_eq!(rows[5].text, "Trashed, \"quoted\"");
+        assert!(rows[5].error.as_deref().unwrap().contains("trash"));
+    }
+
+    #[test]
+    fn csv_quirks() {
+        // LF endings, a BOM, a missing trailing newline, semicolon tags and
+        // columns in another order
+        let csv =
+            "\u{feff}Text,Date,Tags\n\"a, b\",2025-01-01,x; y\n\"say \"\"hi\"\"\",2025-01-02,";
+        let rows = parse_csv(csv).unwrap();
+        assert_eq!(
+            summary(&rows),
+            vec![
+                (
+                    "2025-01-01".to_string(),
+                    "a, b".to_string(),
+                    vec!["x".to_string(), "y".to_string()]
+                ),
+                ("2025-01-02".to_string(), "say \"hi\"".to_string(), vec![]),
+            ]
+        );
+        let rows = parse_csv(
+            "date,text,utc_offset,time_zone\n2025-01-01,a,+330,Asia/Kolkata\n2025-01-02,b,east,\n",
+        )
+        .unwrap();
+        assert_eq!(rows[0].zone.utc_offset, Some(330));
+        assert_eq!(rows[0].zone.time_zone.as_deref(), Some("Asia/Kolkata"));
+        assert!(rows[1].error.as_deref().unwrap().contains("utc_offset"));
+        assert!(parse_csv("date,text\n\"open,2025").is_err());
+        assert!(parse_csv("when,what\n2025-01-01,x").is_err());
+    }
+
+    #[test]
+    fn markdown_round_trip() {
+        let wins = awkward_wins();
+        let trashed = exported(6, "2025-01-04", "Trashed win", &["misc"]);
+        let markdown = export::to_markdown(&wins, &[trashed]);
+        let rows = parse_markdown(&markdown);
+        // Markdown lists each day's wins in export order; the Trash section
+        // isn't imported
+        assert_eq!(summary(&rows), expected(&wins));
+        assert!(rows.iter().all(|r| r.error.is_none()));
+    }
+
+    #[test]
+    fn markdown_journals() {
+        let markdown = "# Journal\n\
+            stray line\n\
+            ## Monday 2025-01-06\n\
+            - [x] Shipped the release #work #release\n\
+            * Lunch with Sam #\n\
+            \n\
+            ### Notes (2025-01-07)\n\
+            Plain line\n  that continues #c#\n\
+            + Price is #1 #money\n";
+        let rows = parse_markdown(markdown);
+        assert_eq!(
+            rows[0].error.as_deref(),
+            Some("entry is not under a dated heading")
+        );
+        assert_eq!(
+            summary(&rows[1..]),
+            vec![
+                (
+                    "2025-01-06".to_string(),
+                    "Shipped the release".to_string(),
+                    vec!["work".to_string(), "release".to_string()]
+                ),
+                (
+                    "2025-01-06".to_string(),
+                    "Lunch with Sam #".to_string(),
+                    vec![]
+                ),
+                (
+                    "2025-01-07".to_string(),
+                    "Plain line\nthat continues".to_string(),
+                    vec!["c#".to_string()]
+                ),
+                (
+                    "2025-01-07".to_string(),
+                    "Price is #1".to_string(),
+                    vec!["money".to_string()]
+                ),
+            ]
+        );
+        // A hashtag on its own is the text, not a tag
+        assert_eq!(
+            split_markdown_tags("#focus"),
+            ("#focus".to_string(), vec![])
+        );
+    }
+
+    fn import_file(
+        conn: &mut Connection,
+        name: &str,
+        contents: &str,
+        dry_run: bool,
+    ) -> ImportReport {
+        let path = std::env::temp_dir().join(format!("quietwins-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let report = import_wins(conn, &path, ExportFormat::Csv, dry_run);
+        fs::remove_file(&path).unwrap();
+        report.unwrap()
+    }
+
+    #[test]
+    fn imports_can_be_undone() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        let csv = "date,text,tags\n2025-01-01,Walked the dog,walk\n2025-01-02,Read a book,\n";
+        let preview = import_file(&mut conn, "preview.csv", csv, true);
+        assert_eq!((preview.valid, preview.imported), (2, 0));
+        assert!(!history::undo_state(&conn).unwrap().can_undo);
+
+        let report = import_file(&mut conn, "undo.csv", csv, false);
+        assert_eq!(report.imported, 2);
+        let count = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM wins", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count(&conn), 2);
+        history::undo(&mut conn).unwrap();
+        assert_eq!(count(&conn), 1);
+        let state = history::undo(&mut conn).unwrap();
+        assert_eq!(count(&conn), 0);
+        assert!(!state.can_undo && state.can_redo);
+        history::redo(&mut conn).unwrap();
+        history::redo(&mut conn).unwrap();
+        assert_eq!(count(&conn), 2);
+        // Importing the same file again only finds duplicates
+        let again = import_file(&mut conn, "again.csv", csv, false);
+        assert_eq!((again.duplicates, again.imported), (2, 0));
+    }
+}
@@ -2,8 +2,72 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 #[tauri::command]
-fn get_wins_with_chains(app: tauri::AppHandle) -> Result<Vec<db::WinWithChain>, String> {
-    db::get_wins_with_chains(&app).map_err(|e| e.to_string())
+fn get_wins_with_chains(
+    state: tauri::State<db::Db>,
+    query: query::WinQuery,
+) -> Result<chains::ChainedWins, String> {
+    let settings = chains::get_settings(&state);
+    state
+        .with(|conn| chains::get_wins_with_chains(conn, &settings, &query))
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+fn get_chain_timeline(
+    state: tauri::State<db::Db>,
+    chain_id: i64,
+) -> Result<chains::ChainTimeline, String> {
+    let settings = chains::get_settings(&state);
+    state
+        .with(|conn| chains::get_chain_timeline(conn, chain_id, &settings, clock::today()))
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+fn get_chain_timelines(state: tauri::State<db::Db>) -> Result<Vec<chains::ChainTimeline>, String> {
+    let settings = chains::get_settings(&state);
+    state
+        .with(|conn| chains::get_chain_timelines(conn, &settings, clock::today()))
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+fn get_chain_settings(state: tauri::State<db::Db>) -> chains::ChainSettings {
+    chains::get_settings(&state)
+}
+#[tauri::command]
+fn set_chain_settings(
+    state: tauri::State<db::Db>,
+    settings: chains::ChainSettings,
+) -> Result<(), String> {
+    chains::set_settings(&state, &settings)
+}
+#[tauri::command]
+fn add_win_to_chain(state: tauri::State<db::Db>, chain_id: i64, win_id: i64) -> Result<(), String> {
+    state
+        .with(|conn| chains::add_to_chain(conn, chain_id, win_id))
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+fn remove_win_from_chain(state: tauri::State<db::Db>, win_id: i64) -> Result<(), String> {
+    state
+        .with(|conn| chains::remove_from_chain(conn, win_id))
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+fn new_chain(state: tauri::State<db::Db>, win_ids: Vec<i64>) -> Result<i64, String> {
+    state
+        .with(|conn| chains::new_chain(conn, &win_ids))
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+fn split_chain(state: tauri::State<db::Db>, chain_id: i64, from_win: i64) -> Result<i64, String> {
+    state
+        .with(|conn| chains::split_chain(conn, chain_id, from_win))
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+fn merge_chains(state: tauri::State<db::Db>, chain_ids: Vec<i64>) -> Result<i64, String> {
+    state
+        .with(|conn| chains::merge_chains(conn, &chain_ids))
+        .map_err(|e| e.to_string())
 }
 #[tauri::command]
 fn suggest_tags_for_text(text: String) -> Vec<String> {
@@ -46,12 +110,47 @@ fn get_notif_time(app: tauri::AppHandle) -> Result<String, String> {
     Ok(buf.trim().to_string())
 }
 #[tauri::command]
-fn get_tag_graph(app: tauri::AppHandle) -> Result<db::TagGraph, String> {
-    db::get_tag_graph(&app).map_err(|e| e.to_string())
+fn get_tag_graph(
+    state: tauri::State<db::Db>,
+    filter: Option<graph::GraphFilter>,
+) -> Result<graph::TagGraph, String> {
+    state
+        .with(|conn| graph::get_tag_graph(conn, &filter.unwrap_or_default()))
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+fn get_tag_clusters(
+    state: tauri::State<db::Db>,
+    filter: Option<graph::GraphFilter>,
+) -> Result<clusters::TagClusters, String> {
+    state
+        .with(|conn| clusters::get_tag_clusters(conn, &filter.unwrap_or_default()))
+        .map_err(|e| e.to_string())
 }
+mod attachments;
+mod backup;
+mod chains;
+mod clock;
+mod clusters;
 mod db;
+mod encryption;
+mod errors;
+mod export;
+mod goals;
+mod graph;
+mod history;
+mod import;
+mod migrations;
 mod mock_data;
 pub mod nlp;
+mod query;
+mod recovery;
+mod search;
+mod similarity;
+mod stats;
+mod streaks;
+mod tags;
+mod trash;
 mod tray;
 
 use tauri::menu::{Menu, MenuItemBuilder};
@@ -60,12 +159,21 @@ use tauri::{WebviewUrl, WebviewWindowBuilder};
 use tauri_plugin_notification::NotificationExt;
 
 #[tauri::command]
-fn add_win(app: tauri::AppHandle, date: String, text: String, tags: String) -> Result<(), String> {
+fn add_win(
+    state: tauri::State<db::Db>,
+    date: Option<String>,
+    text: String,
+    tags: String,
+    ratings: Option<db::Ratings>,
+) -> Result<(), String> {
+    // Without a date the win is for today where the user is
+    let date = date.unwrap_or_else(|| clock::today().format("%Y-%m-%d").to_string());
     println!(
         "[add_win command] called with date: {}, text: {}, tags: {}",
         date, text, tags
     );
-    match db::add_win(&app, &date, &text, &tags) {
+    let ratings = ratings.unwrap_or_default();
+    match state.with(|conn| db::add_win(conn, &date, &text, &tags, ratings)) {
         Ok(res) => {
             println!("[add_win command] success");
             Ok(res)
@@ -79,34 +187,44 @@ fn add_win(app: tauri::AppHandle, date: String, text: String, tags: String) -> R
 
 #[tauri::command]
 fn update_win(
-    app: tauri::AppHandle,
+    state: tauri::State<db::Db>,
     id: i64,
     date: String,
     text: String,
     tags: String,
+    ratings: Option<db::Ratings>,
 ) -> Result<(), String> {
-    db::update_win(&app, id, &date, &text, &tags).map_err(|e| e.to_string())
+    let ratings = ratings.unwrap_or_default();
+    state
+        .with(|conn| db::update_win(conn, id, &date, &text, &tags, ratings))
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn delete_win(app: tauri::AppHandle, id: i64) -> Result<(), String> {
-    db::delete_win(&app, id).map_err(|e| e.to_string())
+fn delete_win(state: tauri::State<db::Db>, id: i64) -> Result<(), String> {
+    state
+        .with(|conn| db::delete_win(conn, id))
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn restore_win(app: tauri::AppHandle, id: i64) -> Result<(), String> {
-    db::restore_win(&app, id).map_err(|e| e.to_string())
+fn restore_win(state: tauri::State<db::Db>, id: i64) -> Result<(), String> {
+    state
+        .with(|conn| db::restore_win(conn, id))
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn get_deleted_wins(app: tauri::AppHandle) -> Result<Vec<db::Win>, String> {
-    db::get_deleted_wins(&app).map_err(|e| e.to_string())
+fn get_deleted_wins(state: tauri::State<db::Db>) -> Result<Vec<db::Win>, String> {
+    state
+        .with(|conn| db::get_deleted_wins(conn))
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn get_wins(app: tauri::AppHandle) -> Result<Vec<db::Win>, String> {
+fn get_wins(state: tauri::State<db::Db>) -> Result<Vec<db::Win>, String> {
     println!("[get_wins command] called");
-    match db::get_wins(&app) {
+    match state.with(|conn| db::get_wins(conn)) {
         Ok(wins) => {
             println!("[get_wins command] success, returning {} wins", wins.len());
             Ok(wins)
@@ -118,12 +236,484 @@ fn get_wins(app: tauri::AppHandle) -> Result<Vec<db::Win>, String> {
     }
 }
 
+#[tauri::command]
+fn search_wins(
+    state: tauri::State<db::Db>,
+    query: String,
+    tags: Option<Vec<String>>,
+    limit: Option<usize>,
+) -> Result<Vec<search::SearchHit>, String> {
+    state
+        .with(|conn| {
+            search::search_wins(conn, &query, &tags.unwrap_or_default(), limit.unwrap_or(50))
+        })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn query_wins(
+    state: tauri::State<db::Db>,
+    query: query::WinQuery,
+) -> Result<query::WinPage, String> {
+    state
+        .with(|conn| query::query_wins(conn, &query))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn export_wins(
+    state: tauri::State<db::Db>,
+    path: String,
+    format: export::ExportFormat,
+    options: Option<export::ExportOptions>,
+) -> Result<export::ExportSummary, String> {
+    let options = options.unwrap_or_default();
+    state
+        .with(|conn| {
+            Ok(export::export_wins(
+                conn,
+                std::path::Path::new(&path),
+                format,
+                &options,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn import_wins(
+    state: tauri::State<db::Db>,
+    path: String,
+    format: export::ExportFormat,
+    dry_run: bool,
+) -> Result<import::ImportReport, String> {
+    state
+        .with(|conn| {
+            Ok(import::import_wins(
+                conn,
+                std::path::Path::new(&path),
+                format,
+                dry_run,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn get_encryption_status(state: tauri::State<db::Db>) -> encryption::EncryptionStatus {
+    encryption::status(&state)
+}
+
+#[tauri::command]
+fn unlock_db(state: tauri::State<db::Db>, passphrase: String) -> Result<(), String> {
+    state.unlock(&passphrase).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn enable_encryption(state: tauri::State<db::Db>, passphrase: String) -> Result<(), String> {
+    encryption::enable_encryption(&state, &passphrase).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn change_passphrase(
+    state: tauri::State<db::Db>,
+    current: String,
+    new: String,
+) -> Result<(), String> {
+    encryption::change_passphrase(&state, &current, &new).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn disable_encryption(state: tauri::State<db::Db>, passphrase: String) -> Result<(), String> {
+    encryption::disable_encryption(&state, &passphrase).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_backups(state: tauri::State<db::Db>) -> Vec<backup::BackupInfo> {
+    backup::list_backups(&state)
+}
+
+#[tauri::command]
+fn create_backup(state: tauri::State<db::Db>) -> Result<backup::BackupInfo, String> {
+    backup::create_backup(&state).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn restore_backup(state: tauri::State<db::Db>, file_name: String) -> Result<(), String> {
+    backup::restore_backup(&state, &file_name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_backup_retention(state: tauri::State<db::Db>) -> backup::RetentionPolicy {
+    backup::get_retention(&state)
+}
+
+#[tauri::command]
+fn set_backup_retention(
+    state: tauri::State<db::Db>,
+    policy: backup::RetentionPolicy,
+) -> Result<(), String> {
+    backup::set_retention(&state, policy)
+}
+
+#[tauri::command]
+fn get_recovery_report(state: tauri::State<db::Db>) -> Option<recovery::RecoveryReport> {
+    state.recovery_report()
+}
+
+#[tauri::command]
+fn dismiss_recovery_report(state: tauri::State<db::Db>) {
+    state.dismiss_recovery_report();
+}
+
+#[tauri::command]
+fn get_trash_settings(state: tauri::State<db::Db>) -> trash::TrashSettings {
+    trash::get_settings(&state)
+}
+
+#[tauri::command]
+fn set_trash_settings(
+    state: tauri::State<db::Db>,
+    settings: trash::TrashSettings,
+) -> Result<(), String> {
+    trash::set_settings(&state, settings)
+}
+
+#[tauri::command]
+fn empty_trash(state: tauri::State<db::Db>) -> Result<usize, String> {
+    state.with(trash::empty_trash).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn purge_win(state: tauri::State<db::Db>, id: i64) -> Result<(), String> {
+    state
+        .with(|conn| trash::purge_win(conn, id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_wins(
+    state: tauri::State<db::Db>,
+    ids: Vec<i64>,
+) -> Result<Vec<trash::BatchResult>, String> {
+    state
+        .with(|conn| trash::delete_wins(conn, &ids))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn restore_wins(
+    state: tauri::State<db::Db>,
+    ids: Vec<i64>,
+) -> Result<Vec<trash::BatchResult>, String> {
+    state
+        .with(|conn| trash::restore_wins(conn, &ids))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn add_attachment(
+    state: tauri::State<db::Db>,
+    win_id: i64,
+    path: String,
+) -> Result<attachments::Attachment, String> {
+    state
+        .with(|conn| attachments::add_attachment(conn, win_id, std::path::Path::new(&path)))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_attachments(
+    state: tauri::State<db::Db>,
+    win_id: i64,
+) -> Result<Vec<attachments::Attachment>, String> {
+    state
+        .with(|conn| attachments::list_attachments(conn, win_id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_attachment_thumbnail(
+    state: tauri::State<db::Db>,
+    id: i64,
+) -> Result<Option<Vec<u8>>, String> {
+    state
+        .with(|conn| attachments::get_thumbnail(conn, id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn open_attachment(state: tauri::State<db::Db>, id: i64) -> Result<(), String> {
+    let path = state
+        .with(|conn| attachments::attachment_file(conn, id))
+        .map_err(|e| e.to_string())?;
+    tauri_plugin_opener::open_path(path, None::<&str>).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_attachment(state: tauri::State<db::Db>, id: i64) -> Result<(), String> {
+    state
+        .with(|conn| attachments::remove_attachment(conn, id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn undo(state: tauri::State<db::Db>) -> Result<history::UndoState, String> {
+    state.with(history::undo).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn redo(state: tauri::State<db::Db>) -> Result<history::UndoState, String> {
+    state.with(history::redo).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_undo_state(state: tauri::State<db::Db>) -> Result<history::UndoState, String> {
+    state
+        .with(|conn| history::undo_state(conn))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_win_history(
+    state: tauri::State<db::Db>,
+    win_id: i64,
+) -> Result<Vec<history::WinRevision>, String> {
+    state
+        .with(|conn| history::get_win_history(conn, win_id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn restore_win_version(state: tauri::State<db::Db>, revision_id: i64) -> Result<(), String> {
+    state
+        .with(|conn| history::restore_version(conn, revision_id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_goals(state: tauri::State<db::Db>) -> Result<Vec<goals::Goal>, String> {
+    state
+        .with(|conn| goals::list_goals(conn))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_goal(state: tauri::State<db::Db>, goal: goals::GoalInput) -> Result<goals::Goal, String> {
+    state
+        .with(|conn| goals::create_goal(conn, &goal))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_goal(
+    state: tauri::State<db::Db>,
+    id: i64,
+    goal: goals::GoalInput,
+) -> Result<goals::Goal, String> {
+    state
+        .with(|conn| goals::update_goal(conn, id, &goal))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_goal(state: tauri::State<db::Db>, id: i64) -> Result<(), String> {
+    state
+        .with(|conn| goals::delete_goal(conn, id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn link_win_to_goal(state: tauri::State<db::Db>, goal_id: i64, win_id: i64) -> Result<(), String> {
+    state
+        .with(|conn| goals::link_win(conn, goal_id, win_id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn unlink_win_from_goal(
+    state: tauri::State<db::Db>,
+    goal_id: i64,
+    win_id: i64,
+) -> Result<(), String> {
+    state
+        .with(|conn| goals::unlink_win(conn, goal_id, win_id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_goals_for_win(
+    state: tauri::State<db::Db>,
+    win_id: i64,
+) -> Result<Vec<goals::WinGoal>, String> {
+    state
+        .with(|conn| goals::goals_for_win(conn, win_id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_goal_progress(
+    state: tauri::State<db::Db>,
+    goal_id: i64,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<goals::GoalProgress, String> {
+    state
+        .with(|conn| goals::get_goal_progress(conn, goal_id, from.as_deref(), to.as_deref()))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_recap(
+    state: tauri::State<db::Db>,
+    period: stats::RecapPeriod,
+) -> Result<stats::Recap, String> {
+    state
+        .with(|conn| stats::get_recap(conn, period, clock::today()))
+        .map_err(|e| e.to_string())
+}
+
+/// Uses the saved rules unless `rules` is given, e.g. to preview a change.
+#[tauri::command]
+fn get_streaks(
+    state: tauri::State<db::Db>,
+    rules: Option<streaks::StreakRules>,
+) -> Result<streaks::Streaks, String> {
+    let rules = rules.unwrap_or_else(|| streaks::get_rules(&state));
+    state
+        .with(|conn| streaks::get_streaks(conn, &rules, clock::today()))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_streak_rules(state: tauri::State<db::Db>) -> streaks::StreakRules {
+    streaks::get_rules(&state)
+}
+
+#[tauri::command]
+fn set_streak_rules(
+    state: tauri::State<db::Db>,
+    rules: streaks::StreakRules,
+) -> Result<(), String> {
+    streaks::set_rules(&state, &rules)
+}
+
+#[tauri::command]
+fn list_rest_days(state: tauri::State<db::Db>) -> Result<Vec<streaks::RestDay>, String> {
+    state
+        .with(|conn| streaks::list_rest_days(conn))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_rest_day(
+    state: tauri::State<db::Db>,
+    date: String,
+    note: Option<String>,
+) -> Result<(), String> {
+    state
+        .with(|conn| streaks::set_rest_day(conn, &date, note.as_deref().unwrap_or("")))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_rest_day(state: tauri::State<db::Db>, date: String) -> Result<(), String> {
+    state
+        .with(|conn| streaks::remove_rest_day(conn, &date))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_tags(state: tauri::State<db::Db>) -> Result<Vec<tags::TagInfo>, String> {
+    state
+        .with(|conn| tags::list_tags(conn))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn rename_tag(
+    state: tauri::State<db::Db>,
+    from: String,
+    to: String,
+) -> Result<tags::TagChange, String> {
+    state
+        .with(|conn| tags::rename_tag(conn, &from, &to))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn merge_tags(
+    state: tauri::State<db::Db>,
+    sources: Vec<String>,
+    into: String,
+) -> Result<tags::TagChange, String> {
+    state
+        .with(|conn| tags::merge_tags(conn, &sources, &into))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn add_tag_alias(
+    state: tauri::State<db::Db>,
+    alias: String,
+    tag: String,
+) -> Result<tags::TagChange, String> {
+    state
+        .with(|conn| tags::add_alias(conn, &alias, &tag))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_tag_alias(
+    state: tauri::State<db::Db>,
+    alias: String,
+) -> Result<tags::TagChange, String> {
+    state
+        .with(|conn| tags::remove_alias(conn, &alias))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_tag_parent(
+    state: tauri::State<db::Db>,
+    tag: String,
+    parent: Option<String>,
+) -> Result<tags::TagChange, String> {
+    state
+        .with(|conn| tags::set_parent(conn, &tag, parent.as_deref()))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_tag_changes(state: tauri::State<db::Db>) -> Result<Vec<tags::TagChange>, String> {
+    state
+        .with(|conn| tags::list_tag_changes(conn))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn undo_tag_change(state: tauri::State<db::Db>) -> Result<Option<tags::TagChange>, String> {
+    state
+        .with(|conn| tags::undo_tag_change(conn))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn did_you_mean(state: tauri::State<db::Db>, tag: String) -> Result<Option<String>, String> {
+    state
+        .with(|conn| tags::did_you_mean(conn, &tag))
+        .map_err(|e| e.to_string())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_notification::init())
         .setup(|app| {
+            // Open the database once; every command shares this connection
+            let database = db::Db::open(app.handle())?;
+            app.manage(database);
             #[cfg(debug_assertions)]
             {
                 /* let _ = db::insert_mock_data(app.handle()); */
@@ -227,14 +817,20 @@ pub fn run() {
                 }
             });
             // ...existing code...
-            // Cleanup old deleted wins (every hour, 48hr retention)
+            // Purge expired trash every hour (retention is a setting, see trash.rs)
             let app_handle = app.handle().clone();
             std::thread::spawn(move || {
                 loop {
                     std::thread::sleep(std::time::Duration::from_secs(3600));
-                    let _ = db::cleanup_old_deletions(&app_handle, 48);
+                    trash::run_scheduled(&app_handle.state::<db::Db>());
                 }
             });
+            // Snapshot the journal once a day (checked hourly, and right after launch)
+            let app_handle = app.handle().clone();
+            std::thread::spawn(move || loop {
+                backup::run_scheduled(&app_handle.state::<db::Db>());
+                std::thread::sleep(std::time::Duration::from_secs(3600));
+            });
             // ...existing code...
             // Daily and every-7-days notification scheduler thread
             use std::sync::atomic::{AtomicBool, Ordering};
@@ -297,28 +893,18 @@ pub fn run() {
                             Some(last) => (now - last).num_days() >= 7
                         };
                         if should_send_weekly && weekly_recap_enabled && notif_enabled {
-                            // Get wins from past 7 days
-                            if let Ok(wins) = crate::db::get_wins(&app_handle) {
-                                let week_ago = now.date_naive() - chrono::Duration::days(6);
-                                let recent: Vec<_> = wins.iter().filter(|w| {
-                                    if let Ok(d) = chrono::NaiveDate::parse_from_str(&w.date, "%Y-%m-%d") {
-                                        d >= week_ago && d <= now.date_naive()
-                                    } else { false }
-                                }).collect();
-                                let count = recent.len();
-                                let tags: Vec<String> = recent.iter().flat_map(|w| w.tags.split(',').map(|t| t.trim().to_string())).collect();
-                                let mut tag_freq = std::collections::HashMap::new();
-                                for tag in tags {
-                                    *tag_freq.entry(tag).or_insert(0) += 1;
+                            // Same numbers the Recap view shows for the last 7 days
+                            let state = app_handle.state::<db::Db>();
+                            // After the sleep, and wherever the user is now
+                            let today = clock::today();
+                            if let Ok(recap) = state.with(|conn| stats::get_recap(conn, stats::RecapPeriod::Last7Days, today)) {
+                                let mut recap_body = stats::notification_text(&recap);
+                                let rules = streaks::get_rules(&state);
+                                if let Ok(s) = state.with(|conn| streaks::get_streaks(conn, &rules, today)) {
+                                    if let Some(current) = s.current.filter(|c| recap.total > 0 && c.length > 1) {
+                                        recap_body.push_str(&format!("\nCurrent streak: {} days", current.length));
+                                    }
                                 }
-                                let mut top_tags: Vec<_> = tag_freq.into_iter().collect();
-                                top_tags.sort_by(|a, b| b.1.cmp(&a.1));
-                                let top_tags_str = top_tags.iter().take(3).map(|(t, _)| t.clone()).collect::<Vec<_>>().join(", ");
-                                let recap_body = if count == 0 {
-                                    "No wins logged this week. Start a new streak!".to_string()
-                                } else {
-                                    format!("You logged {count} wins this week! Top tags: {top_tags_str}")
-                                };
                                 let body = if !weekly_message.is_empty() {
                                     format!("{}\n{}", weekly_message, recap_body)
                                 } else {
@@ -339,7 +925,7 @@ pub fn run() {
             // ...existing code...
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![add_win, update_win, delete_win, restore_win, get_deleted_wins, get_wins, get_tag_graph, set_notif_time, get_notif_time, suggest_tags_for_text, get_wins_with_chains])
+        .invoke_handler(tauri::generate_handler![add_win, update_win, delete_win, restore_win, get_deleted_wins, get_trash_settings, set_trash_settings, empty_trash, purge_win, delete_wins, restore_wins, undo, redo, get_undo_state, get_win_history, restore_win_version, list_goals, create_goal, update_goal, delete_goal, link_win_to_goal, unlink_win_from_goal, get_goals_for_win, get_goal_progress, get_recap, get_streaks, get_streak_rules, set_streak_rules, list_rest_days, set_rest_day, remove_rest_day, list_tags, rename_tag, merge_tags, add_tag_alias, remove_tag_alias, set_tag_parent, list_tag_changes, undo_tag_change, did_you_mean, add_attachment, list_attachments, get_attachment_thumbnail, open_attachment, remove_attachment, get_wins, query_wins, search_wins, export_wins, import_wins, get_encryption_status, unlock_db, enable_encryption, change_passphrase, disable_encryption, list_backups, create_backup, restore_backup, get_backup_retention, set_backup_retention, get_recovery_report, dismiss_recovery_report, get_tag_graph, get_tag_clusters, set_notif_time, get_notif_time, suggest_tags_for_text, get_wins_with_chains, get_chain_timeline, get_chain_timelines, get_chain_settings, set_chain_settings, add_win_to_chain, remove_win_from_chain, new_chain, split_chain, merge_chains])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
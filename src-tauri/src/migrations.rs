@@ -0,0 +1,639 @@
+// migrations.rs - Versioned schema migrations for the QuietWins SQLite store
+//
+// The schema version lives in `PRAGMA user_version`. Each migration runs once,
+// in order, inside its own transaction together with the version bump, so a
+// failed step leaves the file exactly as it was before the step started.
+
+use crate::errors;
+use rusqlite::{ffi, Connection, Result, Transaction};
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: fn(&Transaction) -> Result<()>,
+}
+
+/// All known migrations, oldest first. Never edit or reorder a migration that
+/// has shipped; add a new one with the next version number instead. For the
+/// same reason a migration never calls helpers that live on in other modules:
+/// what it needs is copied in as it was when the migration shipped.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial wins and deleted_wins tables",
+        up: m001_initial_schema,
+    },
+    Migration {
+        version: 2,
+        name: "normalized tags and win_tags tables",
+        up: m002_normalized_tags,
+    },
+    Migration {
+        version: 3,
+        name: "wins_fts full-text index",
+        up: m003_wins_fts,
+    },
+    Migration {
+        version: 4,
+        name: "index wins by date for keyset paging",
+        up: m004_wins_date_index,
+    },
+    Migration {
+        version: 5,
+        name: "win_revisions edit history",
+        up: m005_win_revisions,
+    },
+    Migration {
+        version: 6,
+        name: "win_attachments",
+        up: m006_win_attachments,
+    },
+    Migration {
+        version: 7,
+        name: "mood, energy and impact ratings",
+        up: m007_win_ratings,
+    },
+    Migration {
+        version: 8,
+        name: "goals, goal_wins and goal_tag_rules",
+        up: m008_goals,
+    },
+    Migration {
+        version: 9,
+        name: "rest_days",
+        up: m009_rest_days,
+    },
+    Migration {
+        version: 10,
+        name: "tag_aliases and tag_changes",
+        up: m010_tag_aliases,
+    },
+    Migration {
+        version: 11,
+        name: "tag hierarchy with tag_ancestry and win_tags_rollup",
+        up: m011_tag_hierarchy,
+    },
+    Migration {
+        version: 12,
+        name: "chains and chain_wins",
+        up: m012_chains,
+    },
+    Migration {
+        version: 13,
+        name: "utc_offset and time_zone on wins",
+        up: m013_win_time_zones,
+    },
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn schema_version(conn: &Connection) -> Result<i64> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Brings the database up to the latest schema version.
+pub fn run_migrations(conn: &mut Connection) -> Result<()> {
+    apply_migrations(conn, MIGRATIONS)
+}
+
+fn apply_migrations(conn: &mut Connection, migrations: &[Migration]) -> Result<()> {
+    let current = schema_version(conn)?;
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+    if current > latest {
+        println!(
+            "[migrations] Refusing to open schema v{} (latest known is v{})",
+            current, latest
+        );
+        return Err(schema_too_new(current, latest));
+    }
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        println!(
+            "[migrations] Applying v{}: {}",
+            migration.version, migration.name
+        );
+        let tx = conn.transaction()?;
+        if let Err(e) = (migration.up)(&tx) {
+            println!(
+                "[migrations] v{} failed, rolling back: {}",
+                migration.version, e
+            );
+            return Err(e);
+        }
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Error returned when the database was written by a newer build of the app.
+/// The file is left untouched so the newer build can still open it.
+fn schema_too_new(found: i64, supported: i64) -> rusqlite::Error {
+    errors::failure(
+        ffi::SQLITE_CANTOPEN,
+        &format!(
+            "Database schema version {} is newer than this app supports ({}). Please update Quiet Wins.",
+            found, supported
+        ),
+    )
+}
+
+/// Baseline schema. Uses IF NOT EXISTS so databases created before versioning
+/// (user_version 0, tables already present) are simply stamped as v1.
+fn m001_initial_schema(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS wins (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            date TEXT NOT NULL,
+            text TEXT NOT NULL,
+            tags TEXT,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS deleted_wins (
+            id INTEGER PRIMARY KEY,
+            date TEXT NOT NULL,
+            text TEXT NOT NULL,
+            tags TEXT,
+            created_at INTEGER NOT NULL,
+            deleted_at INTEGER NOT NULL
+        );",
+    )
+}
+
+/// Moves the free-form `tags` strings into `tags`/`win_tags`. Rows in
+/// `deleted_wins` keep their id, so trashed wins share the same join table and
+/// get their tags back on restore.
+fn m002_normalized_tags(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE
+        );
+        CREATE TABLE win_tags (
+            win_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            PRIMARY KEY (win_id, tag_id)
+        );
+        CREATE INDEX idx_win_tags_tag ON win_tags(tag_id);",
+    )?;
+    let mut rows: Vec<(i64, Option<String>)> = Vec::new();
+    for table in ["wins", "deleted_wins"] {
+        let mut stmt = tx.prepare(&format!("SELECT id, tags FROM {}", table))?;
+        let iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for r in iter {
+            rows.push(r?);
+        }
+    }
+    for (win_id, raw) in rows {
+        for name in v2_parse_tag_string(raw.as_deref().unwrap_or("")) {
+            tx.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", [&name])?;
+            tx.execute(
+                "INSERT OR IGNORE INTO win_tags (win_id, tag_id)
+                 SELECT ?1, id FROM tags WHERE name = ?2",
+                (win_id, &name),
+            )?;
+        }
+    }
+    tx.execute_batch(
+        "ALTER TABLE wins DROP COLUMN tags;
+        ALTER TABLE deleted_wins DROP COLUMN tags;",
+    )
+}
+
+/// `db::parse_tag_string` as it was in v2: comma separated, with JSON-ish
+/// brackets and quotes stripped and repeats dropped regardless of case.
+fn v2_parse_tag_string(raw: &str) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    raw.split(',')
+        .map(|t| t.trim_matches(|c: char| c == '[' || c == ']' || c == '"' || c.is_whitespace()))
+        .filter(|t| !t.is_empty() && seen.insert(t.to_lowercase()))
+        .map(|t| t.to_string())
+        .collect()
+}
+
+/// External-content FTS5 index over `wins.text`, kept in sync by triggers.
+/// Trashed wins leave the index and come back on restore like any insert.
+fn m003_wins_fts(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE wins_fts USING fts5(
+            text,
+            content='wins',
+            content_rowid='id',
+            tokenize='unicode61 remove_diacritics 2',
+            prefix='2 3'
+        );
+        INSERT INTO wins_fts (wins_fts) VALUES ('rebuild');
+        CREATE TRIGGER wins_fts_insert AFTER INSERT ON wins BEGIN
+            INSERT INTO wins_fts (rowid, text) VALUES (new.id, new.text);
+        END;
+        CREATE TRIGGER wins_fts_delete AFTER DELETE ON wins BEGIN
+            INSERT INTO wins_fts (wins_fts, rowid, text) VALUES ('delete', old.id, old.text);
+        END;
+        CREATE TRIGGER wins_fts_update AFTER UPDATE OF text ON wins BEGIN
+            INSERT INTO wins_fts (wins_fts, rowid, text) VALUES ('delete', old.id, old.text);
+            INSERT INTO wins_fts (rowid, text) VALUES (new.id, new.text);
+        END;",
+    )
+}
+
+fn m004_wins_date_index(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("CREATE INDEX idx_wins_date ON wins(date, id);")
+}
+
+/// One row per create/edit/delete/restore. `before`/`after` are JSON
+/// snapshots of the win; `state` is 'done', 'undone' (on the redo stack) or
+/// 'discarded' (undone, then overwritten by a newer change).
+fn m005_win_revisions(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE win_revisions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            win_id INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            before TEXT,
+            after TEXT,
+            created_at INTEGER NOT NULL,
+            state TEXT NOT NULL DEFAULT 'done'
+        );
+        CREATE INDEX idx_win_revisions_win ON win_revisions(win_id, id);
+        CREATE INDEX idx_win_revisions_state ON win_revisions(state, id);",
+    )
+}
+
+/// Files attached to wins. The bytes live outside the database under
+/// `attachments/`, named by their SHA-256 `hash`, so identical files are
+/// stored once. Like `win_tags`, `win_id` also covers wins in the trash.
+fn m006_win_attachments(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE win_attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            win_id INTEGER NOT NULL,
+            hash TEXT NOT NULL,
+            file_name TEXT NOT NULL,
+            mime TEXT NOT NULL,
+            size_bytes INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX idx_win_attachments_win ON win_attachments(win_id);
+        CREATE INDEX idx_win_attachments_hash ON win_attachments(hash);",
+    )
+}
+
+/// Optional 1–5 ratings. NULL means "not rated", so existing wins need no
+/// backfill.
+fn m007_win_ratings(tx: &Transaction) -> Result<()> {
+    for table in ["wins", "deleted_wins"] {
+        for column in ["mood", "energy", "impact"] {
+            tx.execute_batch(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} INTEGER
+                     CHECK ({column} IS NULL OR {column} BETWEEN 1 AND 5);"
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Goals a win can count toward, either linked by hand (`goal_wins`) or by
+/// carrying one of the goal's rule tags (`goal_tag_rules`). A target such as
+/// "3 per week" is `target_count` = 3, `target_period` = 'week'.
+fn m008_goals(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE goals (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            description TEXT NOT NULL DEFAULT '',
+            target_count INTEGER CHECK (target_count IS NULL OR target_count > 0),
+            target_period TEXT CHECK (target_period IN ('day', 'week', 'month', 'total')),
+            deadline TEXT,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE goal_wins (
+            goal_id INTEGER NOT NULL REFERENCES goals(id) ON DELETE CASCADE,
+            win_id INTEGER NOT NULL,
+            PRIMARY KEY (goal_id, win_id)
+        );
+        CREATE INDEX idx_goal_wins_win ON goal_wins(win_id);
+        CREATE TABLE goal_tag_rules (
+            goal_id INTEGER NOT NULL REFERENCES goals(id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            PRIMARY KEY (goal_id, tag_id)
+        );",
+    )
+}
+
+/// Days declared off in advance (or after the fact). A missing win on a rest
+/// day doesn't break a streak.
+fn m009_rest_days(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE rest_days (
+            date TEXT PRIMARY KEY,
+            note TEXT NOT NULL DEFAULT ''
+        );",
+    )
+}
+
+/// Alternative spellings that resolve to a canonical tag whenever a win is
+/// tagged, and a log of renames, merges and alias edits. `undo` holds what is
+/// needed to reverse a change as JSON; see `tags::undo_tag_change`.
+fn m010_tag_aliases(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE tag_aliases (
+            alias TEXT PRIMARY KEY COLLATE NOCASE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE
+        );
+        CREATE INDEX idx_tag_aliases_tag ON tag_aliases(tag_id);
+        CREATE TABLE tag_changes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            action TEXT NOT NULL,
+            summary TEXT NOT NULL,
+            undo TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            undone_at INTEGER
+        );",
+    )
+}
+
+/// Parent/child tags. `tag_ancestry` holds every (tag, ancestor) pair, the tag
+/// itself included, and `win_tags_rollup` lists each win under its tags and
+/// all of their ancestors, so a filter on `exercise` also finds `yoga`.
+fn m011_tag_hierarchy(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE tags ADD COLUMN parent_id INTEGER REFERENCES tags(id) ON DELETE SET NULL;
+        CREATE TABLE tag_ancestry (
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            ancestor_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            PRIMARY KEY (tag_id, ancestor_id)
+        );
+        CREATE INDEX idx_tag_ancestry_ancestor ON tag_ancestry(ancestor_id);
+        CREATE TRIGGER tags_ancestry_insert AFTER INSERT ON tags BEGIN
+            INSERT INTO tag_ancestry (tag_id, ancestor_id) VALUES (new.id, new.id);
+        END;
+        CREATE VIEW win_tags_rollup AS
+            SELECT wt.win_id, a.ancestor_id AS tag_id FROM win_tags wt
+            JOIN tag_ancestry a ON a.tag_id = wt.tag_id;",
+    )?;
+    // Only between tags already in use; new tags pick up their starter parent
+    // when first created
+    for (child, parent) in V11_STARTER_PARENTS {
+        tx.execute(
+            "UPDATE tags SET parent_id = (SELECT id FROM tags WHERE name = ?2)
+             WHERE name = ?1 AND parent_id IS NULL",
+            [child, parent],
+        )?;
+    }
+    tx.execute_batch(
+        "INSERT OR IGNORE INTO tag_ancestry (tag_id, ancestor_id)
+        WITH RECURSIVE up(tag_id, ancestor_id) AS (
+            SELECT id, id FROM tags
+            UNION
+            SELECT up.tag_id, t.parent_id FROM up JOIN tags t ON t.id = up.ancestor_id
+            WHERE t.parent_id IS NOT NULL
+        )
+        SELECT tag_id, ancestor_id FROM up;",
+    )
+}
+
+/// `tags::STARTER_PARENTS` as it was in v11.
+const V11_STARTER_PARENTS: [(&str, &str); 14] = [
+    ("exercise", "health"),
+    ("walk", "health"),
+    ("yoga", "exercise"),
+    ("run", "exercise"),
+    ("swim", "exercise"),
+    ("class", "school"),
+    ("homework", "school"),
+    ("study", "school"),
+    ("exam", "school"),
+    ("meeting", "admin"),
+    ("email", "admin"),
+    ("read", "learning"),
+    ("cook", "life"),
+    ("clean", "life"),
+];
+
+/// Persisted win chains. Detection fills `chain_wins` and keeps chain ids
+/// stable between runs; rows the user placed by hand are `pinned` and left
+/// alone, and a pinned row with no `chain_id` keeps a win out of every chain.
+/// Trashed wins keep their row, like their tags.
+fn m012_chains(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE chains (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE chain_wins (
+            win_id INTEGER PRIMARY KEY,
+            chain_id INTEGER REFERENCES chains(id) ON DELETE CASCADE,
+            pinned INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX idx_chain_wins_chain ON chain_wins(chain_id);",
+    )
+}
+
+/// The zone each win was logged in: its UTC offset in minutes and the IANA
+/// name. Older wins keep NULL in both; their dates stay as they were logged.
+fn m013_win_time_zones(tx: &Transaction) -> Result<()> {
+    for table in ["wins", "deleted_wins"] {
+        tx.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN utc_offset INTEGER;
+             ALTER TABLE {table} ADD COLUMN time_zone TEXT;"
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_db() -> Connection {
+        // Schema as written by builds that predate migrations
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE wins (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                date TEXT NOT NULL,
+                text TEXT NOT NULL,
+                tags TEXT,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE deleted_wins (
+                id INTEGER PRIMARY KEY,
+                date TEXT NOT NULL,
+                text TEXT NOT NULL,
+                tags TEXT,
+                created_at INTEGER NOT NULL,
+                deleted_at INTEGER NOT NULL
+            );
+            INSERT INTO wins (date, text, tags, created_at)
+                VALUES ('2025-01-02', 'Went for a walk', 'walk, health', 1735800000);
+            INSERT INTO wins (date, text, tags, created_at)
+                VALUES ('2025-01-03', 'Yoga class', '[Yoga,class, exercise, health]', 1735890000);
+            INSERT INTO wins (date, text, tags, created_at)
+                VALUES ('2025-01-04', 'Untagged', NULL, 1735980000);
+            INSERT INTO deleted_wins (id, date, text, tags, created_at, deleted_at)
+                VALUES (40, '2025-01-01', 'Trashed', '[\"misc\"]', 1735700000, 1735710000);",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM wins", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn legacy_database_keeps_its_rows() {
+        let mut conn = legacy_db();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        let text: String = conn
+            .query_row("SELECT text FROM wins WHERE id = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(text, "Went for a walk");
+    }
+
+    fn tags_of(conn: &Connection, win_id: i64) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT t.name FROM win_tags wt JOIN tags t ON t.id = wt.tag_id
+                 WHERE wt.win_id = ?1 ORDER BY t.name",
+            )
+            .unwrap();
+        let names = stmt
+            .query_map([win_id], |row| row.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        names
+    }
+
+    #[test]
+    fn fts_index_follows_wins() {
+        let mut conn = legacy_db();
+        run_migrations(&mut conn).unwrap();
+        let matches = |conn: &Connection, q: &str| -> i64 {
+            conn.query_row(
+                "SELECT COUNT(*) FROM wins_fts WHERE wins_fts MATCH ?1",
+                [q],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        // Existing rows are indexed by the migration itself
+        assert_eq!(matches(&conn, "walk"), 1);
+        conn.execute("UPDATE wins SET text = 'Swam laps' WHERE id = 1", [])
+            .unwrap();
+        assert_eq!(matches(&conn, "walk"), 0);
+        assert_eq!(matches(&conn, "laps"), 1);
+        conn.execute("DELETE FROM wins WHERE id = 1", []).unwrap();
+        assert_eq!(matches(&conn, "laps"), 0);
+    }
+
+    #[test]
+    fn legacy_tag_strings_are_normalized() {
+        let mut conn = legacy_db();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(tags_of(&conn, 1), vec!["health", "walk"]);
+        assert_eq!(
+            tags_of(&conn, 2),
+            vec!["class", "exercise", "health", "Yoga"]
+        );
+        assert!(tags_of(&conn, 3).is_empty());
+        assert_eq!(tags_of(&conn, 40), vec!["misc"]);
+        let distinct: i64 = conn
+            .query_row("SELECT COUNT(*) FROM tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(distinct, 6);
+        let has_tags_column: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('wins') WHERE name = 'tags'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(has_tags_column, 0);
+    }
+
+    #[test]
+    fn legacy_tags_get_starter_parents() {
+        let mut conn = legacy_db();
+        run_migrations(&mut conn).unwrap();
+        let ancestors = |name: &str| -> Vec<String> {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT p.name FROM tag_ancestry a
+                     JOIN tags t ON t.id = a.tag_id JOIN tags p ON p.id = a.ancestor_id
+                     WHERE t.name = ?1 ORDER BY p.name",
+                )
+                .unwrap();
+            let names = stmt
+                .query_map([name], |row| row.get(0))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect();
+            names
+        };
+        assert_eq!(ancestors("exercise"), vec!["exercise", "health"]);
+        assert_eq!(ancestors("walk"), vec!["health", "walk"]);
+        assert_eq!(ancestors("class"), vec!["class"]);
+    }
+
+    #[test]
+    fn running_twice_is_a_no_op() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+        let err = run_migrations(&mut conn).unwrap_err();
+        assert!(err.to_string().contains("newer than this app supports"));
+        assert_eq!(schema_version(&conn).unwrap(), latest_version() + 1);
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        fn create_notes(tx: &Transaction) -> Result<()> {
+            tx.execute_batch("CREATE TABLE notes (id INTEGER PRIMARY KEY)")
+        }
+        fn broken(tx: &Transaction) -> Result<()> {
+            tx.execute_batch("CREATE TABLE half_done (id INTEGER); SELECT * FROM missing_table")
+        }
+        let steps = [
+            Migration {
+                version: 1,
+                name: "notes",
+                up: create_notes,
+            },
+            Migration {
+                version: 2,
+                name: "broken",
+                up: broken,
+            },
+        ];
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(apply_migrations(&mut conn, &steps).is_err());
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+        let leftovers: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'half_done'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(leftovers, 0);
+    }
+}
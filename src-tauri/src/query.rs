@@ -0,0 +1,334 @@
+// query.rs - Filtered, keyset-paginated win queries for the log and recap views
+
+use crate::db::{self, Win};
+use rusqlite::{named_params, Connection, Result};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+/// Filters shared by `query_wins` and the exporters. Dates are inclusive
+/// `YYYY-MM-DD` strings; a win must carry every `include_tags` entry and none
+/// of the `exclude_tags`. A tag also matches wins tagged with any tag below it.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct WinFilter {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub include_tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+    pub contains: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct WinQuery {
+    #[serde(flatten)]
+    pub filter: WinFilter,
+    pub sort: SortOrder,
+    /// `next_cursor` from the previous page; omit for the first page
+    pub cursor: Option<String>,
+    pub page_size: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct WinPage {
+    pub wins: Vec<Win>,
+    /// Pass back as `cursor` to get the following page; `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+pub fn query_wins(conn: &Connection, query: &WinQuery) -> Result<WinPage> {
+    let page_size = query
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let cursor = query.cursor.as_deref().and_then(parse_cursor);
+    // Fetch one extra row to learn whether another page exists
+    let mut wins = filtered_wins(
+        conn,
+        "wins",
+        &query.filter,
+        query.sort,
+        cursor,
+        Some(page_size + 1),
+    )?;
+    let next_cursor = if wins.len() > page_size {
+        wins.truncate(page_size);
+        wins.last().map(|w| format!("{}:{}", w.date, w.id))
+    } else {
+        None
+    };
+    println!(
+        "[query_wins] Returning {} wins (more: {})",
+        wins.len(),
+        next_cursor.is_some()
+    );
+    Ok(WinPage { wins, next_cursor })
+}
+
+/// Runs `filter` against `wins` or `deleted_wins`, ordered by (date, id).
+/// `after` is the (date, id) keyset position to continue from.
+pub(crate) fn filtered_wins(
+    conn: &Connection,
+    table: &str,
+    filter: &WinFilter,
+    sort: SortOrder,
+    after: Option<(String, i64)>,
+    limit: Option<usize>,
+) -> Result<Vec<Win>> {
+    let (direction, keyset_op) = match sort {
+        SortOrder::Newest => ("DESC", "<"),
+        SortOrder::Oldest => ("ASC", ">"),
+    };
+    let sql = format!(
+        "SELECT {columns} FROM {table} w
+         WHERE (:from IS NULL OR w.date >= :from)
+           AND (:to IS NULL OR w.date <= :to)
+           AND (:contains IS NULL OR instr(lower(w.text), lower(:contains)) > 0)
+           AND (SELECT COUNT(DISTINCT t.id) FROM win_tags_rollup wt JOIN tags t ON t.id = wt.tag_id
+                WHERE wt.win_id = w.id AND t.name IN (SELECT value FROM json_each(:include)))
+               = json_array_length(:include)
+           AND NOT EXISTS (SELECT 1 FROM win_tags_rollup wt JOIN tags t ON t.id = wt.tag_id
+                WHERE wt.win_id = w.id AND t.name IN (SELECT value FROM json_each(:exclude)))
+           AND (:after_date IS NULL OR (w.date, w.id) {op} (:after_date, :after_id))
+         ORDER BY w.date {dir}, w.id {dir}
+         LIMIT :limit",
+        columns = db::win_columns(),
+        table = table,
+        op = keyset_op,
+        dir = direction,
+    );
+    let include = tag_json(&filter.include_tags);
+    let exclude = tag_json(&filter.exclude_tags);
+    let contains = filter.contains.as_deref().filter(|c| !c.trim().is_empty());
+    let (after_date, after_id) = match after {
+        Some((date, id)) => (Some(date), Some(id)),
+        None => (None, None),
+    };
+    // SQLite treats a negative LIMIT as "no limit"
+    let limit = limit.map(|l| l as i64).unwrap_or(-1);
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(
+        named_params! {
+            ":from": filter.from.as_deref().filter(|d| !d.is_empty()),
+            ":to": filter.to.as_deref().filter(|d| !d.is_empty()),
+            ":contains": contains,
+            ":include": include,
+            ":exclude": exclude,
+            ":after_date": after_date,
+            ":after_id": after_id,
+            ":limit": limit,
+        },
+        db::win_from_row,
+    )?;
+    let mut wins = Vec::new();
+    for win in rows {
+        wins.push(win?);
+    }
+    Ok(wins)
+}
+
+fn tag_json(tags: &[String]) -> String {
+    let tags = db::parse_tag_string(&tags.join(","));
+    serde_json::to_string(&tags).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// Cursors are `<date>:<id>` of the last win on the previous page.
+fn parse_cursor(cursor: &str) -> Option<(String, i64)> {
+    let (date, id) = cursor.rsplit_once(':')?;
+    Some((date.to_string(), id.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn journal(dates: &[&str]) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        for (i, date) in dates.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO wins (date, text, created_at) VALUES (?1, ?2, ?3)",
+                (date, format!("win {}", i + 1), i as i64),
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    /// Pages through the whole query, returning the ids page by page.
+    fn pages(conn: &Connection, sort: SortOrder, page_size: usize) -> Vec<Vec<i64>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let query = WinQuery {
+                sort,
+                cursor,
+                page_size: Some(page_size),
+                ..Default::default()
+            };
+            let page = query_wins(conn, &query).unwrap();
+            pages.push(page.wins.iter().map(|w| w.id).collect());
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn pages_split_same_day_wins_without_gaps_or_repeats() {
+        // Ids 2, 3, 4 and 5 share a day, so pages of two break inside it
+        let conn = journal(&[
+            "2025-01-01",
+            "2025-01-02",
+            "2025-01-02",
+            "2025-01-02",
+            "2025-01-02",
+            "2025-01-03",
+        ]);
+        assert_eq!(
+            pages(&conn, SortOrder::Newest, 2),
+            vec![vec![6, 5], vec![4, 3], vec![2, 1]]
+        );
+        assert_eq!(
+            pages(&conn, SortOrder::Oldest, 4),
+            vec![vec![1, 2, 3, 4], vec![5, 6]]
+        );
+        assert_eq!(
+            pages(&conn, SortOrder::Newest, 6),
+            vec![vec![6, 5, 4, 3, 2, 1]]
+        );
+    }
+
+    #[test]
+    fn wins_logged_out_of_order_page_by_date() {
+        // A win backdated after later days were logged
+        let conn = journal(&["2025-01-03", "2025-01-05", "2025-01-03", "2025-01-04"]);
+        assert_eq!(
+            pages(&conn, SortOrder::Newest, 3),
+            vec![vec![2, 4, 3], vec![1]]
+        );
+    }
+
+    #[test]
+    fn filters_apply_on_every_page() {
+        let conn = journal(&["2025-01-01", "2025-01-02", "2025-01-02", "2025-01-03"]);
+        let query = |cursor: Option<String>| WinQuery {
+            filter: WinFilter {
+                from: Some("2025-01-02".to_string()),
+                ..Default::default()
+            },
+            cursor,
+            page_size: Some(2),
+            ..Default::default()
+        };
+        let first = query_wins(&conn, &query(None)).unwrap();
+        assert_eq!(
+            first.wins.iter().map(|w| w.id).collect::<Vec<_>>(),
+            vec![4, 3]
+        );
+        assert_eq!(first.next_cursor.as_deref(), Some("2025-01-02:3"));
+        let second = query_wins(&conn, &query(first.next_cursor)).unwrap();
+        assert_eq!(
+            second.wins.iter().map(|w| w.id).collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert!(second.next_cursor.is_none());
+    }
+
+    /// Ids of the wins `filter` matches, oldest first. The wins are tagged
+    /// walk (under health), walk and read, read, and nothing.
+    fn matching(filter: WinFilter) -> Vec<i64> {
+        let conn = journal(&["2025-01-01", "2025-01-02", "2025-01-03", "2025-01-04"]);
+        conn.execute_batch(
+            "UPDATE wins SET text = 'Walked to the Library' WHERE id = 2;
+             UPDATE wins SET text = 'Read 100% of it' WHERE id = 3;",
+        )
+        .unwrap();
+        for (id, tags) in [(1, "walk"), (2, "walk, read"), (3, "read")] {
+            db::set_win_tags(&conn, id, &db::parse_tag_string(tags)).unwrap();
+        }
+        let wins = filtered_wins(&conn, "wins", &filter, SortOrder::Oldest, None, None).unwrap();
+        wins.iter().map(|w| w.id).collect()
+    }
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn tag_filters_include_and_exclude() {
+        let include = |names: &[&str]| {
+            matching(WinFilter {
+                include_tags: tags(names),
+                ..Default::default()
+            })
+        };
+        assert_eq!(include(&[]), vec![1, 2, 3, 4]);
+        assert_eq!(include(&["walk"]), vec![1, 2]);
+        // Every included tag must be there, in any case and through parents
+        assert_eq!(include(&["walk", "READ"]), vec![2]);
+        assert_eq!(include(&["health"]), vec![1, 2]);
+        assert_eq!(include(&["nowhere"]), Vec::<i64>::new());
+
+        let exclude = |names: &[&str]| {
+            matching(WinFilter {
+                exclude_tags: tags(names),
+                ..Default::default()
+            })
+        };
+        assert_eq!(exclude(&["read"]), vec![1, 4]);
+        assert_eq!(exclude(&["health", "read"]), vec![4]);
+
+        let both = matching(WinFilter {
+            include_tags: tags(&["read"]),
+            exclude_tags: tags(&["walk"]),
+            ..Default::default()
+        });
+        assert_eq!(both, vec![3]);
+    }
+
+    #[test]
+    fn contains_matches_text_in_any_case() {
+        let contains = |text: &str| {
+            matching(WinFilter {
+                contains: Some(text.to_string()),
+                ..Default::default()
+            })
+        };
+        assert_eq!(contains("library"), vec![2]);
+        assert_eq!(contains("WIN"), vec![1, 4]);
+        // Taken literally, not as a LIKE pattern
+        assert_eq!(contains("100%"), vec![3]);
+        assert_eq!(contains("%"), vec![3]);
+        assert_eq!(contains("  "), vec![1, 2, 3, 4]);
+
+        let narrowed = matching(WinFilter {
+            include_tags: tags(&["walk"]),
+            contains: Some("win".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(narrowed, vec![1]);
+    }
+
+    #[test]
+    fn bad_cursors_start_over() {
+        assert_eq!(
+            parse_cursor("2025-01-02:17"),
+            Some(("2025-01-02".to_string(), 17))
+        );
+        assert_eq!(parse_cursor("2025-01-02"), None);
+        assert_eq!(parse_cursor("2025-01-02:x"), None);
+    }
+}
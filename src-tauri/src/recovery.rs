@@ -0,0 +1,933 @@
+// recovery.rs - Salvage wins from a corrupt database file into a fresh one
+
+use crate::db;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, Transaction};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use time::OffsetDateTime;
+
+/// Tables worth salvaging. The FTS index is rebuilt by its triggers.
+const TABLES: [&str; 4] = ["wins", "deleted_wins", "tags", "win_tags"];
+const WIN_TABLES: [&str; 2] = ["wins", "deleted_wins"];
+
+#[derive(Serialize, Clone, Default)]
+pub struct RecoveryReport {
+    /// Where the corrupt file was moved; it is kept for manual recovery
+    pub corrupt_file: String,
+    pub recovered_at: i64,
+    pub wins_recovered: usize,
+    pub deleted_wins_recovered: usize,
+    /// Wins whose tags couldn't be read and were re-inferred from the text
+    pub tags_inferred: usize,
+    /// Wins that were found in the file but couldn't be read back
+    pub rows_lost: usize,
+    /// Database pages the page scan couldn't parse
+    pub pages_unreadable: usize,
+    /// What went wrong along the way, in plain words for the UI
+    pub notes: Vec<String>,
+}
+
+/// A salvaged row keyed by column name.
+type Row = HashMap<String, Value>;
+
+#[derive(Default)]
+struct Found {
+    /// table -> rowid -> row
+    rows: HashMap<&'static str, BTreeMap<i64, Row>>,
+    /// Rowids known to exist in the win tables, readable or not
+    seen: HashMap<&'static str, HashSet<i64>>,
+}
+
+/// Reads every row it can from `corrupt` and inserts the wins, trash and tags
+/// into `conn`, which must be a freshly migrated, empty database. Rows are
+/// read table by table and row by row through SQL first; whatever that can't
+/// reach is picked up by walking the file's b-tree pages directly, in the
+/// spirit of the sqlite3 `.recover` command.
+pub fn salvage(corrupt: &Path, conn: &mut Connection) -> RecoveryReport {
+    println!("[salvage] Salvaging rows from {}", corrupt.display());
+    let mut report = RecoveryReport {
+        corrupt_file: corrupt.display().to_string(),
+        recovered_at: OffsetDateTime::now_utc().unix_timestamp(),
+        ..Default::default()
+    };
+    let mut found = Found::default();
+
+    match Connection::open(corrupt) {
+        Ok(old) => {
+            for table in TABLES {
+                sql_pass(&old, table, &mut found, &mut report);
+            }
+        }
+        Err(e) => report
+            .notes
+            .push(format!("The damaged file couldn't be opened: {}", e)),
+    }
+    let from_sql = count_wins(&found);
+    page_scan(corrupt, &mut found, &mut report);
+    let from_pages = count_wins(&found) - from_sql;
+    if from_pages > 0 {
+        report.notes.push(format!(
+            "{} wins were read directly from damaged pages.",
+            from_pages
+        ));
+    }
+
+    if let Err(e) = insert_salvaged(conn, &found, &mut report) {
+        report
+            .notes
+            .push(format!("Recovered rows couldn't be saved: {}", e));
+        report.wins_recovered = 0;
+        report.deleted_wins_recovered = 0;
+    }
+    println!(
+        "[salvage] Recovered {} wins and {} deleted wins; {} lost, {} unreadable pages",
+        report.wins_recovered,
+        report.deleted_wins_recovered,
+        report.rows_lost,
+        report.pages_unreadable
+    );
+    report
+}
+
+fn count_wins(found: &Found) -> usize {
+    WIN_TABLES
+        .iter()
+        .map(|t| found.rows.get(t).map_or(0, |rows| rows.len()))
+        .sum()
+}
+
+/// Lists rowids until the scan hits damage, then reads each row on its own so
+/// one bad row doesn't cost the rest.
+fn sql_pass(old: &Connection, table: &'static str, found: &mut Found, report: &mut RecoveryReport) {
+    let mut rowids = Vec::new();
+    match old.prepare(&format!("SELECT rowid FROM {}", table)) {
+        Ok(mut stmt) => {
+            if let Ok(mut rows) = stmt.query([]) {
+                // A damaged b-tree page ends the scan early; the page scan
+                // picks up what lies beyond it
+                while let Ok(Some(row)) = rows.next() {
+                    if let Ok(id) = row.get(0) {
+                        rowids.push(id);
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            if WIN_TABLES.contains(&table) {
+                report
+                    .notes
+                    .push(format!("The {} table couldn't be read: {}", table, e));
+            }
+            return;
+        }
+    }
+    if WIN_TABLES.contains(&table) {
+        found.seen.entry(table).or_default().extend(&rowids);
+    }
+    let Ok(mut stmt) = old.prepare(&format!("SELECT * FROM {} WHERE rowid = ?1", table)) else {
+        return;
+    };
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let rows = found.rows.entry(table).or_default();
+    for id in rowids {
+        let values = stmt.query_row([id], |row| {
+            (0..columns.len())
+                .map(|i| row.get::<_, Value>(i))
+                .collect::<rusqlite::Result<Vec<Value>>>()
+        });
+        if let Ok(values) = values {
+            rows.insert(id, columns.iter().cloned().zip(values).collect());
+        }
+    }
+}
+
+/// Walks each table's b-tree from the root page listed in sqlite_master and
+/// adds any row the SQL pass didn't get.
+fn page_scan(corrupt: &Path, found: &mut Found, report: &mut RecoveryReport) {
+    let Some(pages) = Pages::open(corrupt) else {
+        report
+            .notes
+            .push("The damaged file has no readable header; pages weren't scanned.".to_string());
+        return;
+    };
+    let mut visited = HashSet::new();
+    let mut bad_cells = 0;
+    let mut schema = Vec::new();
+    pages.walk(
+        1,
+        &mut schema,
+        &mut visited,
+        &mut report.pages_unreadable,
+        &mut bad_cells,
+    );
+    if schema.is_empty() {
+        report
+            .notes
+            .push("The schema page is damaged, so tables couldn't be located.".to_string());
+    }
+    for (_, entry) in schema {
+        let [Value::Text(kind), Value::Text(name), _, Value::Integer(root), Value::Text(sql)] =
+            entry.as_slice()
+        else {
+            continue;
+        };
+        let Some(&table) = TABLES.iter().find(|t| **t == name.as_str()) else {
+            continue;
+        };
+        if kind != "table" {
+            continue;
+        }
+        let columns = column_names(sql);
+        let mut cells = Vec::new();
+        let mut table_bad_cells = 0;
+        pages.walk(
+            *root as u32,
+            &mut cells,
+            &mut visited,
+            &mut report.pages_unreadable,
+            &mut table_bad_cells,
+        );
+        let rows = found.rows.entry(table).or_default();
+        for (rowid, values) in cells {
+            if WIN_TABLES.contains(&table) {
+                found.seen.entry(table).or_default().insert(rowid);
+            }
+            rows.entry(rowid).or_insert_with(|| {
+                let mut row: Row = columns.iter().cloned().zip(values).collect();
+                // INTEGER PRIMARY KEY columns are stored as NULL; the value is the rowid
+                if matches!(row.get("id"), Some(Value::Null)) {
+                    row.insert("id".to_string(), Value::Integer(rowid));
+                }
+                row
+            });
+        }
+        if WIN_TABLES.contains(&table) {
+            report.rows_lost += table_bad_cells;
+        }
+    }
+    orphan_scan(&pages, &visited, found, report);
+}
+
+/// Leaf pages no table reaches any more (their parent page was destroyed)
+/// still hold rows. Anything shaped like a win is restored to the log, since
+/// there's no telling which table it came from.
+fn orphan_scan(
+    pages: &Pages,
+    visited: &HashSet<u32>,
+    found: &mut Found,
+    report: &mut RecoveryReport,
+) {
+    let free = pages.freelist();
+    let mut cells = Vec::new();
+    for number in 2..=pages.page_count() as u32 {
+        if visited.contains(&number) || free.contains(&number) {
+            continue;
+        }
+        if let Some(page) = pages.page(number).filter(|p| p[0] == 0x0D) {
+            for cell in cell_offsets(page, 0, 8) {
+                cells.extend(pages.leaf_cell(page, cell));
+            }
+        }
+    }
+    let known: HashSet<i64> = WIN_TABLES
+        .iter()
+        .filter_map(|t| found.rows.get(t))
+        .flat_map(|rows| rows.keys().copied())
+        .collect();
+    let mut orphans = false;
+    let rows = found.rows.entry("wins").or_default();
+    for (rowid, values) in cells {
+        if known.contains(&rowid) || rows.contains_key(&rowid) {
+            continue;
+        }
+        if let Some(row) = win_shaped(rowid, &values) {
+            rows.insert(rowid, row);
+            orphans = true;
+        }
+    }
+    if orphans {
+        report.notes.push(
+            "Some wins were cut off from their table; any that were in the trash are back in the log."
+                .to_string(),
+        );
+    }
+}
+
+/// `[id, date, text, (legacy tags,) created_at, ...]` with a YYYY-MM-DD date.
+fn win_shaped(rowid: i64, values: &[Value]) -> Option<Row> {
+    let [Value::Null | Value::Integer(_), Value::Text(date), Value::Text(text), rest @ ..] = values
+    else {
+        return None;
+    };
+    let is_date = date.len() == 10
+        && date.bytes().enumerate().all(|(i, b)| {
+            if i == 4 || i == 7 {
+                b == b'-'
+            } else {
+                b.is_ascii_digit()
+            }
+        });
+    if !is_date || rest.is_empty() {
+        return None;
+    }
+    let mut row = Row::new();
+    row.insert("id".to_string(), Value::Integer(rowid));
+    row.insert("date".to_string(), Value::Text(date.clone()));
+    row.insert("text".to_string(), Value::Text(text.clone()));
+    if let Some(Value::Text(tags)) = rest.first() {
+        row.insert("tags".to_string(), Value::Text(tags.clone()));
+    }
+    if let Some(created_at) = rest.iter().find(|v| matches!(v, Value::Integer(_))) {
+        row.insert("created_at".to_string(), created_at.clone());
+    }
+    Some(row)
+}
+
+fn insert_salvaged(
+    conn: &mut Connection,
+    found: &Found,
+    report: &mut RecoveryReport,
+) -> rusqlite::Result<()> {
+    let empty = BTreeMap::new();
+    let rows = |table: &str| found.rows.get(table).unwrap_or(&empty);
+    let tag_names: HashMap<i64, String> = rows("tags")
+        .iter()
+        .filter_map(|(id, row)| match row.get("name") {
+            Some(Value::Text(name)) => Some((*id, name.clone())),
+            _ => None,
+        })
+        .collect();
+    let mut win_tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for row in rows("win_tags").values() {
+        if let (Some(Value::Integer(win_id)), Some(Value::Integer(tag_id))) =
+            (row.get("win_id"), row.get("tag_id"))
+        {
+            if let Some(name) = tag_names.get(tag_id) {
+                win_tags.entry(*win_id).or_default().push(name.clone());
+            }
+        }
+    }
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let tx = conn.transaction()?;
+    let mut live_ids = HashSet::new();
+    for table in WIN_TABLES {
+        let columns = table_columns(&tx, table)?;
+        let mut inserted = HashSet::new();
+        for (id, row) in rows(table) {
+            // A win can only be live or trashed; the live copy wins
+            if table == "deleted_wins" && live_ids.contains(id) {
+                continue;
+            }
+            let (Some(Value::Text(_)), Some(Value::Text(text))) =
+                (row.get("date"), row.get("text"))
+            else {
+                continue;
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+            let mut names = vec!["id".to_string()];
+            let mut values = vec![Value::Integer(*id)];
+            for column in columns.iter().filter(|c| c.as_str() != "id") {
+                let value = match row.get(column) {
+                    Some(Value::Null) | None if column.ends_with("_at") => Value::Integer(now),
+                    Some(value) => value.clone(),
+                    None => continue,
+                };
+                names.push(column.clone());
+                values.push(value);
+            }
+            let sql = format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                table,
+                names.join(", "),
+                vec!["?"; names.len()].join(", ")
+            );
+            if tx.execute(&sql, params_from_iter(values.iter())).is_err() {
+                continue;
+            }
+            let mut tags = match row.get("tags") {
+                // Files from before tags were normalized keep them inline
+                Some(Value::Text(legacy)) => db::parse_tag_string(legacy),
+                _ => win_tags.get(id).cloned().unwrap_or_default(),
+            };
+            if tags.is_empty() {
+                tags = db::infer_tags(text);
+                report.tags_inferred += 1;
+            }
+            db::set_win_tags(&tx, *id, &tags)?;
+            inserted.insert(*id);
+        }
+        let seen = found.seen.get(table).map_or(0, |s| s.len());
+        report.rows_lost += seen.saturating_sub(inserted.len());
+        if table == "wins" {
+            report.wins_recovered = inserted.len();
+            live_ids = inserted;
+        } else {
+            report.deleted_wins_recovered = inserted.len();
+        }
+    }
+    tx.commit()
+}
+
+fn table_columns(tx: &Transaction, table: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = tx.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+    let names = stmt.query_map([], |row| row.get(0))?;
+    names.collect()
+}
+
+/// Column names from a CREATE TABLE statement, skipping table constraints.
+fn column_names(sql: &str) -> Vec<String> {
+    let (Some(start), Some(end)) = (sql.find('('), sql.rfind(')')) else {
+        return Vec::new();
+    };
+    let mut defs = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in sql[start + 1..end].chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                defs.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    defs.push(current);
+    defs.iter()
+        .filter_map(|def| def.split_whitespace().next())
+        .map(|name| name.trim_matches(['"', '`', '[', ']']).to_string())
+        .filter(|name| {
+            !["PRIMARY", "UNIQUE", "CHECK", "FOREIGN", "CONSTRAINT"]
+                .contains(&name.to_uppercase().as_str())
+        })
+        .collect()
+}
+
+/// Just enough of the SQLite file format to read table b-trees from a file
+/// SQLite itself refuses to trust. Any page or cell that doesn't parse is
+/// counted and skipped.
+struct Pages {
+    data: Vec<u8>,
+    page_size: usize,
+    usable: usize,
+}
+
+impl Pages {
+    fn open(path: &Path) -> Option<Pages> {
+        Pages::parse(fs::read(path).ok()?)
+    }
+
+    fn parse(data: Vec<u8>) -> Option<Pages> {
+        // Every header field read below is inside the 100-byte file header
+        if data.len() < 100 || &data[..16] != b"SQLite format 3\0" {
+            return None;
+        }
+        let page_size = match u16::from_be_bytes([data[16], data[17]]) {
+            1 => 65536,
+            size => size as usize,
+        };
+        if page_size < 512 || !page_size.is_power_of_two() {
+            return None;
+        }
+        // SQLite itself refuses files with less than 480 usable bytes a page
+        let usable = page_size
+            .checked_sub(data[20] as usize)
+            .filter(|u| *u >= 480)?;
+        Some(Pages {
+            data,
+            page_size,
+            usable,
+        })
+    }
+
+    fn page(&self, number: u32) -> Option<&[u8]> {
+        let start = (number as usize).checked_sub(1)? * self.page_size;
+        self.data.get(start..start + self.page_size)
+    }
+
+    fn page_count(&self) -> usize {
+        self.data.len() / self.page_size
+    }
+
+    /// Pages on the freelist hold stale rows from deleted data; never salvage
+    /// from them.
+    fn freelist(&self) -> HashSet<u32> {
+        let mut free = HashSet::new();
+        let mut trunk = be_u32(&self.data, 32).unwrap_or(0);
+        while trunk != 0 && free.insert(trunk) {
+            let Some(page) = self.page(trunk) else { break };
+            let count = be_u32(page, 4).unwrap_or(0) as usize;
+            for i in 0..count.min(self.usable / 4 - 2) {
+                if let Some(leaf) = be_u32(page, 8 + i * 4) {
+                    free.insert(leaf);
+                }
+            }
+            trunk = be_u32(page, 0).unwrap_or(0);
+        }
+        free
+    }
+
+    /// Collects `(rowid, values)` for every readable cell in the table b-tree
+    /// rooted at `root`.
+    fn walk(
+        &self,
+        root: u32,
+        rows: &mut Vec<(i64, Vec<Value>)>,
+        visited: &mut HashSet<u32>,
+        bad_pages: &mut usize,
+        bad_cells: &mut usize,
+    ) {
+        let mut stack = vec![root];
+        while let Some(number) = stack.pop() {
+            if !visited.insert(number) {
+                continue;
+            }
+            let Some(page) = self.page(number) else {
+                *bad_pages += 1;
+                continue;
+            };
+            // Page 1 carries the 100-byte file header before its b-tree header
+            let header = if number == 1 { 100 } else { 0 };
+            match page[header] {
+                // Table leaf
+                0x0D => {
+                    for cell in cell_offsets(page, header, 8) {
+                        match self.leaf_cell(page, cell) {
+                            Some(row) => rows.push(row),
+                            None => *bad_cells += 1,
+                        }
+                    }
+                }
+                // Table interior: left child pointers, then the right-most child
+                0x05 => {
+                    for cell in cell_offsets(page, header, 12) {
+                        if let Some(child) = be_u32(page, cell) {
+                            stack.push(child);
+                        }
+                    }
+                    if let Some(right) = be_u32(page, header + 8) {
+                        stack.push(right);
+                    }
+                }
+                _ => *bad_pages += 1,
+            }
+        }
+    }
+
+    fn leaf_cell(&self, page: &[u8], offset: usize) -> Option<(i64, Vec<Value>)> {
+        let (total, n) = varint(page, offset)?;
+        let (rowid, m) = varint(page, offset + n)?;
+        let total = usize::try_from(total)
+            .ok()
+            .filter(|t| *t <= self.data.len())?;
+        let payload = self.payload(page, offset + n + m, total)?;
+        Some((rowid as i64, decode_record(&payload)?))
+    }
+
+    /// Reassembles a cell payload, following the overflow chain when the
+    /// record doesn't fit on its page.
+    fn payload(&self, page: &[u8], start: usize, total: usize) -> Option<Vec<u8>> {
+        let usable = self.usable;
+        let max_local = usable - 35;
+        let local = if total <= max_local {
+            total
+        } else {
+            let min_local = (usable - 12) * 32 / 255 - 23;
+            let k = min_local + (total - min_local) % (usable - 4);
+            if k <= max_local {
+                k
+            } else {
+                min_local
+            }
+        };
+        let mut out = page.get(start..start + local)?.to_vec();
+        if local < total {
+            let mut next = be_u32(page, start + local)?;
+            let mut hops = 0;
+            while out.len() < total {
+                hops += 1;
+                if next == 0 || hops > self.page_count() {
+                    return None;
+                }
+                let overflow = self.page(next)?;
+                let take = (total - out.len()).min(usable - 4);
+                out.extend_from_slice(overflow.get(4..4 + take)?);
+                next = be_u32(overflow, 0)?;
+            }
+        }
+        Some(out)
+    }
+}
+
+/// Offsets of the cells on a b-tree page whose header is `header_len` bytes.
+fn cell_offsets(page: &[u8], header: usize, header_len: usize) -> Vec<usize> {
+    let count = page
+        .get(header + 3..header + 5)
+        .map_or(0, |b| u16::from_be_bytes([b[0], b[1]]) as usize);
+    (0..count)
+        .filter_map(|i| {
+            let at = header + header_len + i * 2;
+            let b = page.get(at..at + 2)?;
+            let offset = u16::from_be_bytes([b[0], b[1]]) as usize;
+            (offset > header && offset < page.len()).then_some(offset)
+        })
+        .collect()
+}
+
+fn be_u32(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// SQLite's big-endian varint: 7 bits per byte, the ninth byte uses all 8.
+fn varint(buf: &[u8], at: usize) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+    for i in 0..9 {
+        let byte = *buf.get(at + i)?;
+        if i == 8 {
+            return Some(((value << 8) | byte as u64, 9));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn decode_record(record: &[u8]) -> Option<Vec<Value>> {
+    let (header_len, mut at) = varint(record, 0)?;
+    let header_len = usize::try_from(header_len)
+        .ok()
+        .filter(|h| *h <= record.len())?;
+    let mut types = Vec::new();
+    while at < header_len {
+        let (serial, n) = varint(record, at)?;
+        types.push(serial);
+        at += n;
+    }
+    let mut body = header_len;
+    let mut values = Vec::with_capacity(types.len());
+    for serial in types {
+        let (value, size) = match serial {
+            0 => (Value::Null, 0),
+            1..=6 => {
+                let size = [0, 1, 2, 3, 4, 6, 8][serial as usize];
+                let bytes = record.get(body..body + size)?;
+                // Sign-extend from the first byte
+                let mut v: i64 = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+                for b in bytes {
+                    v = (v << 8) | *b as i64;
+                }
+                (Value::Integer(v), size)
+            }
+            7 => {
+                let bytes = record.get(body..body + 8)?.try_into().ok()?;
+                (Value::Real(f64::from_be_bytes(bytes)), 8)
+            }
+            8 => (Value::Integer(0), 0),
+            9 => (Value::Integer(1), 0),
+            10 | 11 => return None,
+            s if s % 2 == 0 => {
+                let size = ((s - 12) / 2) as usize;
+                (Value::Blob(record.get(body..body + size)?.to_vec()), size)
+            }
+            s => {
+                let size = ((s - 13) / 2) as usize;
+                let text = String::from_utf8_lossy(record.get(body..body + size)?);
+                (Value::Text(text.into_owned()), size)
+            }
+        };
+        values.push(value);
+        body += size;
+    }
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+    use std::path::PathBuf;
+
+    const PAGE_SIZE: usize = 1024;
+
+    /// A journal file with small pages, so a few hundred wins span many
+    /// leaves under an interior root. Win 7 is long enough to overflow.
+    fn journal(name: &str, wins: i64) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "quietwins-recovery-{}-{}.sqlite",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        let mut conn = Connection::open(&path).unwrap();
+        conn.pragma_update(None, "page_size", PAGE_SIZE).unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        for id in 1..=wins {
+            let text = if id == 7 {
+                "overflow ".repeat(400)
+            } else {
+                format!("Win number {} of the recovery test", id)
+            };
+            conn.execute(
+                "INSERT INTO wins (id, date, text, created_at) VALUES (?1, '2025-01-02', ?2, ?1)",
+                (id, text),
+            )
+            .unwrap();
+        }
+        conn.execute(
+            "INSERT INTO deleted_wins (id, date, text, created_at, deleted_at)
+             VALUES (?1, '2025-01-01', 'In the trash', 0, 0)",
+            [wins + 1],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO tags (name) VALUES ('kept')", [])
+            .unwrap();
+        conn.execute("INSERT INTO win_tags (win_id, tag_id) VALUES (1, 1)", [])
+            .unwrap();
+        path
+    }
+
+    fn root_page(path: &Path, table: &str) -> u32 {
+        let conn = Connection::open(path).unwrap();
+        conn.query_row(
+            "SELECT rootpage FROM sqlite_master WHERE name = ?1",
+            [table],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    fn overwrite_page(path: &Path, number: u32, fill: u8) {
+        let mut data = fs::read(path).unwrap();
+        let start = (number as usize - 1) * PAGE_SIZE;
+        data[start..start + PAGE_SIZE].fill(fill);
+        fs::write(path, data).unwrap();
+    }
+
+    /// Salvages `path` into a fresh database and returns the report and the
+    /// recovered win ids, log then trash.
+    fn recover(path: &Path) -> (RecoveryReport, Vec<i64>, Vec<i64>) {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        let report = salvage(path, &mut conn);
+        let ids = |table: &str| -> Vec<i64> {
+            let mut stmt = conn
+                .prepare(&format!("SELECT id FROM {} ORDER BY id", table))
+                .unwrap();
+            let ids = stmt
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect();
+            ids
+        };
+        let (wins, deleted) = (ids("wins"), ids("deleted_wins"));
+        let _ = fs::remove_file(path);
+        (report, wins, deleted)
+    }
+
+    #[test]
+    fn short_files_have_no_header() {
+        for len in [0, 16, 18, 20, 99] {
+            let mut data = b"SQLite format 3\0".to_vec();
+            data.resize(len.max(16), 0x04);
+            data.truncate(len);
+            assert!(Pages::parse(data).is_none(), "{} bytes", len);
+        }
+        let mut header = b"SQLite format 3\0".to_vec();
+        header.resize(100, 0);
+        header[16..18].copy_from_slice(&4096u16.to_be_bytes());
+        assert!(Pages::parse(header.clone()).is_some());
+        // 4096 - 255 reserved bytes is still usable; 512 - 255 isn't
+        header[20] = 255;
+        assert!(Pages::parse(header.clone()).is_some());
+        header[16..18].copy_from_slice(&512u16.to_be_bytes());
+        assert!(Pages::parse(header).is_none());
+    }
+
+    #[test]
+    fn truncated_file_is_reported_not_fatal() {
+        let path = std::env::temp_dir().join(format!(
+            "quietwins-recovery-{}-truncated.sqlite",
+            std::process::id()
+        ));
+        fs::write(&path, b"SQLite format 3\0\x10\x00\x01").unwrap();
+        let (report, wins, deleted) = recover(&path);
+        assert!(wins.is_empty() && deleted.is_empty());
+        assert!(report
+            .notes
+            .iter()
+            .any(|n| n.contains("no readable header")));
+    }
+
+    #[test]
+    fn intact_file_comes_back_whole() {
+        let path = journal("intact", 300);
+        let (report, wins, deleted) = recover(&path);
+        assert_eq!(wins, (1..=300).collect::<Vec<_>>());
+        assert_eq!(deleted, vec![301]);
+        assert_eq!((report.rows_lost, report.pages_unreadable), (0, 0));
+    }
+
+    #[test]
+    fn rows_under_a_destroyed_root_are_found_as_orphans() {
+        let path = journal("root", 300);
+        let root = root_page(&path, "wins");
+        overwrite_page(&path, root, 0);
+        let (report, wins, deleted) = recover(&path);
+        assert_eq!(wins, (1..=300).collect::<Vec<_>>());
+        assert_eq!(deleted, vec![301]);
+        assert!(report.pages_unreadable >= 1);
+        assert!(report.notes.iter().any(|n| n.contains("cut off")));
+    }
+
+    #[test]
+    fn overflowing_rows_are_reassembled_from_pages() {
+        let path = journal("overflow", 300);
+        let root = root_page(&path, "wins");
+        overwrite_page(&path, root, 0);
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        salvage(&path, &mut conn);
+        let text: String = conn
+            .query_row("SELECT text FROM wins WHERE id = 7", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(text, "overflow ".repeat(400));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn a_destroyed_leaf_loses_only_its_rows() {
+        let path = journal("leaf", 300);
+        let pages = Pages::open(&path).unwrap();
+        let mut cells = Vec::new();
+        pages.walk(
+            root_page(&path, "wins"),
+            &mut cells,
+            &mut HashSet::new(),
+            &mut 0,
+            &mut 0,
+        );
+        assert_eq!(cells.len(), 300);
+        // Find a leaf under the root that doesn't hold win 7's overflow chain
+        let root = pages.page(root_page(&path, "wins")).unwrap();
+        assert_eq!(root[0], 0x05, "the table should have outgrown one page");
+        let leaf = be_u32(root, cell_offsets(root, 0, 12)[1]).unwrap();
+        let mut lost = Vec::new();
+        for cell in cell_offsets(pages.page(leaf).unwrap(), 0, 8) {
+            lost.push(pages.leaf_cell(pages.page(leaf).unwrap(), cell).unwrap().0);
+        }
+        assert!(!lost.is_empty() && !lost.contains(&7));
+        overwrite_page(&path, leaf, 0xFF);
+        let (report, wins, _) = recover(&path);
+        let expected: Vec<i64> = (1..=300).filter(|id| !lost.contains(id)).collect();
+        assert_eq!(wins, expected);
+        assert!(report.pages_unreadable >= 1);
+    }
+
+    #[test]
+    fn freelist_pages_are_never_salvaged() {
+        let path = journal("freelist", 300);
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute("DELETE FROM wins WHERE id > 10", []).unwrap();
+        }
+        let (_, wins, _) = recover(&path);
+        assert_eq!(wins, (1..=10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn varints() {
+        assert_eq!(varint(&[0x05], 0), Some((5, 1)));
+        assert_eq!(varint(&[0x81, 0x00], 0), Some((128, 2)));
+        assert_eq!(varint(&[0xFF; 9], 0), Some((u64::MAX, 9)));
+        assert_eq!(varint(&[0x81], 0), None);
+        assert_eq!(varint(&[0x01, 0x82, 0x01], 1), Some((257, 2)));
+    }
+
+    #[test]
+    fn records_decode_every_serial_type() {
+        // Header: its length, then NULL, int8, int16, float, 0, 1, text(3), blob(2)
+        let mut record = vec![9, 0, 1, 2, 7, 8, 9, 19, 16];
+        record.push(0xFE); // -2
+        record.extend_from_slice(&300i16.to_be_bytes());
+        record.extend_from_slice(&1.5f64.to_be_bytes());
+        record.extend_from_slice(b"abc");
+        record.extend_from_slice(&[0xDE, 0xAD]);
+        assert_eq!(
+            decode_record(&record),
+            Some(vec![
+                Value::Null,
+                Value::Integer(-2),
+                Value::Integer(300),
+                Value::Real(1.5),
+                Value::Integer(0),
+                Value::Integer(1),
+                Value::Text("abc".to_string()),
+                Value::Blob(vec![0xDE, 0xAD]),
+            ])
+        );
+        // A body cut short, and the reserved serial types
+        assert!(decode_record(&record[..record.len() - 1]).is_none());
+        assert!(decode_record(&[2, 10]).is_none());
+    }
+
+    #[test]
+    fn column_names_skip_constraints() {
+        assert_eq!(
+            column_names(
+                "CREATE TABLE win_tags (
+                    win_id INTEGER NOT NULL,
+                    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+                    \"note\" TEXT DEFAULT (lower('X')),
+                    PRIMARY KEY (win_id, tag_id),
+                    CONSTRAINT c CHECK (win_id > 0)
+                )"
+            ),
+            vec!["win_id", "tag_id", "note"]
+        );
+        assert!(column_names("CREATE TABLE broken").is_empty());
+    }
+
+    #[test]
+    fn only_win_shaped_rows_are_adopted() {
+        let text = |s: &str| Value::Text(s.to_string());
+        let row = win_shaped(
+            4,
+            &[
+                Value::Null,
+                text("2025-01-02"),
+                text("Hi"),
+                Value::Integer(9),
+            ],
+        )
+        .unwrap();
+        assert_eq!(row.get("id"), Some(&Value::Integer(4)));
+        assert_eq!(row.get("created_at"), Some(&Value::Integer(9)));
+        assert!(win_shaped(
+            4,
+            &[Value::Null, text("2025-1-02"), text("Hi"), Value::Null]
+        )
+        .is_none());
+        assert!(win_shaped(4, &[Value::Null, text("2025-01-02"), text("Hi")]).is_none());
+        assert!(win_shaped(
+            4,
+            &[
+                Value::Integer(1),
+                Value::Integer(2),
+                text("Hi"),
+                Value::Null
+            ]
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,290 @@
+// search.rs - Full-text search over wins using the wins_fts FTS5 index
+
+use crate::db::{self, Win};
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct SearchHit {
+    pub win: Win,
+    /// HTML-escaped excerpt of the win text with matches wrapped in <mark>
+    pub snippet: String,
+    /// bm25 score from FTS5; lower is a better match
+    pub rank: f64,
+}
+
+/// Requires every tag in the JSON array bound to ?2 to be on the win, itself or
+/// through a child tag.
+const TAG_FILTER: &str = "(SELECT COUNT(DISTINCT t.id) FROM win_tags_rollup wt \
+     JOIN tags t ON t.id = wt.tag_id \
+     WHERE wt.win_id = w.id AND t.name IN (SELECT value FROM json_each(?2))) \
+     = json_array_length(?2)";
+
+/// Searches win text, optionally narrowed to wins carrying all of `tags`.
+/// An empty query with tags returns the tagged wins newest first.
+pub fn search_wins(
+    conn: &Connection,
+    query: &str,
+    tags: &[String],
+    limit: usize,
+) -> Result<Vec<SearchHit>> {
+    let fts_query = to_fts_query(query);
+    let tags = db::parse_tag_string(&tags.join(","));
+    let tag_json = serde_json::to_string(&tags).unwrap_or_else(|_| "[]".to_string());
+    println!(
+        "[search_wins] query: {:?} -> {:?}, tags: {:?}",
+        query, fts_query, tags
+    );
+    let sql = if fts_query.is_empty() {
+        if tags.is_empty() {
+            return Ok(Vec::new());
+        }
+        format!(
+            "SELECT {}, w.text, 0.0
+             FROM wins w
+             WHERE {}
+             ORDER BY w.created_at DESC
+             LIMIT ?3",
+            db::win_columns(),
+            TAG_FILTER
+        )
+    } else {
+        format!(
+            "SELECT {},
+                    snippet(wins_fts, 0, char(1), char(2), '…', 16),
+                    bm25(wins_fts)
+             FROM wins_fts JOIN wins w ON w.id = wins_fts.rowid
+             WHERE wins_fts MATCH ?1 AND {}
+             ORDER BY bm25(wins_fts)
+             LIMIT ?3",
+            db::win_columns(),
+            TAG_FILTER
+        )
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let hits = stmt.query_map((&fts_query, &tag_json, limit as i64), |row| {
+        Ok(SearchHit {
+            win: db::win_from_row(row)?,
+            snippet: highlight(&row.get::<_, String>(db::WIN_COLUMN_COUNT)?),
+            rank: row.get(db::WIN_COLUMN_COUNT + 1)?,
+        })
+    })?;
+    let mut result = Vec::new();
+    for hit in hits {
+        result.push(hit?);
+    }
+    println!("[search_wins] Returning {} hits", result.len());
+    Ok(result)
+}
+
+/// Turns user input into an FTS5 query. `"quoted phrases"` stay phrases,
+/// `word*` becomes a prefix match, `OR` is passed through, and every other
+/// word is quoted so punctuation can never cause an FTS5 syntax error.
+fn to_fts_query(input: &str) -> String {
+    let mut terms: Vec<String> = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '"' {
+            chars.next();
+            let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            if has_word_chars(&phrase) {
+                terms.push(quote(&phrase));
+            }
+            continue;
+        }
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '"' {
+                break;
+            }
+            word.push(c);
+            chars.next();
+        }
+        if word == "OR" {
+            if terms.last().is_some_and(|t| t != "OR") {
+                terms.push(word);
+            }
+        } else if let Some(stem) = word.strip_suffix('*') {
+            let stem = stem.trim_end_matches('*');
+            if has_word_chars(stem) {
+                terms.push(format!("{}*", quote(stem)));
+            }
+        } else if has_word_chars(&word) {
+            terms.push(quote(&word));
+        }
+    }
+    if terms.last().is_some_and(|t| t == "OR") {
+        terms.pop();
+    }
+    terms.join(" ")
+}
+
+fn has_word_chars(s: &str) -> bool {
+    s.chars().any(char::is_alphanumeric)
+}
+
+fn quote(term: &str) -> String {
+    format!("\"{}\"", term.replace('"', "\"\""))
+}
+
+/// Escapes the snippet for display and swaps the \x01/\x02 match markers
+/// emitted by `snippet()` for <mark> tags.
+fn highlight(snippet: &str) -> String {
+    let mut out = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            '\u{1}' => out.push_str("<mark>"),
+            '\u{2}' => out.push_str("</mark>"),
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn journal() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        for text in [
+            "Ran a half marathon",
+            "Marathon training: 20 miles",
+            "Fixed the C++ build (finally)",
+            "Wrote \"hello world\" in Rust",
+        ] {
+            conn.execute(
+                "INSERT INTO wins (date, text, created_at) VALUES ('2025-01-01', ?1, 0)",
+                [text],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn texts(conn: &Connection, query: &str) -> Vec<String> {
+        let mut texts: Vec<String> = search_wins(conn, query, &[], 10)
+            .unwrap()
+            .into_iter()
+            .map(|hit| hit.win.text)
+            .collect();
+        texts.sort();
+        texts
+    }
+
+    #[test]
+    fn words_are_quoted() {
+        assert_eq!(to_fts_query("half marathon"), "\"half\" \"marathon\"");
+        assert_eq!(to_fts_query("  "), "");
+    }
+
+    #[test]
+    fn phrases_stay_phrases() {
+        assert_eq!(
+            to_fts_query("\"half marathon\" ran"),
+            "\"half marathon\" \"ran\""
+        );
+        // An unclosed quote runs to the end of the input
+        assert_eq!(to_fts_query("\"half marathon"), "\"half marathon\"");
+        assert_eq!(to_fts_query("\"\" \"...\""), "");
+    }
+
+    #[test]
+    fn prefix_terms() {
+        assert_eq!(to_fts_query("mara*"), "\"mara\"*");
+        assert_eq!(to_fts_query("mara**"), "\"mara\"*");
+        assert_eq!(to_fts_query("* **"), "");
+    }
+
+    #[test]
+    fn or_needs_terms_on_both_sides() {
+        assert_eq!(to_fts_query("ran OR wrote"), "\"ran\" OR \"wrote\"");
+        assert_eq!(
+            to_fts_query("OR ran OR OR wrote OR"),
+            "\"ran\" OR \"wrote\""
+        );
+        // Only the upper-case keyword is an operator
+        assert_eq!(to_fts_query("ran or wrote"), "\"ran\" \"or\" \"wrote\"");
+    }
+
+    #[test]
+    fn fts_syntax_is_escaped() {
+        assert_eq!(to_fts_query("C++ (finally)"), "\"C++\" \"(finally)\"");
+        assert_eq!(to_fts_query("NOT AND NEAR"), "\"NOT\" \"AND\" \"NEAR\"");
+        assert_eq!(to_fts_query("text:rust -build"), "\"text:rust\" \"-build\"");
+        assert_eq!(to_fts_query("hello\"world"), "\"hello\" \"world\"");
+        assert_eq!(to_fts_query("^ - : ( )"), "");
+    }
+
+    #[test]
+    fn awkward_input_never_fails() {
+        let conn = journal();
+        for query in [
+            "C++",
+            "(finally",
+            "\"hello",
+            "NOT",
+            "AND OR",
+            "NEAR(",
+            "text:rust",
+            "-marathon",
+            "***",
+            "mara* OR",
+            "\"\"\"",
+            "{col}",
+        ] {
+            assert!(search_wins(&conn, query, &[], 10).is_ok(), "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn queries_find_the_right_wins() {
+        let conn = journal();
+        assert_eq!(
+            texts(&conn, "marathon"),
+            vec!["Marathon training: 20 miles", "Ran a half marathon"]
+        );
+        assert_eq!(
+            texts(&conn, "\"half marathon\""),
+            vec!["Ran a half marathon"]
+        );
+        assert_eq!(texts(&conn, "\"marathon half\""), Vec::<String>::new());
+        assert_eq!(texts(&conn, "train*"), vec!["Marathon training: 20 miles"]);
+        assert_eq!(
+            texts(&conn, "miles OR rust"),
+            vec![
+                "Marathon training: 20 miles",
+                "Wrote \"hello world\" in Rust"
+            ]
+        );
+        assert_eq!(
+            texts(&conn, "\"hello world\""),
+            vec!["Wrote \"hello world\" in Rust"]
+        );
+    }
+
+    #[test]
+    fn snippets_are_escaped_and_marked() {
+        assert_eq!(
+            highlight("a \u{1}<b>\u{2} & \"c\" 'd'"),
+            "a <mark>&lt;b&gt;</mark> &amp; &quot;c&quot; &#39;d&#39;"
+        );
+        let conn = journal();
+        let hits = search_wins(&conn, "hello", &[], 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0]
+            .snippet
+            .contains("&quot;<mark>hello</mark> world&quot;"));
+    }
+}
@@ -0,0 +1,321 @@
+// similarity.rs - TF-IDF vectors over win text and tags, compared by cosine similarity
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Tags that label a kind of thing rather than a thing: entity labels from
+/// the NLP service, sentiment and the catch-all.
+pub(crate) const GENERIC_TAGS: &[&str] = &[
+    "misc",
+    "positive",
+    "neutral",
+    "negative",
+    "person",
+    "org",
+    "gpe",
+    "loc",
+    "fac",
+    "norp",
+    "product",
+    "event",
+    "work_of_art",
+    "law",
+    "language",
+    "date",
+    "time",
+    "percent",
+    "money",
+    "quantity",
+    "ordinal",
+    "cardinal",
+];
+
+/// Common English words plus the verbs every win uses ("finished", "got").
+const STOPWORDS: &str = "a about above after again all also am an and any are as at be been \
+    before being below between both but by can could did do does doing done down during each \
+    few for from further had has have having he her here hers him his how i if in into is it its \
+    just me more most much my no nor not now of off on once one only or other our ours out over \
+    own really same she should so some still such than that the their them then there these they \
+    this those through to too two three under until up us very was we were what when where which \
+    while who whom why will with would you your yours today yesterday tonight week weekend \
+    morning evening day finally finished finish completed complete started start got get made \
+    make did went go going able managed first last new big little lot lots";
+
+/// A term shared by several wins, with how strongly it links them.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SharedTerm {
+    /// The most common spelling of the term, e.g. "training" for `train`
+    pub term: String,
+    /// Wins that contain it
+    pub wins: usize,
+    /// Its summed TF-IDF weight over those wins
+    pub weight: f64,
+}
+
+/// Unit-length TF-IDF vectors for a set of wins, so the dot product of two
+/// vectors is their cosine similarity.
+pub(crate) struct Corpus {
+    /// Display form of each term, by term id
+    terms: Vec<String>,
+    /// One sparse vector per win, sorted by term id
+    pub vectors: Vec<Vec<(usize, f64)>>,
+}
+
+impl Corpus {
+    /// Builds vectors for `(text, tags)` pairs. Terms are stemmed words of at
+    /// least three letters that aren't stopwords, plus the specific tags.
+    pub fn build<'a>(docs: impl IntoIterator<Item = (&'a str, &'a [String])>) -> Corpus {
+        let stopwords: HashSet<&str> = STOPWORDS.split_whitespace().collect();
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        let mut spellings: Vec<HashMap<String, usize>> = Vec::new();
+        let mut counts: Vec<HashMap<usize, f64>> = Vec::new();
+        for (text, tags) in docs {
+            let mut tf: HashMap<usize, f64> = HashMap::new();
+            let tag_words = tags
+                .iter()
+                .filter(|t| !GENERIC_TAGS.contains(&t.to_lowercase().as_str()))
+                .flat_map(|t| words(t));
+            for word in words(text).into_iter().chain(tag_words) {
+                let stemmed = stem(&word);
+                if stopwords.contains(word.as_str()) || stopwords.contains(stemmed.as_str()) {
+                    continue;
+                }
+                let next = ids.len();
+                let id = *ids.entry(stemmed).or_insert(next);
+                if id == spellings.len() {
+                    spellings.push(HashMap::new());
+                }
+                *spellings[id].entry(word).or_insert(0) += 1;
+                *tf.entry(id).or_insert(0.0) += 1.0;
+            }
+            counts.push(tf);
+        }
+
+        let mut df = vec![0usize; ids.len()];
+        for id in counts.iter().flat_map(|tf| tf.keys()) {
+            df[*id] += 1;
+        }
+        let n = counts.len() as f64;
+        let vectors = counts
+            .into_iter()
+            .map(|tf| {
+                let mut v: Vec<(usize, f64)> = tf
+                    .into_iter()
+                    .map(|(id, count)| {
+                        let idf = ((1.0 + n) / (1.0 + df[id] as f64)).ln() + 1.0;
+                        (id, (1.0 + count.ln()) * idf)
+                    })
+                    .collect();
+                let norm = v.iter().map(|(_, w)| w * w).sum::<f64>().sqrt();
+                for (_, w) in v.iter_mut() {
+                    *w /= norm;
+                }
+                v.sort_by_key(|(id, _)| *id);
+                v
+            })
+            .collect();
+        let terms = spellings
+            .into_iter()
+            .map(|forms| {
+                forms
+                    .into_iter()
+                    .max_by(|a, b| a.1.cmp(&b.1).then(b.0.len().cmp(&a.0.len())))
+                    .map(|(form, _)| form)
+                    .unwrap_or_default()
+            })
+            .collect();
+        Corpus { terms, vectors }
+    }
+
+    /// Terms found in at least two of `members`, strongest first: what the
+    /// wins have in common.
+    pub fn shared_terms(&self, members: &[usize], limit: usize) -> Vec<SharedTerm> {
+        let mut totals: HashMap<usize, (usize, f64)> = HashMap::new();
+        for &m in members {
+            for &(id, w) in &self.vectors[m] {
+                let entry = totals.entry(id).or_insert((0, 0.0));
+                entry.0 += 1;
+                entry.1 += w;
+            }
+        }
+        let mut shared: Vec<SharedTerm> = totals
+            .into_iter()
+            .filter(|(_, (wins, _))| *wins >= 2)
+            .map(|(id, (wins, weight))| SharedTerm {
+                term: self.terms[id].clone(),
+                wins,
+                weight,
+            })
+            .collect();
+        shared.sort_by(|a, b| b.weight.total_cmp(&a.weight).then(a.term.cmp(&b.term)));
+        shared.truncate(limit);
+        shared
+    }
+}
+
+/// Lowercased words of at least three characters, numbers left out.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= 3 && !w.chars().all(|c| c.is_ascii_digit()))
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// A light suffix stripper in the spirit of Porter's: enough that "trained",
+/// "training" and "trains" meet at `train`. Words it doesn't understand,
+/// including non-ASCII ones, are left as they are.
+pub(crate) fn stem(word: &str) -> String {
+    if word.len() <= 3 || !word.is_ascii() {
+        return word.to_string();
+    }
+    let mut w = word.to_string();
+    if let Some(s) = w.strip_suffix("sses") {
+        w = format!("{}ss", s);
+    } else if let Some(s) = w.strip_suffix("ies") {
+        w = format!("{}y", s);
+    } else if w.ends_with('s') && !w.ends_with("ss") && !w.ends_with("us") && !w.ends_with("is") {
+        w.pop();
+    }
+    for suffix in ["ness", "ment", "ful", "ly"] {
+        if w.len() >= suffix.len() + 4 && w.ends_with(suffix) {
+            w.truncate(w.len() - suffix.len());
+            break;
+        }
+    }
+    if let Some(s) = w.strip_suffix("ied") {
+        w = format!("{}y", s);
+    } else {
+        for suffix in ["ing", "ed"] {
+            let Some(s) = w.strip_suffix(suffix) else {
+                continue;
+            };
+            if s.len() >= 3 && s.chars().any(|c| "aeiouy".contains(c)) {
+                w = s.to_string();
+                let b = w.as_bytes();
+                let n = b.len();
+                if b[n - 1] == b[n - 2] && !b"aeioulsz".contains(&b[n - 1]) {
+                    w.pop();
+                }
+            }
+            break;
+        }
+    }
+    if w.len() >= 4 && w.ends_with('e') {
+        w.pop();
+    }
+    w
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus(docs: &[(&str, &[&str])]) -> Corpus {
+        let tags: Vec<Vec<String>> = docs
+            .iter()
+            .map(|(_, tags)| tags.iter().map(|t| t.to_string()).collect())
+            .collect();
+        Corpus::build(
+            docs.iter()
+                .zip(&tags)
+                .map(|((text, _), tags)| (*text, tags.as_slice())),
+        )
+    }
+
+    fn cosine(corpus: &Corpus, a: usize, b: usize) -> f64 {
+        let other: HashMap<usize, f64> = corpus.vectors[b].iter().copied().collect();
+        corpus.vectors[a]
+            .iter()
+            .map(|(id, w)| w * other.get(id).copied().unwrap_or(0.0))
+            .sum()
+    }
+
+    #[test]
+    fn word_forms_stem_together() {
+        for word in ["trained", "training", "trains", "train"] {
+            assert_eq!(stem(word), "train", "{}", word);
+        }
+        assert_eq!(stem("studies"), "study");
+        assert_eq!(stem("studied"), "study");
+        assert_eq!(stem("running"), "run");
+        assert_eq!(stem("classes"), "class");
+        // Short, unknown and non-ASCII words are left alone
+        assert_eq!(stem("bus"), "bus");
+        assert_eq!(stem("café"), "café");
+    }
+
+    #[test]
+    fn word_forms_link_wins() {
+        let c = corpus(&[
+            ("Trained for the marathon", &[]),
+            ("Training run along the river", &[]),
+            ("Rested", &[]),
+        ]);
+        assert!(cosine(&c, 0, 1) > 0.0);
+        assert_eq!(cosine(&c, 0, 2), 0.0);
+        let shared = c.shared_terms(&[0, 1], 5);
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].wins, 2);
+    }
+
+    #[test]
+    fn stopwords_never_link_wins() {
+        let c = corpus(&[
+            ("Finally finished the tax return", &[]),
+            ("Finished painting the kitchen today", &[]),
+            ("Got started on it this morning", &[]),
+        ]);
+        assert_eq!(cosine(&c, 0, 1), 0.0);
+        assert!(c.shared_terms(&[0, 1], 5).is_empty());
+        // Nothing but stopwords leaves no vector at all
+        assert!(c.vectors[2].is_empty());
+    }
+
+    #[test]
+    fn generic_tags_are_ignored() {
+        let c = corpus(&[
+            ("Called grandma", &["person", "positive", "misc", "family"]),
+            ("Fixed the bike", &["PERSON", "positive", "misc"]),
+            ("Hosted dinner", &["family"]),
+        ]);
+        assert_eq!(cosine(&c, 0, 1), 0.0);
+        assert!(cosine(&c, 0, 2) > 0.0);
+        let shared = c.shared_terms(&[0, 1, 2], 5);
+        assert_eq!(
+            shared.iter().map(|t| t.term.as_str()).collect::<Vec<_>>(),
+            vec!["family"]
+        );
+    }
+
+    #[test]
+    fn vectors_have_unit_length() {
+        let c = corpus(&[
+            ("Wrote the thesis introduction", &["thesis"]),
+            ("Thesis thesis thesis", &[]),
+            ("Swam two kilometres in the lake", &["swim", "exercise"]),
+        ]);
+        for v in &c.vectors {
+            let norm: f64 = v.iter().map(|(_, w)| w * w).sum();
+            assert!((norm - 1.0).abs() < 1e-9, "{}", norm);
+        }
+        assert!((cosine(&c, 0, 0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn shared_terms_need_two_wins() {
+        let c = corpus(&[
+            ("Garden weeding and planting", &[]),
+            ("Planted tomatoes in the garden", &[]),
+            ("Garden shed tidy", &[]),
+        ]);
+        let shared = c.shared_terms(&[0, 1, 2], 5);
+        assert!(shared.iter().all(|t| t.wins >= 2));
+        let terms: Vec<(&str, usize)> = shared.iter().map(|t| (t.term.as_str(), t.wins)).collect();
+        assert_eq!(terms[0], ("garden", 3));
+        assert!(terms.iter().any(|(t, n)| t.starts_with("plant") && *n == 2));
+        assert!(!terms.iter().any(|(t, _)| *t == "shed" || *t == "tomatoes"));
+        // A single win shares nothing with itself
+        assert!(c.shared_terms(&[0], 5).is_empty());
+        assert_eq!(c.shared_terms(&[0, 1, 2], 1).len(), 1);
+    }
+}
@@ -0,0 +1,522 @@
+// stats.rs - Recap statistics shared by the Recap view and the weekly notification
+
+use crate::db::{self, Win};
+use crate::tags::Taxonomy;
+use chrono::{Datelike, Days, Months, NaiveDate};
+use rusqlite::{Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+const TOP_TAGS: usize = 5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum RecapPeriod {
+    /// The 7 days ending today
+    #[serde(rename = "7d")]
+    Last7Days,
+    #[serde(rename = "30d")]
+    Last30Days,
+    /// The calendar week (Monday to Sunday) containing today
+    #[serde(rename = "week")]
+    Week,
+    #[serde(rename = "month")]
+    Month,
+    #[serde(rename = "year")]
+    Year,
+}
+
+impl RecapPeriod {
+    /// First and last day of the period containing `today`, inclusive. Only
+    /// the rolling periods end today; calendar periods run to their last day.
+    fn bounds(self, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+        let back = |days: u64| today.checked_sub_days(Days::new(days)).unwrap_or(today);
+        match self {
+            RecapPeriod::Last7Days => (back(6), today),
+            RecapPeriod::Last30Days => (back(29), today),
+            RecapPeriod::Week => {
+                let start = back(today.weekday().num_days_from_monday() as u64);
+                (start, start + Days::new(6))
+            }
+            RecapPeriod::Month => {
+                let start = today.with_day(1).unwrap_or(today);
+                let end = start
+                    .checked_add_months(Months::new(1))
+                    .and_then(|d| d.pred_opt())
+                    .unwrap_or(today);
+                (start, end)
+            }
+            RecapPeriod::Year => (
+                NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap_or(today),
+                NaiveDate::from_ymd_opt(today.year(), 12, 31).unwrap_or(today),
+            ),
+        }
+    }
+
+    /// The period just before the one containing `today`, of the same kind.
+    fn previous_bounds(self, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+        let (start, end) = self.bounds(today);
+        match self {
+            RecapPeriod::Month => self.bounds(start.pred_opt().unwrap_or(start)),
+            RecapPeriod::Year => self.bounds(start.pred_opt().unwrap_or(start)),
+            _ => {
+                let len = (end - start).num_days() as u64 + 1;
+                (start - Days::new(len), start - Days::new(1))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct DayCount {
+    pub date: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+/// Totals for the previous period, for "up 3 from last week".
+#[derive(Serialize)]
+pub struct Comparison {
+    pub start: String,
+    pub end: String,
+    pub total: usize,
+    pub avg_mood: Option<f64>,
+    /// `total` minus the previous total
+    pub change: i64,
+    /// Percent change; `None` when the previous period had no wins
+    pub change_pct: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct Recap {
+    pub period: RecapPeriod,
+    pub start: String,
+    pub end: String,
+    pub days: i64,
+    pub total: usize,
+    pub per_day: f64,
+    /// One entry per day of the period, zeros included
+    pub series: Vec<DayCount>,
+    /// Parent tags include their children's wins
+    pub top_tags: Vec<TagCount>,
+    /// Means over the wins that were rated; unrated wins don't count as zero
+    pub avg_mood: Option<f64>,
+    pub avg_energy: Option<f64>,
+    pub avg_impact: Option<f64>,
+    pub best_day: Option<DayCount>,
+    /// Tags first used during this period
+    pub new_tags: Vec<String>,
+    pub top_impact: Option<Win>,
+    pub previous: Comparison,
+    /// Newest first
+    pub wins: Vec<Win>,
+}
+
+pub fn get_recap(conn: &Connection, period: RecapPeriod, today: NaiveDate) -> Result<Recap> {
+    let (start, end) = period.bounds(today);
+    let (prev_start, prev_end) = period.previous_bounds(today);
+    let wins = wins_between(conn, start, end)?;
+    let previous = wins_between(conn, prev_start, prev_end)?;
+
+    let mut per_day: BTreeMap<NaiveDate, usize> = start
+        .iter_days()
+        .take_while(|d| *d <= end)
+        .map(|d| (d, 0))
+        .collect();
+    for win in &wins {
+        if let Some(n) = NaiveDate::parse_from_str(&win.date, "%Y-%m-%d")
+            .ok()
+            .and_then(|d| per_day.get_mut(&d))
+        {
+            *n += 1;
+        }
+    }
+    let series: Vec<DayCount> = per_day
+        .iter()
+        .map(|(d, count)| DayCount {
+            date: d.format("%Y-%m-%d").to_string(),
+            count: *count,
+        })
+        .collect();
+    // Earliest of the busiest days
+    let best_day = series
+        .iter()
+        .filter(|d| d.count > 0)
+        .fold(None::<&DayCount>, |best, d| match best {
+            Some(b) if b.count >= d.count => Some(b),
+            _ => Some(d),
+        })
+        .cloned();
+
+    let days = series.len() as i64;
+    let total = wins.len();
+    let prev_total = previous.len();
+    let top_impact = wins
+        .iter()
+        .filter(|w| w.ratings.impact.is_some())
+        .fold(None::<&Win>, |best, w| match best {
+            Some(b) if b.ratings.impact >= w.ratings.impact => Some(b),
+            _ => Some(w),
+        })
+        .cloned();
+    Ok(Recap {
+        period,
+        start: start.format("%Y-%m-%d").to_string(),
+        end: end.format("%Y-%m-%d").to_string(),
+        days,
+        total,
+        per_day: if days > 0 {
+            total as f64 / days as f64
+        } else {
+            0.0
+        },
+        series,
+        top_tags: top_tags(&wins, &Taxonomy::load(conn)?, TOP_TAGS),
+        avg_mood: average(wins.iter().map(|w| w.ratings.mood)),
+        avg_energy: average(wins.iter().map(|w| w.ratings.energy)),
+        avg_impact: average(wins.iter().map(|w| w.ratings.impact)),
+        best_day,
+        new_tags: new_tags(conn, start, end)?,
+        top_impact,
+        previous: Comparison {
+            start: prev_start.format("%Y-%m-%d").to_string(),
+            end: prev_end.format("%Y-%m-%d").to_string(),
+            total: prev_total,
+            avg_mood: average(previous.iter().map(|w| w.ratings.mood)),
+            change: total as i64 - prev_total as i64,
+            change_pct: (prev_total > 0)
+                .then(|| (total as f64 - prev_total as f64) * 100.0 / prev_total as f64),
+        },
+        wins,
+    })
+}
+
+/// Body of the weekly recap notification.
+pub fn notification_text(recap: &Recap) -> String {
+    if recap.total == 0 {
+        return "No wins logged this week. Start a new streak!".to_string();
+    }
+    let tags = recap
+        .top_tags
+        .iter()
+        .take(3)
+        .map(|t| t.tag.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut body = format!(
+        "You logged {} wins this week! Top tags: {}",
+        recap.total, tags
+    );
+    if recap.previous.change != 0 && recap.previous.total > 0 {
+        body.push_str(&format!(
+            " ({} {} from last week)",
+            if recap.previous.change > 0 {
+                "up"
+            } else {
+                "down"
+            },
+            recap.previous.change.abs()
+        ));
+    }
+    if let Some(avg) = recap.avg_mood {
+        body.push_str(&format!("\nAverage mood: {:.1}/5", avg));
+    }
+    if let Some(win) = &recap.top_impact {
+        body.push_str(&format!("\nBiggest impact: {}", win.text));
+    }
+    body
+}
+
+fn wins_between(conn: &Connection, start: NaiveDate, end: NaiveDate) -> Result<Vec<Win>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM wins w WHERE w.date BETWEEN ?1 AND ?2
+         ORDER BY w.date DESC, w.created_at DESC",
+        db::win_columns()
+    ))?;
+    let wins = stmt.query_map(
+        [
+            start.format("%Y-%m-%d").to_string(),
+            end.format("%Y-%m-%d").to_string(),
+        ],
+        db::win_from_row,
+    )?;
+    wins.collect()
+}
+
+/// Most used first, ties alphabetical. A win counts once toward each of its
+/// tags and their ancestors.
+fn top_tags(wins: &[Win], taxonomy: &Taxonomy, limit: usize) -> Vec<TagCount> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for tag in wins.iter().flat_map(|w| taxonomy.expand(&w.tags)) {
+        *counts.entry(tag).or_insert(0) += 1;
+    }
+    let mut tags: Vec<TagCount> = counts
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag, count })
+        .collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    tags.truncate(limit);
+    tags
+}
+
+fn new_tags(conn: &Connection, start: NaiveDate, end: NaiveDate) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.name FROM tags t
+         JOIN win_tags wt ON wt.tag_id = t.id
+         JOIN wins w ON w.id = wt.win_id
+         GROUP BY t.id
+         HAVING MIN(w.date) BETWEEN ?1 AND ?2
+         ORDER BY MIN(w.date), t.name",
+    )?;
+    let tags = stmt.query_map(
+        [
+            start.format("%Y-%m-%d").to_string(),
+            end.format("%Y-%m-%d").to_string(),
+        ],
+        |row| row.get(0),
+    )?;
+    tags.collect()
+}
+
+fn average(values: impl Iterator<Item = Option<i64>>) -> Option<f64> {
+    let rated: Vec<i64> = values.flatten().collect();
+    (!rated.is_empty()).then(|| rated.iter().sum::<i64>() as f64 / rated.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn span(bounds: (NaiveDate, NaiveDate)) -> (String, String) {
+        (bounds.0.to_string(), bounds.1.to_string())
+    }
+
+    fn days(start: &str, end: &str) -> (String, String) {
+        (start.to_string(), end.to_string())
+    }
+
+    fn journal(wins: &[(&str, Option<i64>)]) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        for (date, mood) in wins {
+            let ratings = db::Ratings {
+                mood: *mood,
+                ..Default::default()
+            };
+            db::add_win(&mut conn, date, "Did a thing", "", ratings).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn months_cover_the_calendar_month() {
+        let month = RecapPeriod::Month;
+        assert_eq!(
+            span(month.bounds(date("2025-01-31"))),
+            days("2025-01-01", "2025-01-31")
+        );
+        assert_eq!(
+            span(month.previous_bounds(date("2025-01-31"))),
+            days("2024-12-01", "2024-12-31")
+        );
+        assert_eq!(
+            span(month.bounds(date("2024-02-10"))),
+            days("2024-02-01", "2024-02-29")
+        );
+        assert_eq!(
+            span(month.previous_bounds(date("2025-03-31"))),
+            days("2025-02-01", "2025-02-28")
+        );
+    }
+
+    #[test]
+    fn years_cover_the_calendar_year() {
+        let year = RecapPeriod::Year;
+        assert_eq!(
+            span(year.bounds(date("2025-01-01"))),
+            days("2025-01-01", "2025-12-31")
+        );
+        assert_eq!(
+            span(year.previous_bounds(date("2025-12-31"))),
+            days("2024-01-01", "2024-12-31")
+        );
+    }
+
+    #[test]
+    fn weeks_and_rolling_periods_step_back_by_their_length() {
+        // 2025-01-01 is a Wednesday, so its week starts in 2024
+        let week = RecapPeriod::Week;
+        assert_eq!(
+            span(week.bounds(date("2025-01-01"))),
+            days("2024-12-30", "2025-01-05")
+        );
+        assert_eq!(
+            span(week.previous_bounds(date("2025-01-01"))),
+            days("2024-12-23", "2024-12-29")
+        );
+        assert_eq!(
+            span(RecapPeriod::Last7Days.bounds(date("2025-03-02"))),
+            days("2025-02-24", "2025-03-02")
+        );
+        assert_eq!(
+            span(RecapPeriod::Last30Days.previous_bounds(date("2025-03-01"))),
+            days("2025-01-01", "2025-01-30")
+        );
+    }
+
+    #[test]
+    fn recap_counts_the_month() {
+        let conn = journal(&[
+            ("2024-12-31", None),
+            ("2025-01-01", Some(2)),
+            ("2025-01-05", Some(4)),
+            ("2025-01-05", None),
+            ("2025-01-31", None),
+            ("2025-02-01", None),
+        ]);
+        let recap = get_recap(&conn, RecapPeriod::Month, date("2025-01-15")).unwrap();
+        assert_eq!(
+            (recap.start.as_str(), recap.end.as_str()),
+            ("2025-01-01", "2025-01-31")
+        );
+        assert_eq!((recap.days, recap.total), (31, 4));
+        assert_eq!(recap.series.len(), 31);
+        assert_eq!(recap.series[4].count, 2);
+        assert_eq!(recap.avg_mood, Some(3.0));
+        assert_eq!(
+            recap.best_day,
+            Some(DayCount {
+                date: "2025-01-05".to_string(),
+                count: 2
+            })
+        );
+        assert_eq!(recap.previous.total, 1);
+        assert_eq!(recap.previous.change, 3);
+        assert_eq!(recap.previous.change_pct, Some(300.0));
+    }
+
+    #[test]
+    fn best_day_ties_go_to_the_earliest() {
+        let conn = journal(&[
+            ("2025-01-09", None),
+            ("2025-01-03", None),
+            ("2025-01-07", None),
+            ("2025-01-03", None),
+            ("2025-01-07", None),
+        ]);
+        let recap = get_recap(&conn, RecapPeriod::Month, date("2025-01-20")).unwrap();
+        assert_eq!(recap.best_day.unwrap().date, "2025-01-03");
+    }
+
+    #[test]
+    fn nothing_before_means_no_percentage() {
+        let conn = journal(&[("2025-01-02", None), ("2025-01-03", None)]);
+        let recap = get_recap(&conn, RecapPeriod::Year, date("2025-06-01")).unwrap();
+        assert_eq!(recap.previous.total, 0);
+        assert_eq!(recap.previous.change, 2);
+        assert_eq!(recap.previous.change_pct, None);
+
+        // An empty period against a busy one is a 100% drop
+        let recap = get_recap(&conn, RecapPeriod::Year, date("2026-06-01")).unwrap();
+        assert_eq!(recap.previous.change_pct, Some(-100.0));
+        assert!(recap.best_day.is_none() && recap.avg_mood.is_none());
+    }
+
+    fn rated(mood: Option<i64>, energy: Option<i64>, impact: Option<i64>) -> db::Ratings {
+        db::Ratings {
+            mood,
+            energy,
+            impact,
+        }
+    }
+
+    #[test]
+    fn ratings_stay_on_the_scale() {
+        assert!(rated(Some(1), Some(5), None).validate().is_ok());
+        assert!(db::Ratings::default().validate().is_ok());
+        for bad in [
+            rated(Some(0), None, None),
+            rated(None, Some(6), None),
+            rated(None, None, Some(-1)),
+        ] {
+            let err = bad.validate().unwrap_err();
+            assert!(err.to_string().contains("between 1 and 5"));
+        }
+
+        let mut conn = journal(&[("2025-01-02", Some(3))]);
+        assert!(db::add_win(
+            &mut conn,
+            "2025-01-02",
+            "Too good",
+            "",
+            rated(None, None, Some(6))
+        )
+        .is_err());
+        assert!(db::update_win(
+            &mut conn,
+            1,
+            "2025-01-02",
+            "Too low",
+            "",
+            rated(Some(0), None, None)
+        )
+        .is_err());
+        // The columns refuse them too, whatever the caller
+        for column in ["mood", "energy", "impact"] {
+            let sql = format!("UPDATE wins SET {} = 9 WHERE id = 1", column);
+            assert!(conn.execute(&sql, []).is_err(), "{}", column);
+        }
+        let mood: Option<i64> = conn
+            .query_row("SELECT mood FROM wins WHERE id = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mood, Some(3));
+    }
+
+    #[test]
+    fn recap_averages_ratings_and_finds_the_biggest_impact() {
+        let mut conn = journal(&[]);
+        for (date, text, ratings) in [
+            ("2025-01-06", "Shipped it", rated(Some(4), Some(2), Some(5))),
+            ("2025-01-07", "Tidied up", rated(Some(2), None, Some(3))),
+            ("2025-01-08", "Unrated", db::Ratings::default()),
+            ("2025-01-09", "Felt great", rated(Some(5), Some(3), None)),
+        ] {
+            db::add_win(&mut conn, date, text, "", ratings).unwrap();
+        }
+        let recap = get_recap(&conn, RecapPeriod::Week, date("2025-01-09")).unwrap();
+        assert_eq!(recap.total, 4);
+        assert_eq!(recap.avg_mood, Some(11.0 / 3.0));
+        assert_eq!(recap.avg_energy, Some(2.5));
+        assert_eq!(recap.avg_impact, Some(4.0));
+        assert_eq!(recap.top_impact.as_ref().unwrap().text, "Shipped it");
+
+        let text = notification_text(&recap);
+        assert!(text.starts_with("You logged 4 wins this week!"));
+        assert!(text.contains("\nAverage mood: 3.7/5"));
+        assert!(text.ends_with("\nBiggest impact: Shipped it"));
+    }
+
+    #[test]
+    fn unrated_weeks_leave_ratings_out_of_the_notification() {
+        let conn = journal(&[("2025-01-06", None), ("2025-01-07", None)]);
+        let recap = get_recap(&conn, RecapPeriod::Week, date("2025-01-09")).unwrap();
+        assert!(recap.avg_impact.is_none() && recap.top_impact.is_none());
+        let text = notification_text(&recap);
+        assert!(!text.contains("Average mood") && !text.contains("Biggest impact"));
+
+        let empty = get_recap(&conn, RecapPeriod::Week, date("2025-01-20")).unwrap();
+        assert_eq!(
+            notification_text(&empty),
+            "No wins logged this week. Start a new streak!"
+        );
+    }
+}
@@ -0,0 +1,463 @@
+// streaks.rs - Current and longest streaks under configurable rules
+
+use crate::db::Db;
+use crate::errors;
+use chrono::{Datelike, NaiveDate, Weekday};
+use rusqlite::{ffi, Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+const SETTINGS_FILE: &str = "streak_rules.json";
+
+/// What it takes for a day to keep a streak going.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct StreakRules {
+    /// Saturdays and Sundays neither break nor are needed for a streak
+    pub weekdays_only: bool,
+    pub min_wins_per_day: u32,
+    /// Only wins with this tag (or a tag under it) count
+    pub tag: Option<String>,
+    /// Missed days per calendar month that are bridged automatically
+    pub freezes_per_month: u32,
+}
+
+impl Default for StreakRules {
+    fn default() -> Self {
+        StreakRules {
+            weekdays_only: false,
+            min_wins_per_day: 1,
+            tag: None,
+            freezes_per_month: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Streak {
+    /// Days that met the rules; skipped, rest and frozen days aren't counted
+    pub length: u32,
+    pub start: String,
+    /// Last day that met the rules
+    pub end: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Streaks {
+    /// `None` once a day has been missed; today doesn't count as missed
+    /// until it's over
+    pub current: Option<Streak>,
+    pub longest: Option<Streak>,
+    pub today_done: bool,
+    /// Days in the current streak bridged by a freeze
+    pub frozen_days: Vec<String>,
+    /// Freezes still available this month
+    pub freezes_left: u32,
+    pub rules: StreakRules,
+}
+
+#[derive(Serialize)]
+pub struct RestDay {
+    pub date: String,
+    pub note: String,
+}
+
+fn settings_path(db: &Db) -> std::path::PathBuf {
+    db.path()
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(SETTINGS_FILE)
+}
+
+pub fn get_rules(db: &Db) -> StreakRules {
+    fs::read_to_string(settings_path(db))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn set_rules(db: &Db, rules: &StreakRules) -> Result<(), String> {
+    if rules.min_wins_per_day < 1 {
+        return Err("A streak day needs at least one win.".to_string());
+    }
+    let json = serde_json::to_string_pretty(rules).map_err(|e| e.to_string())?;
+    fs::write(settings_path(db), json).map_err(|e| e.to_string())
+}
+
+pub fn get_streaks(conn: &Connection, rules: &StreakRules, today: NaiveDate) -> Result<Streaks> {
+    let mut stmt = conn.prepare(
+        "SELECT w.date, COUNT(*) FROM wins w
+         WHERE ?1 IS NULL OR EXISTS (
+             SELECT 1 FROM win_tags_rollup wt JOIN tags t ON t.id = wt.tag_id
+             WHERE wt.win_id = w.id AND t.name = ?1)
+         GROUP BY w.date",
+    )?;
+    let rows = stmt.query_map([rules.tag.as_deref()], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+    })?;
+    let mut days = BTreeMap::new();
+    for row in rows {
+        let (date, count) = row?;
+        if let Ok(d) = NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+            *days.entry(d).or_insert(0) += count;
+        }
+    }
+    let rest = list_rest_days(conn)?
+        .iter()
+        .filter_map(|r| NaiveDate::parse_from_str(&r.date, "%Y-%m-%d").ok())
+        .collect();
+    Ok(compute(&days, &rest, rules, today))
+}
+
+/// Walks day by day from the first qualifying day to `today`. Working in
+/// calendar dates rather than timestamps keeps 23- and 25-hour DST days from
+/// shifting anything.
+pub(crate) fn compute(
+    wins_per_day: &BTreeMap<NaiveDate, u32>,
+    rest_days: &HashSet<NaiveDate>,
+    rules: &StreakRules,
+    today: NaiveDate,
+) -> Streaks {
+    let min = rules.min_wins_per_day.max(1);
+    let qualifies = |d: NaiveDate| wins_per_day.get(&d).is_some_and(|n| *n >= min);
+    let optional = |d: NaiveDate| {
+        rest_days.contains(&d)
+            || (rules.weekdays_only && matches!(d.weekday(), Weekday::Sat | Weekday::Sun))
+    };
+
+    let freezes_used_in = |month: (i32, u32), frozen: &[NaiveDate]| {
+        frozen
+            .iter()
+            .filter(|d| (d.year(), d.month()) == month)
+            .count() as u32
+    };
+
+    let mut run: Option<Streak> = None;
+    let mut longest: Option<Streak> = None;
+    // Freezes spent in the current run, and over the whole walk
+    let mut frozen: Vec<NaiveDate> = Vec::new();
+    let mut all_frozen: Vec<NaiveDate> = Vec::new();
+    let first = wins_per_day.keys().copied().find(|d| qualifies(*d));
+    let mut day = first.unwrap_or(today.succ_opt().unwrap_or(today));
+    while day <= today {
+        if qualifies(day) {
+            let date = day.format("%Y-%m-%d").to_string();
+            let streak = run.get_or_insert_with(|| Streak {
+                length: 0,
+                start: date.clone(),
+                end: date.clone(),
+            });
+            streak.length += 1;
+            streak.end = date;
+            if longest.as_ref().map_or(0, |l| l.length) < streak.length {
+                longest = Some(streak.clone());
+            }
+        } else if optional(day) || day == today || run.is_none() {
+            // Neither counts nor breaks
+        } else if freezes_used_in((day.year(), day.month()), &all_frozen) < rules.freezes_per_month
+        {
+            all_frozen.push(day);
+            frozen.push(day);
+        } else {
+            run = None;
+            frozen.clear();
+        }
+        match day.succ_opt() {
+            Some(next) => day = next,
+            None => break,
+        }
+    }
+
+    let used = freezes_used_in((today.year(), today.month()), &all_frozen);
+    Streaks {
+        today_done: qualifies(today),
+        frozen_days: frozen
+            .iter()
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .collect(),
+        freezes_left: rules.freezes_per_month.saturating_sub(used),
+        current: run,
+        longest,
+        rules: rules.clone(),
+    }
+}
+
+pub fn list_rest_days(conn: &Connection) -> Result<Vec<RestDay>> {
+    let mut stmt = conn.prepare("SELECT date, note FROM rest_days ORDER BY date")?;
+    let days = stmt.query_map([], |row| {
+        Ok(RestDay {
+            date: row.get(0)?,
+            note: row.get(1)?,
+        })
+    })?;
+    days.collect()
+}
+
+pub fn set_rest_day(conn: &Connection, date: &str, note: &str) -> Result<()> {
+    if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "Rest days must be YYYY-MM-DD dates.",
+        ));
+    }
+    println!("[set_rest_day] {}", date);
+    conn.execute(
+        "INSERT INTO rest_days (date, note) VALUES (?1, ?2)
+         ON CONFLICT(date) DO UPDATE SET note = excluded.note",
+        (date, note.trim()),
+    )?;
+    Ok(())
+}
+
+pub fn remove_rest_day(conn: &Connection, date: &str) -> Result<()> {
+    println!("[remove_rest_day] {}", date);
+    conn.execute("DELETE FROM rest_days WHERE date = ?1", [date])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::local_date;
+    use crate::clock::tests::UsEastern;
+    use chrono::{DateTime, Days, Duration, TimeZone, Utc};
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn days(dates: &[&str]) -> BTreeMap<NaiveDate, u32> {
+        let mut map = BTreeMap::new();
+        for d in dates {
+            *map.entry(date(d)).or_insert(0) += 1;
+        }
+        map
+    }
+
+    fn streaks(dates: &[&str], rules: &StreakRules, today: &str) -> Streaks {
+        compute(&days(dates), &HashSet::new(), rules, date(today))
+    }
+
+    fn span(s: &Option<Streak>) -> (u32, &str, &str) {
+        let s = s.as_ref().expect("a streak");
+        (s.length, s.start.as_str(), s.end.as_str())
+    }
+
+    #[test]
+    fn no_wins_means_no_streak() {
+        let s = streaks(&[], &StreakRules::default(), "2025-01-01");
+        assert!(s.current.is_none() && s.longest.is_none());
+        assert!(!s.today_done);
+    }
+
+    #[test]
+    fn runs_across_month_end() {
+        let s = streaks(
+            &["2025-01-30", "2025-01-31", "2025-02-01"],
+            &StreakRules::default(),
+            "2025-02-01",
+        );
+        assert_eq!(span(&s.current), (3, "2025-01-30", "2025-02-01"));
+        assert!(s.today_done);
+    }
+
+    #[test]
+    fn runs_across_leap_day() {
+        let s = streaks(
+            &["2024-02-28", "2024-02-29", "2024-03-01"],
+            &StreakRules::default(),
+            "2024-03-01",
+        );
+        assert_eq!(span(&s.current), (3, "2024-02-28", "2024-03-01"));
+        // 2025 has no Feb 29, so the 28th to the 1st is consecutive
+        let s = streaks(
+            &["2025-02-27", "2025-02-28", "2025-03-01"],
+            &StreakRules::default(),
+            "2025-03-01",
+        );
+        assert_eq!(span(&s.current), (3, "2025-02-27", "2025-03-01"));
+    }
+
+    #[test]
+    fn runs_across_new_year() {
+        let s = streaks(
+            &["2024-12-30", "2024-12-31", "2025-01-01", "2025-01-02"],
+            &StreakRules::default(),
+            "2025-01-02",
+        );
+        assert_eq!(span(&s.current), (4, "2024-12-30", "2025-01-02"));
+    }
+
+    #[test]
+    fn today_is_not_missed_until_it_ends() {
+        let s = streaks(
+            &["2025-12-30", "2025-12-31"],
+            &StreakRules::default(),
+            "2026-01-01",
+        );
+        assert_eq!(span(&s.current), (2, "2025-12-30", "2025-12-31"));
+        assert!(!s.today_done);
+        let s = streaks(
+            &["2025-12-30", "2025-12-31"],
+            &StreakRules::default(),
+            "2026-01-02",
+        );
+        assert!(s.current.is_none());
+        assert_eq!(span(&s.longest), (2, "2025-12-30", "2025-12-31"));
+    }
+
+    #[test]
+    fn dst_changes_do_not_break_or_double_count() {
+        // A win at 23:30 every night around spring forward (Mar 9) and fall
+        // back (Nov 2), dated from the stored instant like a logged win
+        for first in ["2025-03-08", "2025-11-01"] {
+            let nights: Vec<DateTime<UsEastern>> = (0..3)
+                .map(|i| {
+                    let day = date(first) + Days::new(i);
+                    UsEastern
+                        .from_local_datetime(&day.and_hms_opt(23, 30, 0).unwrap())
+                        .unwrap()
+                })
+                .collect();
+            // The nights are 23 or 25 hours apart, not 24
+            assert_ne!(nights[1] - nights[0], Duration::hours(24));
+            let dates: Vec<String> = nights
+                .iter()
+                .map(|t| local_date(&t.with_timezone(&Utc).with_timezone(&UsEastern)).to_string())
+                .collect();
+            let dates: Vec<&str> = dates.iter().map(String::as_str).collect();
+            assert_eq!(dates[2], (date(first) + Days::new(2)).to_string());
+            let today = local_date(&(nights[2] + Duration::minutes(20)));
+            let s = streaks(&dates, &StreakRules::default(), &today.to_string());
+            assert_eq!(span(&s.current), (3, dates[0], dates[2]));
+            assert!(s.today_done);
+        }
+    }
+
+    #[test]
+    fn weekdays_only_skips_weekends() {
+        // Thu, Fri, (Sat, Sun off), Mon
+        let rules = StreakRules {
+            weekdays_only: true,
+            ..Default::default()
+        };
+        let s = streaks(
+            &["2025-01-02", "2025-01-03", "2025-01-06"],
+            &rules,
+            "2025-01-06",
+        );
+        assert_eq!(span(&s.current), (3, "2025-01-02", "2025-01-06"));
+        let s = streaks(
+            &["2025-01-02", "2025-01-03", "2025-01-06"],
+            &StreakRules::default(),
+            "2025-01-06",
+        );
+        assert_eq!(span(&s.current), (1, "2025-01-06", "2025-01-06"));
+    }
+
+    #[test]
+    fn minimum_wins_per_day() {
+        let rules = StreakRules {
+            min_wins_per_day: 2,
+            ..Default::default()
+        };
+        let s = streaks(
+            &[
+                "2025-01-01",
+                "2025-01-01",
+                "2025-01-02",
+                "2025-01-03",
+                "2025-01-03",
+            ],
+            &rules,
+            "2025-01-03",
+        );
+        assert_eq!(span(&s.current), (1, "2025-01-03", "2025-01-03"));
+        assert_eq!(span(&s.longest), (1, "2025-01-01", "2025-01-01"));
+    }
+
+    #[test]
+    fn rest_days_bridge_a_gap() {
+        let rest = HashSet::from([date("2025-01-02")]);
+        let s = compute(
+            &days(&["2025-01-01", "2025-01-03"]),
+            &rest,
+            &StreakRules::default(),
+            date("2025-01-03"),
+        );
+        assert_eq!(span(&s.current), (2, "2025-01-01", "2025-01-03"));
+    }
+
+    #[test]
+    fn freezes_are_limited_per_month() {
+        let rules = StreakRules {
+            freezes_per_month: 1,
+            ..Default::default()
+        };
+        // Jan 30 missed (freeze), Feb 1 missed (February's freeze), Feb 3 missed (none left)
+        let s = streaks(
+            &["2025-01-29", "2025-01-31", "2025-02-02", "2025-02-04"],
+            &rules,
+            "2025-02-04",
+        );
+        assert_eq!(span(&s.current), (1, "2025-02-04", "2025-02-04"));
+        assert_eq!(span(&s.longest), (3, "2025-01-29", "2025-02-02"));
+        assert_eq!(s.freezes_left, 0);
+        let s = streaks(&["2025-01-29", "2025-01-31"], &rules, "2025-02-01");
+        assert_eq!(s.frozen_days, vec!["2025-01-30"]);
+        assert_eq!(s.freezes_left, 1);
+    }
+
+    #[test]
+    fn a_streak_tag_counts_only_wins_under_it() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::migrations::run_migrations(&mut conn).unwrap();
+        let wins = [
+            (1, "2025-01-01", "yoga"),
+            (2, "2025-01-02", "exercise"),
+            (3, "2025-01-03", "read"),
+            (4, "2025-01-03", "exercise"),
+            (5, "2025-01-04", "exercise, read"),
+        ];
+        for (id, date, tags) in wins {
+            conn.execute(
+                "INSERT INTO wins (id, date, text, created_at) VALUES (?1, ?2, '', 0)",
+                (id, date),
+            )
+            .unwrap();
+            crate::db::set_win_tags(&conn, id, &crate::db::parse_tag_string(tags)).unwrap();
+        }
+        // A trashed win doesn't keep the streak going
+        crate::db::delete_win(&mut conn, 4).unwrap();
+
+        let exercise = StreakRules {
+            tag: Some("exercise".to_string()),
+            ..Default::default()
+        };
+        let today = date("2025-01-04");
+        // yoga sits under exercise, so the 1st counts; reading on the 3rd doesn't
+        let s = get_streaks(&conn, &exercise, today).unwrap();
+        assert_eq!(span(&s.current), (1, "2025-01-04", "2025-01-04"));
+        assert_eq!(span(&s.longest), (2, "2025-01-01", "2025-01-02"));
+
+        let s = get_streaks(&conn, &StreakRules::default(), today).unwrap();
+        assert_eq!(span(&s.current), (4, "2025-01-01", "2025-01-04"));
+
+        set_rest_day(&conn, "2025-01-03", " Day off ").unwrap();
+        assert_eq!(list_rest_days(&conn).unwrap()[0].note, "Day off");
+        let s = get_streaks(&conn, &exercise, today).unwrap();
+        assert_eq!(span(&s.current), (3, "2025-01-01", "2025-01-04"));
+    }
+
+    #[test]
+    fn rest_days_must_be_real_dates() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::migrations::run_migrations(&mut conn).unwrap();
+        for bad in ["Jan 3", "2025-02-30", "2025-1-3 ", ""] {
+            assert!(set_rest_day(&conn, bad, "").is_err(), "{:?}", bad);
+        }
+        assert!(list_rest_days(&conn).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1007 @@
+// tags.rs - Renaming, merging, aliasing and nesting tags, with an undo log
+
+use crate::errors;
+use rusqlite::{ffi, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use time::OffsetDateTime;
+
+/// Parents given to these tags when they are first created, following the
+/// groups `infer_tags` adds together. Users can change them afterwards.
+pub(crate) const STARTER_PARENTS: [(&str, &str); 14] = [
+    ("exercise", "health"),
+    ("walk", "health"),
+    ("yoga", "exercise"),
+    ("run", "exercise"),
+    ("swim", "exercise"),
+    ("class", "school"),
+    ("homework", "school"),
+    ("study", "school"),
+    ("exam", "school"),
+    ("meeting", "admin"),
+    ("email", "admin"),
+    ("read", "learning"),
+    ("cook", "life"),
+    ("clean", "life"),
+];
+
+#[derive(Serialize)]
+pub struct TagInfo {
+    pub id: i64,
+    pub name: String,
+    /// Live wins carrying the tag
+    pub count: usize,
+    /// Names that become this tag when typed or inferred
+    pub aliases: Vec<String>,
+    /// Wins tagged with this tag also count toward its parent
+    pub parent: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TagChange {
+    pub id: i64,
+    /// "rename", "merge", "alias", "unalias" or "parent"
+    pub action: String,
+    /// e.g. "Merged workout, Exercise into exercise"
+    pub summary: String,
+    pub created_at: i64,
+    pub undone: bool,
+}
+
+/// A tag that a merge removed, with everything that pointed at it.
+#[derive(Serialize, Deserialize)]
+struct RemovedTag {
+    id: i64,
+    name: String,
+    wins: Vec<i64>,
+    goals: Vec<i64>,
+    #[serde(default)]
+    parent: Option<i64>,
+}
+
+/// What undoing a change has to put back. Only the links a change added are
+/// recorded, so wins tagged after the change keep their tags.
+#[derive(Serialize, Deserialize, Default)]
+struct UndoData {
+    /// (tag id, name before)
+    renamed: Vec<(i64, String)>,
+    removed: Vec<RemovedTag>,
+    /// The tag that sources were merged into
+    target: Option<i64>,
+    /// Wins and goal rules that only have the target because of the merge
+    added_wins: Vec<i64>,
+    added_goals: Vec<i64>,
+    /// Every alias the change touched and the tag it pointed at before
+    aliases: Vec<(String, Option<i64>)>,
+    /// (tag id, parent before)
+    #[serde(default)]
+    reparented: Vec<(i64, Option<i64>)>,
+}
+
+/// Parent links between tags, for rolling tags up outside SQL.
+#[derive(Default)]
+pub struct Taxonomy {
+    /// Each tag's ancestors, nearest first
+    ancestors: HashMap<String, Vec<String>>,
+}
+
+impl Taxonomy {
+    pub fn load(conn: &Connection) -> Result<Taxonomy> {
+        let mut stmt =
+            conn.prepare("SELECT t.name, p.name FROM tags t JOIN tags p ON p.id = t.parent_id")?;
+        let parents = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<String, String>>>()?;
+        let ancestors = parents
+            .keys()
+            .map(|tag| {
+                let mut chain: Vec<String> = Vec::new();
+                let mut seen = HashSet::from([tag.as_str()]);
+                let mut current = tag;
+                while let Some(parent) = parents.get(current) {
+                    if !seen.insert(parent.as_str()) {
+                        break;
+                    }
+                    chain.push(parent.clone());
+                    current = parent;
+                }
+                (tag.clone(), chain)
+            })
+            .collect();
+        Ok(Taxonomy { ancestors })
+    }
+
+    pub fn ancestors(&self, tag: &str) -> &[String] {
+        self.ancestors.get(tag).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// `tags` followed by every ancestor not already in the list.
+    pub fn expand(&self, tags: &[String]) -> Vec<String> {
+        let mut out: Vec<String> = Vec::new();
+        for tag in tags
+            .iter()
+            .chain(tags.iter().flat_map(|t| self.ancestors(t)))
+        {
+            if !out.contains(tag) {
+                out.push(tag.clone());
+            }
+        }
+        out
+    }
+
+    /// `tag` itself, or its ancestor at `level` (0 = top-level) if it sits
+    /// deeper than that.
+    pub fn at_level<'a>(&'a self, tag: &'a str, level: usize) -> &'a str {
+        let chain = self.ancestors(tag);
+        if chain.len() <= level {
+            tag
+        } else {
+            &chain[chain.len() - 1 - level]
+        }
+    }
+
+    /// Whether one tag is above the other.
+    pub fn related(&self, a: &str, b: &str) -> bool {
+        self.ancestors(a).iter().any(|t| t == b) || self.ancestors(b).iter().any(|t| t == a)
+    }
+}
+
+/// Every tag, most used first.
+pub fn list_tags(conn: &Connection) -> Result<Vec<TagInfo>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.id, t.name,
+             (SELECT COUNT(*) FROM win_tags wt JOIN wins w ON w.id = wt.win_id
+              WHERE wt.tag_id = t.id),
+             (SELECT GROUP_CONCAT(alias, ',') FROM
+                 (SELECT alias FROM tag_aliases WHERE tag_id = t.id ORDER BY alias)),
+             (SELECT p.name FROM tags p WHERE p.id = t.parent_id)
+         FROM tags t ORDER BY 3 DESC, t.name COLLATE NOCASE",
+    )?;
+    let tags = stmt.query_map([], |row| {
+        let aliases: Option<String> = row.get(3)?;
+        Ok(TagInfo {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            count: row.get::<_, i64>(2)? as usize,
+            aliases: aliases
+                .map(|a| a.split(',').map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+            parent: row.get(4)?,
+        })
+    })?;
+    tags.collect()
+}
+
+/// Id of the tag a typed or inferred `name` stands for, following aliases and
+/// creating the tag if it's new.
+pub(crate) fn resolve_or_create(conn: &Connection, name: &str) -> Result<i64> {
+    if let Some(id) = resolve(conn, name)? {
+        return Ok(id);
+    }
+    conn.execute("INSERT INTO tags (name) VALUES (?1)", [name])?;
+    let id = conn.last_insert_rowid();
+    let starter = STARTER_PARENTS
+        .iter()
+        .find(|(child, _)| child.eq_ignore_ascii_case(name));
+    if let Some((_, parent)) = starter {
+        let parent_id = resolve_or_create(conn, parent)?;
+        conn.execute(
+            "UPDATE tags SET parent_id = ?1 WHERE id = ?2",
+            (parent_id, id),
+        )?;
+        rebuild_ancestry(conn)?;
+    }
+    Ok(id)
+}
+
+fn resolve(conn: &Connection, name: &str) -> Result<Option<i64>> {
+    let alias = conn
+        .query_row(
+            "SELECT tag_id FROM tag_aliases WHERE alias = ?1",
+            [name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match alias {
+        Some(id) => Ok(Some(id)),
+        None => tag_id(conn, name),
+    }
+}
+
+fn tag_id(conn: &Connection, name: &str) -> Result<Option<i64>> {
+    conn.query_row("SELECT id FROM tags WHERE name = ?1", [name], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+fn tag_name(conn: &Connection, id: i64) -> Result<String> {
+    conn.query_row("SELECT name FROM tags WHERE id = ?1", [id], |row| {
+        row.get(0)
+    })
+}
+
+fn existing_tag(conn: &Connection, name: &str) -> Result<i64> {
+    tag_id(conn, name.trim())?.ok_or_else(|| {
+        errors::failure(
+            ffi::SQLITE_NOTFOUND,
+            &format!("No tag named \"{}\".", name.trim()),
+        )
+    })
+}
+
+fn new_name(name: &str) -> Result<&str> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(',') {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "A tag needs a name without commas.",
+        ));
+    }
+    Ok(name)
+}
+
+/// Renames a tag on every win, goal and alias. Renaming onto another tag's
+/// name merges the two. The old spelling becomes an alias, so it can't creep
+/// back in.
+pub fn rename_tag(conn: &mut Connection, from: &str, to: &str) -> Result<TagChange> {
+    let to = new_name(to)?;
+    let tx = conn.transaction()?;
+    let id = existing_tag(&tx, from)?;
+    let old = tag_name(&tx, id)?;
+    if let Some(other) = resolve(&tx, to)?.filter(|other| *other != id) {
+        let into = tag_name(&tx, other)?;
+        drop(tx);
+        return merge_tags(conn, &[old], &into);
+    }
+    if old == to {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "The tag already has that name.",
+        ));
+    }
+    println!("[rename_tag] {} -> {}", old, to);
+    let mut undo = UndoData::default();
+    rename(&tx, &mut undo, id, &old, to)?;
+    let change = record(&tx, "rename", &format!("Renamed {} to {}", old, to), &undo)?;
+    tx.commit()?;
+    Ok(change)
+}
+
+fn rename(conn: &Connection, undo: &mut UndoData, id: i64, old: &str, to: &str) -> Result<()> {
+    // An alias with the new name would shadow the tag
+    set_alias(conn, undo, to, None)?;
+    conn.execute("UPDATE tags SET name = ?1 WHERE id = ?2", (to, id))?;
+    undo.renamed.push((id, old.to_string()));
+    if !old.eq_ignore_ascii_case(to) {
+        set_alias(conn, undo, old, Some(id))?;
+    }
+    Ok(())
+}
+
+/// Folds `sources` into `into` on every win (live or trashed) and goal rule.
+/// `into` is created from the first source if no such tag exists yet. Each
+/// source name becomes an alias of `into`.
+pub fn merge_tags(conn: &mut Connection, sources: &[String], into: &str) -> Result<TagChange> {
+    let into = new_name(into)?;
+    let tx = conn.transaction()?;
+    let mut ids: Vec<i64> = Vec::new();
+    for name in sources {
+        let id = existing_tag(&tx, name)?;
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    let mut undo = UndoData::default();
+    let target = match resolve(&tx, into)? {
+        Some(id) => id,
+        None => {
+            let Some(first) = ids.first().copied() else {
+                return Err(errors::failure(
+                    ffi::SQLITE_CONSTRAINT,
+                    "Pick at least one tag to merge.",
+                ));
+            };
+            let old = tag_name(&tx, first)?;
+            rename(&tx, &mut undo, first, &old, into)?;
+            first
+        }
+    };
+    let names = ids
+        .iter()
+        .filter(|id| **id != target)
+        .map(|id| tag_name(&tx, *id))
+        .collect::<Result<Vec<_>>>()?;
+    if names.is_empty() && undo.renamed.is_empty() {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "Pick at least one other tag to merge.",
+        ));
+    }
+    let target_name = tag_name(&tx, target)?;
+    println!("[merge_tags] {:?} -> {}", names, target_name);
+    undo.target = Some(target);
+    for id in ids.into_iter().filter(|id| *id != target) {
+        fold(&tx, &mut undo, id, target)?;
+    }
+    let mut merged: Vec<String> = undo.renamed.iter().map(|(_, n)| n.clone()).collect();
+    merged.extend(names);
+    let summary = format!("Merged {} into {}", merged.join(", "), target_name);
+    let change = record(&tx, "merge", &summary, &undo)?;
+    tx.commit()?;
+    Ok(change)
+}
+
+/// Moves everything from tag `id` to `target` and deletes `id`.
+fn fold(conn: &Connection, undo: &mut UndoData, id: i64, target: i64) -> Result<()> {
+    let removed = RemovedTag {
+        id,
+        name: tag_name(conn, id)?,
+        wins: ids(conn, "SELECT win_id FROM win_tags WHERE tag_id = ?1", id)?,
+        goals: ids(
+            conn,
+            "SELECT goal_id FROM goal_tag_rules WHERE tag_id = ?1",
+            id,
+        )?,
+        parent: conn.query_row("SELECT parent_id FROM tags WHERE id = ?1", [id], |row| {
+            row.get(0)
+        })?,
+    };
+    // Children move under the target, unless that would make a loop; those
+    // (the target itself, or tags above it) take the removed tag's parent
+    for child in ids(conn, "SELECT id FROM tags WHERE parent_id = ?1", id)? {
+        let parent = if is_ancestor(conn, child, target)? {
+            removed.parent
+        } else {
+            Some(target)
+        };
+        set_parent_id(conn, undo, child, parent)?;
+    }
+    undo.added_wins.extend(ids2(
+        conn,
+        "SELECT win_id FROM win_tags WHERE tag_id = ?1
+         AND win_id NOT IN (SELECT win_id FROM win_tags WHERE tag_id = ?2)",
+        id,
+        target,
+    )?);
+    undo.added_goals.extend(ids2(
+        conn,
+        "SELECT goal_id FROM goal_tag_rules WHERE tag_id = ?1
+         AND goal_id NOT IN (SELECT goal_id FROM goal_tag_rules WHERE tag_id = ?2)",
+        id,
+        target,
+    )?);
+    conn.execute(
+        "INSERT OR IGNORE INTO win_tags (win_id, tag_id)
+         SELECT win_id, ?2 FROM win_tags WHERE tag_id = ?1",
+        (id, target),
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO goal_tag_rules (goal_id, tag_id)
+         SELECT goal_id, ?2 FROM goal_tag_rules WHERE tag_id = ?1",
+        (id, target),
+    )?;
+    let mut stmt = conn.prepare("SELECT alias FROM tag_aliases WHERE tag_id = ?1")?;
+    let aliases = stmt
+        .query_map([id], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>>>()?;
+    for alias in aliases {
+        set_alias(conn, undo, &alias, Some(target))?;
+    }
+    conn.execute("DELETE FROM tags WHERE id = ?1", [id])?;
+    set_alias(conn, undo, &removed.name, Some(target))?;
+    undo.removed.push(removed);
+    rebuild_ancestry(conn)
+}
+
+/// Files `tag` under `parent`, or makes it top-level with `None`. Wins tagged
+/// with it then also count toward the parent and everything above it. A new
+/// parent name creates the tag.
+pub fn set_parent(conn: &mut Connection, tag: &str, parent: Option<&str>) -> Result<TagChange> {
+    let tx = conn.transaction()?;
+    let id = existing_tag(&tx, tag)?;
+    let name = tag_name(&tx, id)?;
+    let parent = match parent.map(str::trim).filter(|p| !p.is_empty()) {
+        Some(p) => Some(resolve_or_create(&tx, new_name(p)?)?),
+        None => None,
+    };
+    if let Some(p) = parent {
+        if is_ancestor(&tx, id, p)? {
+            return Err(errors::failure(
+                ffi::SQLITE_CONSTRAINT,
+                &format!("\"{}\" is already inside \"{}\".", tag_name(&tx, p)?, name),
+            ));
+        }
+    }
+    let mut undo = UndoData::default();
+    set_parent_id(&tx, &mut undo, id, parent)?;
+    rebuild_ancestry(&tx)?;
+    let summary = match parent {
+        Some(p) => format!("Moved {} under {}", name, tag_name(&tx, p)?),
+        None => format!("Made {} a top-level tag", name),
+    };
+    println!("[set_parent] {}", summary);
+    let change = record(&tx, "parent", &summary, &undo)?;
+    tx.commit()?;
+    Ok(change)
+}
+
+fn set_parent_id(
+    conn: &Connection,
+    undo: &mut UndoData,
+    id: i64,
+    parent: Option<i64>,
+) -> Result<()> {
+    let before: Option<i64> =
+        conn.query_row("SELECT parent_id FROM tags WHERE id = ?1", [id], |row| {
+            row.get(0)
+        })?;
+    if !undo.reparented.iter().any(|(tag, _)| *tag == id) {
+        undo.reparented.push((id, before));
+    }
+    conn.execute("UPDATE tags SET parent_id = ?1 WHERE id = ?2", (parent, id))?;
+    Ok(())
+}
+
+/// Whether `ancestor` is `tag` or above it.
+fn is_ancestor(conn: &Connection, ancestor: i64, tag: i64) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM tag_ancestry WHERE tag_id = ?1 AND ancestor_id = ?2)",
+        [tag, ancestor],
+        |row| row.get(0),
+    )
+}
+
+/// Recomputes `tag_ancestry` from the parent links. Cheap: there are far
+/// fewer tags than wins.
+pub(crate) fn rebuild_ancestry(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "DELETE FROM tag_ancestry;
+        INSERT INTO tag_ancestry (tag_id, ancestor_id)
+        WITH RECURSIVE up(tag_id, ancestor_id) AS (
+            SELECT id, id FROM tags
+            UNION
+            SELECT up.tag_id, t.parent_id FROM up JOIN tags t ON t.id = up.ancestor_id
+            WHERE t.parent_id IS NOT NULL
+        )
+        SELECT tag_id, ancestor_id FROM up;",
+    )
+}
+
+/// Makes `alias` stand for `tag` whenever a win is tagged from now on. If a
+/// tag with that name already exists it is merged into `tag`.
+pub fn add_alias(conn: &mut Connection, alias: &str, tag: &str) -> Result<TagChange> {
+    let alias = new_name(alias)?;
+    let tx = conn.transaction()?;
+    let target = resolve(&tx, tag.trim())?.ok_or_else(|| {
+        errors::failure(
+            ffi::SQLITE_NOTFOUND,
+            &format!("No tag named \"{}\".", tag.trim()),
+        )
+    })?;
+    let target_name = tag_name(&tx, target)?;
+    if alias.eq_ignore_ascii_case(&target_name) {
+        return Err(errors::failure(
+            ffi::SQLITE_CONSTRAINT,
+            "A tag can't be an alias of itself.",
+        ));
+    }
+    if tag_id(&tx, alias)?.is_some() {
+        drop(tx);
+        return merge_tags(conn, &[alias.to_string()], &target_name);
+    }
+    println!("[add_alias] {} -> {}", alias, target_name);
+    let mut undo = UndoData::default();
+    set_alias(&tx, &mut undo, alias, Some(target))?;
+    let summary = format!("{} now means {}", alias, target_name);
+    let change = record(&tx, "alias", &summary, &undo)?;
+    tx.commit()?;
+    Ok(change)
+}
+
+pub fn remove_alias(conn: &mut Connection, alias: &str) -> Result<TagChange> {
+    let tx = conn.transaction()?;
+    let mut undo = UndoData::default();
+    set_alias(&tx, &mut undo, alias.trim(), None)?;
+    if undo.aliases.is_empty() {
+        return Err(errors::failure(
+            ffi::SQLITE_NOTFOUND,
+            &format!("No alias named \"{}\".", alias.trim()),
+        ));
+    }
+    println!("[remove_alias] {}", alias.trim());
+    let summary = format!("Removed alias {}", alias.trim());
+    let change = record(&tx, "unalias", &summary, &undo)?;
+    tx.commit()?;
+    Ok(change)
+}
+
+/// Points `alias` at `tag` (or removes it), remembering where it pointed.
+fn set_alias(conn: &Connection, undo: &mut UndoData, alias: &str, tag: Option<i64>) -> Result<()> {
+    let before: Option<i64> = conn
+        .query_row(
+            "SELECT tag_id FROM tag_aliases WHERE alias = ?1",
+            [alias],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if before == tag {
+        return Ok(());
+    }
+    if !undo
+        .aliases
+        .iter()
+        .any(|(a, _)| a.eq_ignore_ascii_case(alias))
+    {
+        undo.aliases.push((alias.to_string(), before));
+    }
+    conn.execute("DELETE FROM tag_aliases WHERE alias = ?1", [alias])?;
+    if let Some(tag) = tag {
+        conn.execute(
+            "INSERT INTO tag_aliases (alias, tag_id) VALUES (?1, ?2)",
+            (alias, tag),
+        )?;
+    }
+    Ok(())
+}
+
+/// Tag changes, newest first.
+pub fn list_tag_changes(conn: &Connection) -> Result<Vec<TagChange>> {
+    let mut stmt = conn.prepare(&format!("{} ORDER BY id DESC LIMIT 50", CHANGE_SELECT))?;
+    let changes = stmt.query_map([], change_from_row)?;
+    changes.collect()
+}
+
+/// Reverts the most recent tag change that is still in effect. Returns the
+/// change that was undone, or `None` if there was nothing to undo.
+pub fn undo_tag_change(conn: &mut Connection) -> Result<Option<TagChange>> {
+    let tx = conn.transaction()?;
+    let latest = tx
+        .query_row(
+            &format!(
+                "{} WHERE undone_at IS NULL ORDER BY id DESC LIMIT 1",
+                CHANGE_SELECT
+            ),
+            [],
+            |row| Ok((change_from_row(row)?, row.get::<_, String>(5)?)),
+        )
+        .optional()?;
+    let Some((mut change, json)) = latest else {
+        return Ok(None);
+    };
+    let undo: UndoData = serde_json::from_str(&json)
+        .map_err(|e| errors::failure(ffi::SQLITE_CORRUPT, &e.to_string()))?;
+    println!("[undo_tag_change] {}", change.summary);
+
+    for (alias, _) in &undo.aliases {
+        tx.execute("DELETE FROM tag_aliases WHERE alias = ?1", [alias])?;
+    }
+    if let Some(target) = undo.target {
+        for win in &undo.added_wins {
+            tx.execute(
+                "DELETE FROM win_tags WHERE win_id = ?1 AND tag_id = ?2",
+                (win, target),
+            )?;
+        }
+        for goal in &undo.added_goals {
+            tx.execute(
+                "DELETE FROM goal_tag_rules WHERE goal_id = ?1 AND tag_id = ?2",
+                (goal, target),
+            )?;
+        }
+    }
+    for removed in &undo.removed {
+        if tag_id(&tx, &removed.name)?.is_some() {
+            return Err(errors::failure(
+                ffi::SQLITE_CONSTRAINT,
+                &format!(
+                    "Can't undo: there is a tag named \"{}\" again. Merge it first.",
+                    removed.name
+                ),
+            ));
+        }
+        tx.execute(
+            "INSERT INTO tags (id, name, parent_id)
+             VALUES (?1, ?2, (SELECT id FROM tags WHERE id = ?3))",
+            (removed.id, &removed.name, removed.parent),
+        )?;
+        // Wins purged from the trash since stay gone
+        for win in &removed.wins {
+            tx.execute(
+                "INSERT OR IGNORE INTO win_tags (win_id, tag_id)
+                 SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM wins WHERE id = ?1)
+                     OR EXISTS (SELECT 1 FROM deleted_wins WHERE id = ?1)",
+                (win, removed.id),
+            )?;
+        }
+        for goal in &removed.goals {
+            tx.execute(
+                "INSERT OR IGNORE INTO goal_tag_rules (goal_id, tag_id)
+                 SELECT id, ?2 FROM goals WHERE id = ?1",
+                (goal, removed.id),
+            )?;
+        }
+    }
+    for (id, name) in undo.renamed.iter().rev() {
+        tx.execute("UPDATE tags SET name = ?1 WHERE id = ?2", (name, id))?;
+    }
+    for (id, parent) in &undo.reparented {
+        tx.execute(
+            "UPDATE tags SET parent_id = (SELECT id FROM tags WHERE id = ?1) WHERE id = ?2",
+            (parent, id),
+        )?;
+    }
+    rebuild_ancestry(&tx)?;
+    for (alias, before) in &undo.aliases {
+        if let Some(tag) = before {
+            tx.execute(
+                "INSERT OR IGNORE INTO tag_aliases (alias, tag_id)
+                 SELECT ?1, id FROM tags WHERE id = ?2",
+                (alias, tag),
+            )?;
+        }
+    }
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    tx.execute(
+        "UPDATE tag_changes SET undone_at = ?1 WHERE id = ?2",
+        (now, change.id),
+    )?;
+    tx.commit()?;
+    change.undone = true;
+    Ok(Some(change))
+}
+
+const CHANGE_SELECT: &str =
+    "SELECT id, action, summary, created_at, undone_at IS NOT NULL, undo FROM tag_changes";
+
+fn change_from_row(row: &rusqlite::Row) -> Result<TagChange> {
+    Ok(TagChange {
+        id: row.get(0)?,
+        action: row.get(1)?,
+        summary: row.get(2)?,
+        created_at: row.get(3)?,
+        undone: row.get(4)?,
+    })
+}
+
+fn record(conn: &Connection, action: &str, summary: &str, undo: &UndoData) -> Result<TagChange> {
+    let json = serde_json::to_string(undo)
+        .map_err(|e| errors::failure(ffi::SQLITE_MISUSE, &e.to_string()))?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    conn.execute(
+        "INSERT INTO tag_changes (action, summary, undo, created_at) VALUES (?1, ?2, ?3, ?4)",
+        (action, summary, json, now),
+    )?;
+    Ok(TagChange {
+        id: conn.last_insert_rowid(),
+        action: action.to_string(),
+        summary: summary.to_string(),
+        created_at: now,
+        undone: false,
+    })
+}
+
+fn ids(conn: &Connection, sql: &str, id: i64) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([id], |row| row.get(0))?;
+    rows.collect()
+}
+
+fn ids2(conn: &Connection, sql: &str, a: i64, b: i64) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([a, b], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// The closest known tag to a tag being typed, for a "Did you mean" hint.
+/// `None` when the input is already a tag or alias, or nothing is close: one
+/// edit away for short tags, two for longer ones. Ties go to the most used tag.
+pub fn did_you_mean(conn: &Connection, input: &str) -> Result<Option<String>> {
+    let input = input.trim().to_lowercase();
+    if input.is_empty() || resolve(conn, &input)?.is_some() {
+        return Ok(None);
+    }
+    let max_edits = if input.chars().count() <= 4 { 1 } else { 2 };
+    let mut best: Option<(usize, usize, String)> = None;
+    for tag in list_tags(conn)? {
+        let spellings = std::iter::once(&tag.name).chain(tag.aliases.iter());
+        let Some(dist) = spellings
+            .map(|s| levenshtein(&input, &s.to_lowercase()))
+            .min()
+        else {
+            continue;
+        };
+        let better = match &best {
+            Some((d, count, _)) => dist < *d || (dist == *d && tag.count > *count),
+            None => true,
+        };
+        if dist <= max_edits && better {
+            best = Some((dist, tag.count, tag.name));
+        }
+    }
+    Ok(best.map(|(_, _, name)| name))
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            row[j + 1] = if ca == *cb {
+                prev[j]
+            } else {
+                1 + prev[j].min(prev[j + 1]).min(row[j])
+            };
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, migrations, query, stats};
+    use std::collections::BTreeSet;
+
+    /// Wins tagged workout, crossfit (filed under workout, which is under
+    /// fitness) and exercise, an alias "wo" for workout and a goal counting
+    /// workout.
+    fn journal() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        for tags in ["workout", "exercise", "workout, exercise", "crossfit"] {
+            db::add_win(
+                &mut conn,
+                "2025-01-02",
+                "Went",
+                tags,
+                db::Ratings::default(),
+            )
+            .unwrap();
+        }
+        set_parent(&mut conn, "crossfit", Some("workout")).unwrap();
+        set_parent(&mut conn, "workout", Some("fitness")).unwrap();
+        add_alias(&mut conn, "wo", "workout").unwrap();
+        conn.execute_batch(
+            "INSERT INTO goals (name, created_at) VALUES ('Move', 0);
+             INSERT INTO goal_tag_rules (goal_id, tag_id)
+                 SELECT 1, id FROM tags WHERE name = 'workout';",
+        )
+        .unwrap();
+        conn
+    }
+
+    /// Every tag link as readable rows, for comparing before and after.
+    fn state(conn: &Connection) -> BTreeSet<String> {
+        let queries = [
+            "SELECT 'tag ' || id || ' ' || name || ' < ' || IFNULL(parent_id, '-') FROM tags",
+            "SELECT 'win ' || wt.win_id || ' ' || t.name FROM win_tags wt
+             JOIN tags t ON t.id = wt.tag_id",
+            "SELECT 'alias ' || a.alias || ' ' || t.name FROM tag_aliases a
+             JOIN tags t ON t.id = a.tag_id",
+            "SELECT 'goal ' || r.goal_id || ' ' || t.name FROM goal_tag_rules r
+             JOIN tags t ON t.id = r.tag_id",
+            "SELECT 'ancestor ' || tag_id || ' ' || ancestor_id FROM tag_ancestry",
+        ];
+        let mut rows = BTreeSet::new();
+        for sql in queries {
+            let mut stmt = conn.prepare(sql).unwrap();
+            let found = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
+            rows.extend(found.map(|r| r.unwrap()));
+        }
+        rows
+    }
+
+    fn parent_of(conn: &Connection, tag: &str) -> Option<String> {
+        conn.query_row(
+            "SELECT p.name FROM tags t JOIN tags p ON p.id = t.parent_id WHERE t.name = ?1",
+            [tag],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn undoing_a_merge_puts_everything_back() {
+        let mut conn = journal();
+        let before = state(&conn);
+        let change = merge_tags(&mut conn, &["workout".to_string()], "exercise").unwrap();
+        assert_eq!(change.summary, "Merged workout into exercise");
+        let merged = state(&conn);
+        assert!(!merged.iter().any(|r| r.contains("workout <")));
+        assert!(merged.contains("win 1 exercise"));
+        assert!(merged.contains("alias wo exercise"));
+        assert!(merged.contains("alias workout exercise"));
+        assert!(merged.contains("goal 1 exercise"));
+        assert_eq!(parent_of(&conn, "crossfit").as_deref(), Some("exercise"));
+
+        let undone = undo_tag_change(&mut conn).unwrap().unwrap();
+        assert!(undone.undone);
+        assert_eq!(state(&conn), before);
+        assert_eq!(parent_of(&conn, "crossfit").as_deref(), Some("workout"));
+        assert_eq!(parent_of(&conn, "workout").as_deref(), Some("fitness"));
+    }
+
+    #[test]
+    fn undoing_a_rename_restores_the_old_name() {
+        let mut conn = journal();
+        let before = state(&conn);
+        rename_tag(&mut conn, "workout", "training").unwrap();
+        assert_eq!(
+            resolve(&conn, "workout").unwrap(),
+            tag_id(&conn, "training").unwrap()
+        );
+        assert_eq!(parent_of(&conn, "crossfit").as_deref(), Some("training"));
+
+        undo_tag_change(&mut conn).unwrap().unwrap();
+        assert_eq!(state(&conn), before);
+        assert!(tag_id(&conn, "training").unwrap().is_none());
+        assert!(tag_id(&conn, "workout").unwrap().is_some());
+    }
+
+    #[test]
+    fn renaming_onto_a_tag_merges_and_undoes_as_one() {
+        let mut conn = journal();
+        let before = state(&conn);
+        let change = rename_tag(&mut conn, "workout", "exercise").unwrap();
+        assert_eq!(change.action, "merge");
+        undo_tag_change(&mut conn).unwrap().unwrap();
+        assert_eq!(state(&conn), before);
+    }
+
+    #[test]
+    fn undo_goes_newest_first() {
+        let mut conn = journal();
+        let start = state(&conn);
+        rename_tag(&mut conn, "workout", "training").unwrap();
+        let renamed = state(&conn);
+        merge_tags(&mut conn, &["training".to_string()], "exercise").unwrap();
+        undo_tag_change(&mut conn).unwrap();
+        assert_eq!(state(&conn), renamed);
+        undo_tag_change(&mut conn).unwrap();
+        assert_eq!(state(&conn), start);
+        // Then the alias and parent changes the fixture made
+        let summaries: Vec<String> = std::iter::from_fn(|| undo_tag_change(&mut conn).unwrap())
+            .map(|c| c.summary)
+            .collect();
+        assert_eq!(
+            summaries,
+            vec![
+                "wo now means workout",
+                "Moved workout under fitness",
+                "Moved crossfit under workout",
+            ]
+        );
+    }
+
+    fn tagged(conn: &mut Connection, tags: &str) -> i64 {
+        db::add_win(conn, "2025-01-03", "Mat time", tags, db::Ratings::default()).unwrap();
+        conn.query_row("SELECT MAX(id) FROM wins", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn a_tag_cannot_go_inside_itself() {
+        let mut conn = journal();
+        let Err(err) = set_parent(&mut conn, "fitness", Some("crossfit")) else {
+            panic!("fitness went under its own grandchild");
+        };
+        assert!(err
+            .to_string()
+            .contains("\"crossfit\" is already inside \"fitness\""));
+        assert!(set_parent(&mut conn, "workout", Some("workout")).is_err());
+        assert_eq!(parent_of(&conn, "fitness"), None);
+        assert_eq!(parent_of(&conn, "workout").as_deref(), Some("fitness"));
+
+        // Moving a tag sideways is fine
+        set_parent(&mut conn, "crossfit", Some("fitness")).unwrap();
+        assert_eq!(parent_of(&conn, "crossfit").as_deref(), Some("fitness"));
+    }
+
+    #[test]
+    fn new_tags_get_their_starter_parents() {
+        let mut conn = journal();
+        tagged(&mut conn, "yoga");
+        assert_eq!(parent_of(&conn, "yoga").as_deref(), Some("exercise"));
+        assert_eq!(parent_of(&conn, "exercise").as_deref(), Some("health"));
+        assert_eq!(parent_of(&conn, "health"), None);
+
+        // Only when first created: a tag the user moved stays put
+        set_parent(&mut conn, "yoga", None).unwrap();
+        tagged(&mut conn, "yoga, swim");
+        assert_eq!(parent_of(&conn, "yoga"), None);
+        assert_eq!(parent_of(&conn, "swim").as_deref(), Some("exercise"));
+    }
+
+    #[test]
+    fn taxonomy_expands_and_collapses_the_hierarchy() {
+        let taxonomy = Taxonomy::load(&journal()).unwrap();
+        assert_eq!(taxonomy.ancestors("crossfit"), ["workout", "fitness"]);
+        let tags = vec!["crossfit".to_string(), "exercise".to_string()];
+        assert_eq!(
+            taxonomy.expand(&tags),
+            vec!["crossfit", "exercise", "workout", "fitness", "health"]
+        );
+        assert_eq!(taxonomy.at_level("crossfit", 0), "fitness");
+        assert_eq!(taxonomy.at_level("crossfit", 1), "workout");
+        assert_eq!(taxonomy.at_level("crossfit", 2), "crossfit");
+        assert_eq!(taxonomy.at_level("fitness", 1), "fitness");
+        assert!(taxonomy.related("fitness", "crossfit"));
+        assert!(!taxonomy.related("workout", "exercise"));
+    }
+
+    #[test]
+    fn child_tags_count_toward_their_parents() {
+        let mut conn = journal();
+        let yoga = tagged(&mut conn, "yoga");
+        let filter = |include: &[&str], exclude: &[&str]| {
+            let filter = query::WinFilter {
+                include_tags: include.iter().map(|t| t.to_string()).collect(),
+                exclude_tags: exclude.iter().map(|t| t.to_string()).collect(),
+                ..Default::default()
+            };
+            query::filtered_wins(&conn, "wins", &filter, query::SortOrder::Oldest, None, None)
+                .unwrap()
+                .iter()
+                .map(|w| w.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(filter(&["health"], &[]), vec![2, 3, yoga]);
+        assert_eq!(filter(&["health"], &["yoga"]), vec![2, 3]);
+        assert!(!filter(&[], &["health"]).contains(&yoga));
+
+        let today = chrono::NaiveDate::from_ymd_opt(2025, 1, 20).unwrap();
+        let recap = stats::get_recap(&conn, stats::RecapPeriod::Month, today).unwrap();
+        let health = recap.top_tags.iter().find(|t| t.tag == "health").unwrap();
+        assert_eq!(health.count, 3);
+    }
+
+    #[test]
+    fn levenshtein_counts_single_character_edits() {
+        assert_eq!(levenshtein("yoga", "yoga"), 0);
+        assert_eq!(levenshtein("yoag", "yoga"), 2);
+        assert_eq!(levenshtein("runing", "running"), 1);
+        assert_eq!(levenshtein("", "walk"), 4);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn did_you_mean_suggests_close_tags() {
+        let conn = journal();
+        // Already a tag or an alias: nothing to suggest
+        assert_eq!(did_you_mean(&conn, "workout").unwrap(), None);
+        assert_eq!(did_you_mean(&conn, " Workout ").unwrap(), None);
+        assert_eq!(did_you_mean(&conn, "wo").unwrap(), None);
+
+        assert_eq!(
+            did_you_mean(&conn, "workuot").unwrap().as_deref(),
+            Some("workout")
+        );
+        assert_eq!(
+            did_you_mean(&conn, "crossfti").unwrap().as_deref(),
+            Some("crossfit")
+        );
+        // Three edits is too far, and short inputs allow only one
+        assert_eq!(did_you_mean(&conn, "wrkuot").unwrap(), None);
+        assert_eq!(
+            did_you_mean(&conn, "fitnes").unwrap().as_deref(),
+            Some("fitness")
+        );
+        assert_eq!(did_you_mean(&conn, "wxy").unwrap(), None);
+        assert_eq!(did_you_mean(&conn, "").unwrap(), None);
+    }
+
+    #[test]
+    fn did_you_mean_matches_aliases_too() {
+        let mut conn = journal();
+        add_alias(&mut conn, "gymtime", "workout").unwrap();
+        // Far from every tag name, one edit from the alias
+        assert_eq!(
+            did_you_mean(&conn, "gymtim").unwrap().as_deref(),
+            Some("workout")
+        );
+    }
+}
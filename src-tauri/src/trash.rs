@@ -0,0 +1,316 @@
+// trash.rs - Trash retention, purging and batch delete/restore
+
+use crate::attachments;
+use crate::db::{self, Db};
+use crate::errors;
+use rusqlite::{ffi, Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use time::OffsetDateTime;
+
+const SETTINGS_FILE: &str = "trash_retention.json";
+
+/// How long deleted wins stay in the trash. `retention_hours: None` turns
+/// automatic purging off; the trash is then only emptied by hand.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct TrashSettings {
+    pub retention_hours: Option<i64>,
+}
+
+impl Default for TrashSettings {
+    fn default() -> Self {
+        TrashSettings {
+            retention_hours: Some(48),
+        }
+    }
+}
+
+/// Outcome for one id of a batch command; one bad id doesn't stop the rest.
+#[derive(Serialize)]
+pub struct BatchResult {
+    pub id: i64,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+fn settings_path(db: &Db) -> std::path::PathBuf {
+    db.path()
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(SETTINGS_FILE)
+}
+
+pub fn get_settings(db: &Db) -> TrashSettings {
+    fs::read_to_string(settings_path(db))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Saved next to the database like the backup retention policy, so
+/// restoring a backup doesn't change it.
+pub fn set_settings(db: &Db, settings: TrashSettings) -> Result<(), String> {
+    if settings.retention_hours.is_some_and(|h| h < 1) {
+        return Err("Retention must be at least one hour.".to_string());
+    }
+    let json = serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())?;
+    fs::write(settings_path(db), json).map_err(|e| e.to_string())?;
+    run_scheduled(db);
+    Ok(())
+}
+
+/// Called hourly by the cleanup thread.
+pub fn run_scheduled(db: &Db) {
+    if db.is_locked() {
+        return;
+    }
+    let settings = get_settings(db);
+    if let Err(e) = db.with(|conn| purge_scheduled(conn, settings)) {
+        println!("[trash] Scheduled purge failed: {}", e);
+    }
+}
+
+/// The purge `run_scheduled` performs under `settings`; nothing is removed
+/// when automatic purging is off.
+fn purge_scheduled(conn: &mut Connection, settings: TrashSettings) -> Result<usize> {
+    match settings.retention_hours {
+        Some(hours) => purge_expired(conn, hours),
+        None => Ok(0),
+    }
+}
+
+/// Permanently removes wins that have been in the trash longer than
+/// `retention_hours`.
+pub fn purge_expired(conn: &mut Connection, retention_hours: i64) -> Result<usize> {
+    let cutoff = OffsetDateTime::now_utc().unix_timestamp() - retention_hours * 3600;
+    let tx = conn.transaction()?;
+    let count = purge(&tx, "deleted_at < ?1", [cutoff])?;
+    tx.commit()?;
+    attachments::collect_garbage(conn);
+    println!("[purge_expired] Deleted {} old entries", count);
+    Ok(count)
+}
+
+pub fn empty_trash(conn: &mut Connection) -> Result<usize> {
+    let tx = conn.transaction()?;
+    let count = purge(&tx, "1", [])?;
+    tx.commit()?;
+    attachments::collect_garbage(conn);
+    println!("[empty_trash] Deleted {} entries", count);
+    Ok(count)
+}
+
+/// Permanently removes one win, which must already be in the trash.
+pub fn purge_win(conn: &mut Connection, id: i64) -> Result<()> {
+    let tx = conn.transaction()?;
+    if purge(&tx, "id = ?1", [id])? == 0 {
+        return Err(errors::failure(
+            ffi::SQLITE_NOTFOUND,
+            "That win isn't in the trash.",
+        ));
+    }
+    tx.commit()?;
+    attachments::collect_garbage(conn);
+    println!("[purge_win] Deleted win id {}", id);
+    Ok(())
+}
+
+/// Deletes trash rows matching `filter` along with their tags, attachments,
+/// goal and chain links and edit history, so nothing of a purged win is left
+/// behind. Callers run `attachments::collect_garbage` after committing.
+fn purge<P: rusqlite::Params + Copy>(tx: &Connection, filter: &str, params: P) -> Result<usize> {
+    let ids = format!("SELECT id FROM deleted_wins WHERE {}", filter);
+    tx.execute(
+        &format!("DELETE FROM win_tags WHERE win_id IN ({})", ids),
+        params,
+    )?;
+    tx.execute(
+        &format!("DELETE FROM win_revisions WHERE win_id IN ({})", ids),
+        params,
+    )?;
+    tx.execute(
+        &format!("DELETE FROM win_attachments WHERE win_id IN ({})", ids),
+        params,
+    )?;
+    tx.execute(
+        &format!("DELETE FROM goal_wins WHERE win_id IN ({})", ids),
+        params,
+    )?;
+    tx.execute(
+        &format!("DELETE FROM chain_wins WHERE win_id IN ({})", ids),
+        params,
+    )?;
+    tx.execute(
+        &format!("DELETE FROM deleted_wins WHERE {}", filter),
+        params,
+    )
+}
+
+/// Moves each win to the trash in one transaction.
+pub fn delete_wins(conn: &mut Connection, ids: &[i64]) -> Result<Vec<BatchResult>> {
+    println!("[delete_wins] Soft delete for {} ids", ids.len());
+    batch(conn, ids, db::move_to_trash)
+}
+
+/// Moves each win back out of the trash in one transaction.
+pub fn restore_wins(conn: &mut Connection, ids: &[i64]) -> Result<Vec<BatchResult>> {
+    println!("[restore_wins] Restoring {} ids", ids.len());
+    batch(conn, ids, db::move_from_trash)
+}
+
+/// Runs `op` for every id inside one transaction, each under its own
+/// savepoint so a missing id is reported without undoing the others.
+fn batch(
+    conn: &mut Connection,
+    ids: &[i64],
+    op: fn(&Connection, i64) -> Result<()>,
+) -> Result<Vec<BatchResult>> {
+    let mut tx = conn.transaction()?;
+    let mut results = Vec::with_capacity(ids.len());
+    for &id in ids {
+        let sp = tx.savepoint()?;
+        let outcome = op(&sp, id).and_then(|_| sp.commit());
+        results.push(BatchResult {
+            id,
+            ok: outcome.is_ok(),
+            error: outcome.err().map(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => "No such win.".to_string(),
+                e => e.to_string(),
+            }),
+        });
+    }
+    tx.commit()?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn journal() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn).unwrap();
+        conn
+    }
+
+    fn add(conn: &mut Connection, text: &str) -> i64 {
+        db::add_win(conn, "2025-01-02", text, "mine", db::Ratings::default()).unwrap();
+        conn.query_row("SELECT MAX(id) FROM wins", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn count(conn: &Connection, table: &str, id: i64) -> i64 {
+        let column = match table {
+            "wins" | "deleted_wins" => "id",
+            _ => "win_id",
+        };
+        conn.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE {} = ?1", table, column),
+            [id],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    /// Trashes `id` as if it had been deleted long ago.
+    fn trash_long_ago(conn: &mut Connection, id: i64) {
+        db::delete_win(conn, id).unwrap();
+        conn.execute("UPDATE deleted_wins SET deleted_at = 0 WHERE id = ?1", [id])
+            .unwrap();
+    }
+
+    #[test]
+    fn purging_removes_everything_tied_to_the_win() {
+        let mut conn = journal();
+        let id = add(&mut conn, "Linked everywhere");
+        let kept = add(&mut conn, "Left alone");
+        conn.execute_batch(&format!(
+            "INSERT INTO win_attachments (win_id, hash, file_name, mime, size_bytes, created_at)
+                 VALUES ({id}, 'abc', 'a.txt', 'text/plain', 1, 0);
+             INSERT INTO goals (name, created_at) VALUES ('Goal', 0);
+             INSERT INTO goal_wins (goal_id, win_id) VALUES (1, {id}), (1, {kept});
+             INSERT INTO chains (created_at) VALUES (0);
+             INSERT INTO chain_wins (win_id, chain_id, pinned) VALUES ({id}, 1, 1), ({kept}, 1, 1);"
+        ))
+        .unwrap();
+        db::delete_win(&mut conn, id).unwrap();
+        purge_win(&mut conn, id).unwrap();
+
+        let tables = [
+            "deleted_wins",
+            "win_tags",
+            "win_revisions",
+            "win_attachments",
+            "goal_wins",
+            "chain_wins",
+        ];
+        for table in tables {
+            assert_eq!(count(&conn, table, id), 0, "{}", table);
+        }
+        for table in [
+            "wins",
+            "win_tags",
+            "win_revisions",
+            "goal_wins",
+            "chain_wins",
+        ] {
+            assert!(count(&conn, table, kept) > 0, "{}", table);
+        }
+    }
+
+    #[test]
+    fn purge_win_only_takes_wins_from_the_trash() {
+        let mut conn = journal();
+        let id = add(&mut conn, "Still here");
+        let err = purge_win(&mut conn, id).unwrap_err();
+        assert_eq!(err.sqlite_error_code(), Some(rusqlite::ErrorCode::NotFound));
+        assert_eq!(count(&conn, "wins", id), 1);
+        assert_eq!(count(&conn, "win_revisions", id), 1);
+    }
+
+    #[test]
+    fn a_missing_id_does_not_undo_the_rest_of_a_batch() {
+        let mut conn = journal();
+        let a = add(&mut conn, "A");
+        let b = add(&mut conn, "B");
+        let deleted = delete_wins(&mut conn, &[a, 99, b]).unwrap();
+        let oks: Vec<bool> = deleted.iter().map(|r| r.ok).collect();
+        assert_eq!(oks, vec![true, false, true]);
+        assert_eq!(
+            count(&conn, "deleted_wins", a) + count(&conn, "deleted_wins", b),
+            2
+        );
+
+        let restored = restore_wins(&mut conn, &[a, b, a]).unwrap();
+        let oks: Vec<bool> = restored.iter().map(|r| r.ok).collect();
+        assert_eq!(oks, vec![true, true, false]);
+        assert_eq!(restored[2].error.as_deref(), Some("No such win."));
+        assert_eq!(count(&conn, "wins", a) + count(&conn, "wins", b), 2);
+        assert_eq!(count(&conn, "deleted_wins", a), 0);
+    }
+
+    #[test]
+    fn retention_only_purges_when_switched_on() {
+        let mut conn = journal();
+        let old = add(&mut conn, "Old");
+        let recent = add(&mut conn, "Recent");
+        trash_long_ago(&mut conn, old);
+        db::delete_win(&mut conn, recent).unwrap();
+
+        let off = TrashSettings {
+            retention_hours: None,
+        };
+        assert_eq!(purge_scheduled(&mut conn, off).unwrap(), 0);
+        assert_eq!(count(&conn, "deleted_wins", old), 1);
+
+        let on = TrashSettings {
+            retention_hours: Some(1),
+        };
+        assert_eq!(purge_scheduled(&mut conn, on).unwrap(), 1);
+        assert_eq!(count(&conn, "deleted_wins", old), 0);
+        assert_eq!(count(&conn, "deleted_wins", recent), 1);
+    }
+}
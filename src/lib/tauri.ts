@@ -1,51 +1,682 @@
 import { invoke } from '@tauri-apps/api/core';
 
-export interface WinWithChain {
+// Optional self-ratings, each 1–5; null when not rated
+export interface Ratings {
+  mood: number | null;
+  energy: number | null;
+  impact: number | null;
+}
+
+// Where a win was logged: UTC offset in minutes and IANA zone name; null for
+// wins logged before zones were kept
+export interface LoggedZone {
+  utc_offset: number | null;
+  time_zone: string | null;
+}
+
+export interface WinWithChain extends Ratings, LoggedZone {
   id: number;
   date: string;
   text: string;
-  tags: string;
+  tags: string[];
   created_at: number;
-  chain_id: number;
+  chain_id: number | null; // null when the win is in no chain
+}
+
+export interface SharedTerm {
+  term: string;
+  wins: number; // wins in the chain containing it
+  weight: number; // summed TF-IDF weight
+}
+
+// What links a chain's wins, strongest shared term first
+export interface ChainSummary {
+  id: number;
+  length: number;
+  shared_terms: SharedTerm[];
+}
+
+// One page of the log; chains are summarised over all their wins, not just this page's
+export interface ChainedWins {
+  wins: WinWithChain[];
+  chains: ChainSummary[];
+  next_cursor: string | null;
+}
+
+export async function getWinsWithChains(query: WinQuery = {}): Promise<ChainedWins> {
+  return await invoke('get_wins_with_chains', { query });
+}
+
+export interface ChainGap {
+  after: string; // last win before the break
+  before: string; // first win after it
+  days: number; // days with no win in between
 }
 
-export async function getWinsWithChains(): Promise<WinWithChain[]> {
-  return await invoke('get_wins_with_chains');
+export interface ChainTimeline {
+  id: number;
+  title: string; // from the shared terms, e.g. "Marathon / training"
+  wins: Win[]; // oldest first
+  start: string;
+  end: string;
+  span_days: number; // first to last win, inclusive
+  active_days: number; // days with at least one win
+  gaps: ChainGap[]; // longest first
+  dominant_tags: { tag: string; count: number }[];
+  shared_terms: SharedTerm[];
+  active: boolean; // the next win can still join
+}
+
+export async function getChainTimeline(chainId: number): Promise<ChainTimeline> {
+  return await invoke('get_chain_timeline', { chainId });
+}
+
+// Ongoing chains first, then the most recently ended
+export async function getChainTimelines(): Promise<ChainTimeline[]> {
+  return await invoke('get_chain_timelines');
+}
+
+export interface ChainSettings {
+  max_gap_days: number; // wins further apart never link directly
+  min_similarity: number; // TF-IDF cosine similarity (0–1) two wins need to link
+  min_length: number; // smallest chain shown
+}
+
+export async function getChainSettings(): Promise<ChainSettings> {
+  return await invoke('get_chain_settings');
+}
+
+export async function setChainSettings(settings: ChainSettings): Promise<void> {
+  await invoke('set_chain_settings', { settings });
+}
+
+// Manual chain edits; the wins they touch are left alone by detection afterwards
+export async function addWinToChain(chainId: number, winId: number): Promise<void> {
+  await invoke('add_win_to_chain', { chainId, winId });
+}
+
+export async function removeWinFromChain(winId: number): Promise<void> {
+  await invoke('remove_win_from_chain', { winId });
+}
+
+export async function newChain(winIds: number[]): Promise<number> {
+  return await invoke('new_chain', { winIds });
+}
+
+// Moves fromWin and every later win of the chain into a new chain
+export async function splitChain(chainId: number, fromWin: number): Promise<number> {
+  return await invoke('split_chain', { chainId, fromWin });
+}
+
+export async function mergeChains(chainIds: number[]): Promise<number> {
+  return await invoke('merge_chains', { chainIds });
+}
+
+export interface GraphFilter {
+  from?: string; // YYYY-MM-DD, inclusive
+  to?: string;
+  with_tags?: string[]; // only wins carrying all of these
+  min_weight?: number; // drop edges seen in fewer wins
+  top_n?: number; // keep the N most used tags
+  focus?: string; // ego graph around this tag
+  depth?: number;
+  level?: number; // collapse the tag hierarchy to this depth (0 = top-level only)
+}
+
+export interface TagNode {
+  tag: string;
+  count: number;
+  cluster: number | null; // set by getTagClusters
+}
+
+export interface TagEdge {
+  source: string;
+  target: string;
+  weight: number; // wins carrying both tags
+  jaccard: number;
+  pmi: number;
 }
 
 export interface TagGraph {
-  nodes: string[];
-  edges: [string, string][];
+  nodes: TagNode[];
+  edges: TagEdge[];
+  win_count: number;
+}
+
+export async function getTagGraph(filter: GraphFilter = {}): Promise<TagGraph> {
+  return await invoke('get_tag_graph', { filter });
+}
+
+export interface TagCluster {
+  id: number;
+  label: string; // most used tags, e.g. "run / health"
+  tags: string[];
+  weight: number;
 }
 
+export interface TagClusters {
+  graph: TagGraph;
+  clusters: TagCluster[]; // largest first; ids are positions
+  links: { source: number; target: number; weight: number }[];
+  modularity: number;
+}
 
-export async function getTagGraph(): Promise<TagGraph> {
-  return await invoke('get_tag_graph');
+// Groups tags into themes with community detection over the same filtered graph
+export async function getTagClusters(filter: GraphFilter = {}): Promise<TagClusters> {
+  return await invoke('get_tag_clusters', { filter });
 }
 
-export interface Win {
+export interface Win extends Ratings, LoggedZone {
   id: number;
   date: string;
   text: string;
-  tags: string;
+  tags: string[];
 }
 
 
-export async function addWin(win: { date: string; text: string; tags: string }) {
-  return await invoke('add_win', { date: win.date, text: win.text, tags: win.tags });
+// Leave out `date` to log the win for today where the user is
+export async function addWin(win: { date?: string; text: string; tags: string; ratings?: Partial<Ratings> }) {
+  return await invoke('add_win', { date: win.date ?? null, text: win.text, tags: win.tags, ratings: win.ratings ?? null });
 }
 
 export async function getWins(): Promise<Win[]> {
   return await invoke('get_wins');
 }
 
+export interface WinFilter {
+  from?: string; // inclusive YYYY-MM-DD
+  to?: string;
+  include_tags?: string[];
+  exclude_tags?: string[];
+  contains?: string;
+}
+
+export interface WinQuery extends WinFilter {
+  sort?: 'newest' | 'oldest';
+  cursor?: string | null;
+  page_size?: number;
+}
+
+export interface WinPage {
+  wins: Win[];
+  next_cursor: string | null;
+}
+
+export async function queryWins(query: WinQuery): Promise<WinPage> {
+  return await invoke('query_wins', { query });
+}
+
+export type ExportFormat = 'json' | 'csv' | 'markdown';
+
+export interface ExportOptions extends WinFilter {
+  include_deleted?: boolean;
+}
+
+export interface ExportSummary {
+  path: string;
+  wins: number;
+  deleted_wins: number;
+  attachments: number; // copied into a "<name>-attachments" folder beside the export
+}
+
+export async function exportWins(path: string, format: ExportFormat, options: ExportOptions = {}): Promise<ExportSummary> {
+  return await invoke('export_wins', { path, format, options });
+}
+
+export interface ImportRow {
+  line: number;
+  date: string;
+  text: string;
+  tags: string[];
+  tags_inferred: boolean;
+  error: string | null;
+  duplicate: boolean;
+}
+
+export interface ImportReport {
+  dry_run: boolean;
+  rows: ImportRow[];
+  valid: number;
+  invalid: number;
+  duplicates: number;
+  imported: number;
+}
+
+// Run with dryRun = true first to preview validation errors and duplicates
+export async function importWins(path: string, format: ExportFormat, dryRun: boolean): Promise<ImportReport> {
+  return await invoke('import_wins', { path, format, dryRun });
+}
+
+export interface EncryptionStatus {
+  encrypted: boolean;
+  locked: boolean;
+}
+
+export async function getEncryptionStatus(): Promise<EncryptionStatus> {
+  return await invoke('get_encryption_status');
+}
+
+export async function unlockDb(passphrase: string): Promise<void> {
+  await invoke('unlock_db', { passphrase });
+}
+
+export async function enableEncryption(passphrase: string): Promise<void> {
+  await invoke('enable_encryption', { passphrase });
+}
+
+export async function changePassphrase(current: string, newPassphrase: string): Promise<void> {
+  await invoke('change_passphrase', { current, new: newPassphrase });
+}
+
+export async function disableEncryption(passphrase: string): Promise<void> {
+  await invoke('disable_encryption', { passphrase });
+}
+
+export interface BackupInfo {
+  file_name: string;
+  path: string;
+  created_at: number;
+  size_bytes: number;
+  encrypted: boolean;
+}
+
+export interface RetentionPolicy {
+  daily: number;
+  weekly: number;
+  monthly: number;
+}
+
+export async function listBackups(): Promise<BackupInfo[]> {
+  return await invoke('list_backups');
+}
+
+export async function createBackup(): Promise<BackupInfo> {
+  return await invoke('create_backup');
+}
+
+// The current journal is snapshotted first, so a restore can itself be undone
+export async function restoreBackup(fileName: string): Promise<void> {
+  await invoke('restore_backup', { fileName });
+}
+
+export async function getBackupRetention(): Promise<RetentionPolicy> {
+  return await invoke('get_backup_retention');
+}
+
+export async function setBackupRetention(policy: RetentionPolicy): Promise<void> {
+  await invoke('set_backup_retention', { policy });
+}
+
+export interface RecoveryReport {
+  corrupt_file: string;
+  recovered_at: number;
+  wins_recovered: number;
+  deleted_wins_recovered: number;
+  tags_inferred: number;
+  rows_lost: number;
+  pages_unreadable: number;
+  notes: string[];
+}
+
+// Non-null after startup found a corrupt database and salvaged it
+export async function getRecoveryReport(): Promise<RecoveryReport | null> {
+  return await invoke('get_recovery_report');
+}
+
+export async function dismissRecoveryReport(): Promise<void> {
+  await invoke('dismiss_recovery_report');
+}
+
+export interface Attachment {
+  id: number;
+  win_id: number;
+  file_name: string;
+  mime: string;
+  size_bytes: number;
+  created_at: number;
+  hash: string;
+  has_thumbnail: boolean;
+}
+
+export async function addAttachment(winId: number, path: string): Promise<Attachment> {
+  return await invoke('add_attachment', { winId, path });
+}
+
+export async function listAttachments(winId: number): Promise<Attachment[]> {
+  return await invoke('list_attachments', { winId });
+}
+
+// PNG bytes, or null for files that aren't images
+export async function getAttachmentThumbnail(id: number): Promise<Uint8Array | null> {
+  const bytes: number[] | null = await invoke('get_attachment_thumbnail', { id });
+  return bytes ? new Uint8Array(bytes) : null;
+}
+
+// Opens the file in the system's default app
+export async function openAttachment(id: number): Promise<void> {
+  await invoke('open_attachment', { id });
+}
+
+export async function removeAttachment(id: number): Promise<void> {
+  await invoke('remove_attachment', { id });
+}
+
+export interface TrashSettings {
+  retention_hours: number | null; // null: never purge automatically
+}
+
+export interface BatchResult {
+  id: number;
+  ok: boolean;
+  error: string | null;
+}
+
+export async function getTrashSettings(): Promise<TrashSettings> {
+  return await invoke('get_trash_settings');
+}
+
+export async function setTrashSettings(settings: TrashSettings): Promise<void> {
+  await invoke('set_trash_settings', { settings });
+}
+
+export async function emptyTrash(): Promise<number> {
+  return await invoke('empty_trash');
+}
+
+export async function purgeWin(id: number): Promise<void> {
+  await invoke('purge_win', { id });
+}
+
+// Both run in one transaction and report each id separately
+export async function deleteWins(ids: number[]): Promise<BatchResult[]> {
+  return await invoke('delete_wins', { ids });
+}
+
+export async function restoreWins(ids: number[]): Promise<BatchResult[]> {
+  return await invoke('restore_wins', { ids });
+}
+
+export interface WinVersion extends Ratings {
+  date: string;
+  text: string;
+  tags: string[];
+  created_at: number;
+}
+
+export interface WinRevision {
+  id: number;
+  win_id: number;
+  action: 'create' | 'edit' | 'delete' | 'restore';
+  before: WinVersion | null;
+  after: WinVersion | null;
+  created_at: number;
+  state: 'done' | 'undone' | 'discarded';
+}
+
+export interface UndoState {
+  applied: WinRevision | null;
+  can_undo: boolean;
+  can_redo: boolean;
+}
+
+// Undo/redo live in the database, so they survive restarts and are shared by every window
+export async function undo(): Promise<UndoState> {
+  return await invoke('undo');
+}
+
+export async function redo(): Promise<UndoState> {
+  return await invoke('redo');
+}
+
+export async function getUndoState(): Promise<UndoState> {
+  return await invoke('get_undo_state');
+}
+
+export async function getWinHistory(winId: number): Promise<WinRevision[]> {
+  return await invoke('get_win_history', { winId });
+}
+
+export async function restoreWinVersion(revisionId: number): Promise<void> {
+  await invoke('restore_win_version', { revisionId });
+}
+
+export type GoalPeriod = 'day' | 'week' | 'month' | 'total';
+
+export interface GoalTarget {
+  count: number;
+  period: GoalPeriod;
+}
+
+export interface Goal {
+  id: number;
+  name: string;
+  description: string;
+  target: GoalTarget | null;
+  deadline: string | null; // YYYY-MM-DD
+  rule_tags: string[]; // wins with any of these tags count automatically
+  created_at: number;
+}
+
+export type GoalInput = Omit<Goal, 'id' | 'created_at'>;
+
+export interface WinGoal {
+  goal_id: number;
+  linked: boolean; // linked by hand
+  by_tag: boolean; // matches one of the goal's rule tags
+}
+
+export interface PeriodProgress {
+  start: string;
+  end: string;
+  count: number;
+  ratio: number | null;
+  met: boolean;
+}
+
+export interface GoalProgress {
+  goal: Goal;
+  periods: PeriodProgress[];
+  wins: Win[];
+  periods_met: number;
+  current_run: number;
+  days_left: number | null;
+}
+
+export async function listGoals(): Promise<Goal[]> {
+  return await invoke('list_goals');
+}
+
+export async function createGoal(goal: GoalInput): Promise<Goal> {
+  return await invoke('create_goal', { goal });
+}
+
+export async function updateGoal(id: number, goal: GoalInput): Promise<Goal> {
+  return await invoke('update_goal', { id, goal });
+}
+
+export async function deleteGoal(id: number): Promise<void> {
+  await invoke('delete_goal', { id });
+}
+
+export async function linkWinToGoal(goalId: number, winId: number): Promise<void> {
+  await invoke('link_win_to_goal', { goalId, winId });
+}
+
+export async function unlinkWinFromGoal(goalId: number, winId: number): Promise<void> {
+  await invoke('unlink_win_from_goal', { goalId, winId });
+}
+
+export async function getGoalsForWin(winId: number): Promise<WinGoal[]> {
+  return await invoke('get_goals_for_win', { winId });
+}
+
+export async function getGoalProgress(goalId: number, from?: string, to?: string): Promise<GoalProgress> {
+  return await invoke('get_goal_progress', { goalId, from, to });
+}
+
+export type RecapPeriod = '7d' | '30d' | 'week' | 'month' | 'year';
+
+export interface DayCount {
+  date: string;
+  count: number;
+}
+
+export interface Recap {
+  period: RecapPeriod;
+  start: string;
+  end: string;
+  days: number;
+  total: number;
+  per_day: number;
+  series: DayCount[]; // every day of the period, zeros included
+  top_tags: { tag: string; count: number }[];
+  avg_mood: number | null;
+  avg_energy: number | null;
+  avg_impact: number | null;
+  best_day: DayCount | null;
+  new_tags: string[]; // first used in this period
+  top_impact: Win | null;
+  previous: {
+    start: string;
+    end: string;
+    total: number;
+    avg_mood: number | null;
+    change: number;
+    change_pct: number | null;
+  };
+  wins: Win[];
+}
+
+export async function getRecap(period: RecapPeriod): Promise<Recap> {
+  return await invoke('get_recap', { period });
+}
+
+export interface StreakRules {
+  weekdays_only: boolean;
+  min_wins_per_day: number;
+  tag: string | null; // only wins with this tag count
+  freezes_per_month: number;
+}
+
+export interface Streak {
+  length: number;
+  start: string;
+  end: string;
+}
+
+export interface Streaks {
+  current: Streak | null;
+  longest: Streak | null;
+  today_done: boolean;
+  frozen_days: string[];
+  freezes_left: number;
+  rules: StreakRules;
+}
+
+export interface RestDay {
+  date: string;
+  note: string;
+}
+
+// Pass `rules` to preview them; otherwise the saved rules are used
+export async function getStreaks(rules?: StreakRules): Promise<Streaks> {
+  return await invoke('get_streaks', { rules });
+}
+
+export async function getStreakRules(): Promise<StreakRules> {
+  return await invoke('get_streak_rules');
+}
+
+export async function setStreakRules(rules: StreakRules): Promise<void> {
+  await invoke('set_streak_rules', { rules });
+}
+
+export async function listRestDays(): Promise<RestDay[]> {
+  return await invoke('list_rest_days');
+}
+
+export async function setRestDay(date: string, note = ''): Promise<void> {
+  await invoke('set_rest_day', { date, note });
+}
+
+export async function removeRestDay(date: string): Promise<void> {
+  await invoke('remove_rest_day', { date });
+}
+
+export interface TagInfo {
+  id: number;
+  name: string;
+  count: number; // live wins carrying the tag
+  aliases: string[]; // spellings that become this tag when typed or inferred
+  parent: string | null; // wins tagged with this tag also count toward the parent
+}
+
+export interface TagChange {
+  id: number;
+  action: 'rename' | 'merge' | 'alias' | 'unalias' | 'parent';
+  summary: string;
+  created_at: number;
+  undone: boolean;
+}
+
+export async function listTags(): Promise<TagInfo[]> {
+  return await invoke('list_tags');
+}
+
+// Renaming onto an existing tag merges the two; the old name becomes an alias
+export async function renameTag(from: string, to: string): Promise<TagChange> {
+  return await invoke('rename_tag', { from, to });
+}
+
+export async function mergeTags(sources: string[], into: string): Promise<TagChange> {
+  return await invoke('merge_tags', { sources, into });
+}
+
+export async function addTagAlias(alias: string, tag: string): Promise<TagChange> {
+  return await invoke('add_tag_alias', { alias, tag });
+}
+
+export async function removeTagAlias(alias: string): Promise<TagChange> {
+  return await invoke('remove_tag_alias', { alias });
+}
+
+// Files `tag` under `parent` (created if new), or makes it top-level with null
+export async function setTagParent(tag: string, parent: string | null): Promise<TagChange> {
+  return await invoke('set_tag_parent', { tag, parent });
+}
+
+export async function listTagChanges(): Promise<TagChange[]> {
+  return await invoke('list_tag_changes');
+}
+
+// Reverts the most recent rename, merge or alias edit still in effect
+export async function undoTagChange(): Promise<TagChange | null> {
+  return await invoke('undo_tag_change');
+}
+
+// Closest known tag to `tag`, or null if it's already known or nothing is close
+export async function didYouMean(tag: string): Promise<string | null> {
+  return await invoke('did_you_mean', { tag });
+}
+
+export interface SearchHit {
+  win: Win;
+  snippet: string; // HTML-escaped, matches wrapped in <mark>
+  rank: number;
+}
+
+export async function searchWins(query: string, tags: string[] = [], limit = 50): Promise<SearchHit[]> {
+  return await invoke('search_wins', { query, tags, limit });
+}
+
 export async function getDeletedWins(): Promise<Win[]> {
   return await invoke('get_deleted_wins');
 }
 
-export async function updateWin(win: { id: number; date: string; text: string; tags: string }) {
+// Ratings left out are cleared, so pass the win's current ones when only editing text
+export async function updateWin(win: { id: number; date: string; text: string; tags: string; ratings?: Partial<Ratings> }) {
   const { invoke } = await import('@tauri-apps/api/core');
-  return invoke('update_win', { ...win });
+  return invoke('update_win', { ...win, ratings: win.ratings ?? null });
 }
 
 export async function deleteWin(id: number) {